@@ -0,0 +1,416 @@
+use specs::prelude::*;
+
+use super::physics::Position;
+use super::routing::Junction;
+use super::signals::{ApproachSignal, ApproachAspect, JunctionSignal};
+use super::simulation::Simulation;
+use super::history::EditOp;
+use super::track::Tracks;
+
+/**
+ * How far ahead of a junction signal its approach signals are put up, in pixels. That's
+ * enough for a train going full speed to come to a halt, with a bit of room to spare.
+ */
+pub const APPROACH_DISTANCE: f64 = 200.0;
+
+/**
+ * How close a driver has to be to a junction signal to make it out with their own eyes.
+ */
+pub const SIGHTING_DISTANCE: f64 = 50.0;
+
+/**
+ * The place on the rails where an approach signal goes.
+ */
+struct Spot {
+    rail:   (Entity, Entity),
+    offset: f64,
+    pos:    Position,
+}
+
+/**
+ * Walk back from the junction signal along the line that comes in from via, for as long
+ * as it goes straight on without any switches or signals, until we're distance away from
+ * the signal. If the line doesn't go on for that long, the approach signal goes where it
+ * ends, which is the best warning we can give.
+ */
+fn find_spot(
+    signal: Entity,
+    via: Entity,
+    distance: f64,
+    junctions: &ReadStorage<Junction>,
+    signals: &ReadStorage<JunctionSignal>,
+    tracks: &Tracks
+) -> Option<Spot> {
+    if !signals.contains(signal) || !junctions.get(signal)?.connections.contains(&via) {
+        return None;
+    }
+    let mut ahead = signal;
+    let mut here = via;
+    let mut walked = 0.0;
+    loop {
+        let segment = tracks.get(tracks.segment_between(here, ahead)?)?;
+        let length = segment.length();
+        let junction = junctions.get(here)?;
+        let straight_on = here != signal && !junction.is_terminal &&
+            junction.connections.len() == 2 && !signals.contains(here);
+        if walked + length >= distance || !straight_on {
+            let offset = (distance - walked).min(length);
+            let from_ahead = if length > 0.0 { offset / length } else { 0.0 };
+            return Some(Spot {
+                rail:   (here, ahead),
+                offset,
+                pos:    segment.point_at(if segment.to == ahead { 1.0 - from_ahead } else { from_ahead }),
+            });
+        }
+        walked += length;
+        let behind = *junction.connections.iter().find(|&&next| next != ahead)?;
+        ahead = here;
+        here = behind;
+    }
+}
+
+/**
+ * Follow the rails from `from` towards `towards` for as long as they go straight on, and
+ * see if there's a junction signal at the end. Returns that signal, the junction right in
+ * front of it, and how far it is from where we started, starting walked pixels before
+ * `towards`.
+ */
+fn signal_ahead(
+    from: Entity,
+    towards: Entity,
+    walked: f64,
+    junctions: &ReadStorage<Junction>,
+    signals: &ReadStorage<JunctionSignal>,
+    tracks: &Tracks
+) -> Option<(Entity, Entity, f64)> {
+    let mut behind = from;
+    let mut here = towards;
+    let mut walked = walked;
+    loop {
+        if signals.contains(here) {
+            return Some((here, behind, walked));
+        }
+        let junction = junctions.get(here)?;
+        if junction.is_terminal || junction.connections.len() != 2 {
+            return None;
+        }
+        let next = *junction.connections.iter().find(|&&next| next != behind)?;
+        // Going round in circles without ever meeting a signal.
+        if next == towards {
+            return None;
+        }
+        walked += tracks.get(tracks.segment_between(here, next)?)?.length();
+        behind = here;
+        here = next;
+    }
+}
+
+/**
+ * Find out which lines lead from pos on the rails to a junction signal, going either way.
+ * Returns the signal, the junction right in front of it and how far away it is, for each
+ * of them.
+ */
+fn signals_from(
+    pos: &Position,
+    max_distance: f64,
+    junctions: &ReadStorage<Junction>,
+    signals: &ReadStorage<JunctionSignal>,
+    tracks: &Tracks
+) -> Vec<(Entity, Entity, f64)> {
+    let mut found = vec![];
+    for hit in tracks.hits_near(pos, max_distance) {
+        if let Some(segment) = tracks.get(hit.segment) {
+            let to_end = hit.point.distance_length_to(&segment.end);
+            let to_start = hit.point.distance_length_to(&segment.start);
+            found.extend(signal_ahead(segment.from, segment.to, to_end, junctions, signals, tracks));
+            found.extend(signal_ahead(segment.to, segment.from, to_start, junctions, signals, tracks));
+        }
+    }
+    found
+}
+
+/**
+ * Find the line that leads from pos on the rails to the given junction signal. Returns
+ * the junction right in front of the signal and how far away it is.
+ */
+fn line_to(
+    signal: Entity,
+    pos: &Position,
+    junctions: &ReadStorage<Junction>,
+    signals: &ReadStorage<JunctionSignal>,
+    tracks: &Tracks
+) -> Option<(Entity, f64)> {
+    signals_from(pos, 1.0, junctions, signals, tracks).into_iter()
+        .find(|&(other, ..)| other == signal)
+        .map(|(_, via, distance)| (via, distance))
+}
+
+/**
+ * Put up an approach signal for the junction signal, on the line that comes in from via,
+ * distance pixels ahead of it. Any other approach signal on that line has to go.
+ */
+pub fn place_approach_signal(world: &mut World, signal: Entity, via: Entity, distance: f64) -> Option<Entity> {
+    let spot = find_spot(
+        signal,
+        via,
+        distance,
+        &world.read_storage::<Junction>(),
+        &world.read_storage::<JunctionSignal>(),
+        &world.read_resource::<Tracks>()
+    )?;
+    let replaced: Vec<Entity> = (&world.entities(), &world.read_storage::<ApproachSignal>()).join()
+        .filter(|(_, approach)| approach.junction_signal == signal && approach.via == via)
+        .map(|(ent, _)| ent)
+        .collect();
+    for ent in replaced {
+        world.delete_entity(ent).expect("approach signal was already gone");
+    }
+    let approach = world.create_entity()
+        .with(spot.pos)
+        .with(ApproachSignal {
+            junction_signal: signal,
+            via,
+            distance,
+            rail:            spot.rail,
+            offset:          spot.offset,
+            aspect:          ApproachAspect::Dark,
+            by_hand:         true,
+        })
+        .build();
+    list_approach_signals(world);
+    Some(approach)
+}
+
+/**
+ * Make sure that every junction signal has an approach signal on every line that leads
+ * into it, after signals and rails have come and gone. The ones that were put up by hand
+ * stay where they were asked to be, as long as there's still a line from there to their
+ * signal. Returns the signal and the spot of each of those that had to go.
+ */
+pub fn put_up_approach_signals(world: &mut World) -> Vec<(Entity, Position)> {
+    let mut wanted: Vec<(Entity, Entity, f64, bool, Spot)> = vec![];
+    let mut dropped = vec![];
+    let existing: Vec<(Entity, ApproachSignal)> = {
+        let entities = world.entities();
+        let junctions = world.read_storage::<Junction>();
+        let signals = world.read_storage::<JunctionSignal>();
+        let approaches = world.read_storage::<ApproachSignal>();
+        let positions = world.read_storage::<Position>();
+        let tracks = world.read_resource::<Tracks>();
+        // The ones put up by hand stay on their spot, even if the rails in between got
+        // split up in the meantime, so we look at where the line from there leads now.
+        for (pos, approach) in (&positions, &approaches).join().filter(|(_, approach)| approach.by_hand) {
+            let spot = line_to(approach.junction_signal, pos, &junctions, &signals, &tracks)
+                .and_then(|(via, distance)| {
+                    find_spot(approach.junction_signal, via, distance, &junctions, &signals, &tracks)
+                        .map(|spot| (via, distance, spot))
+                });
+            match spot {
+                Some((via, distance, spot)) => wanted.push((approach.junction_signal, via, distance, true, spot)),
+                None => dropped.push((approach.junction_signal, pos.clone())),
+            }
+        }
+        for (signal, junction, _) in (&entities, &junctions, &signals).join() {
+            for &via in &junction.connections {
+                if wanted.iter().any(|&(other, other_via, ..)| (other, other_via) == (signal, via)) {
+                    continue;
+                }
+                if let Some(spot) = find_spot(signal, via, APPROACH_DISTANCE, &junctions, &signals, &tracks) {
+                    wanted.push((signal, via, APPROACH_DISTANCE, false, spot));
+                }
+            }
+        }
+        (&entities, &approaches).join().map(|(ent, approach)| (ent, approach.clone())).collect()
+    };
+
+    // Approach signals that are already standing where they should be just get moved
+    // along, so that they don't get a new name every time somebody lays a rail.
+    let mut unused: Vec<Entity> = existing.iter().map(|(ent, _)| *ent).collect();
+    for (signal, via, distance, by_hand, spot) in wanted {
+        let found = existing.iter()
+            .find(|(ent, approach)| {
+                unused.contains(ent) && approach.junction_signal == signal && approach.via == via && approach.by_hand == by_hand
+            })
+            .map(|(ent, approach)| (*ent, approach.aspect));
+        let approach = ApproachSignal {
+            junction_signal: signal,
+            via,
+            distance,
+            rail:            spot.rail,
+            offset:          spot.offset,
+            aspect:          found.map_or(ApproachAspect::Dark, |(_, aspect)| aspect),
+            by_hand,
+        };
+        match found {
+            Some((ent, _)) => {
+                unused.retain(|&other| other != ent);
+                world.write_storage::<Position>().insert(ent, spot.pos).expect("approach signal lost its place");
+                world.write_storage::<ApproachSignal>().insert(ent, approach).expect("approach signal lost its way");
+            },
+            None => {
+                world.create_entity()
+                    .with(spot.pos)
+                    .with(approach)
+                    .build();
+            },
+        }
+    }
+    for ent in unused {
+        world.delete_entity(ent).expect("approach signal was already gone");
+    }
+    list_approach_signals(world);
+    dropped
+}
+
+/**
+ * Tell every junction signal which approach signals are announcing it.
+ */
+fn list_approach_signals(world: &mut World) {
+    let entities = world.entities();
+    let approaches = world.read_storage::<ApproachSignal>();
+    let mut signals = world.write_storage::<JunctionSignal>();
+    for signal in (&mut signals).join() {
+        signal.appr_signals.clear();
+    }
+    for (ent, approach) in (&entities, &approaches).join() {
+        if let Some(signal) = signals.get_mut(approach.junction_signal) {
+            signal.appr_signals.push(ent);
+        }
+    }
+}
+
+impl Simulation {
+    /**
+     * Put up an approach signal by hand, right where pos is on the rails, for the junction
+     * signal that the line leads to. If the line leads to a signal both ways, it's for the
+     * closer one. Returns false if there's no rail there or no signal to announce.
+     */
+    pub fn place_approach_signal(&mut self, pos: &Position) -> bool {
+        let found = {
+            let junctions = self.world.read_storage::<Junction>();
+            let signals = self.world.read_storage::<JunctionSignal>();
+            let tracks = self.world.read_resource::<Tracks>();
+            self.track_at(pos, 5.0).and_then(|hit| {
+                signals_from(&hit.point, 0.5, &junctions, &signals, &tracks).into_iter()
+                    .min_by(|(_, _, left), (_, _, right)| left.partial_cmp(right).unwrap())
+            })
+        };
+        let (signal, via, distance) = match found {
+            Some(found) => found,
+            None => return false,
+        };
+        println!("Putting up an approach signal {:.0} ahead of {:?}", distance, signal);
+        // There's only room for one on each line, so one that was put up there by hand
+        // before has to go, and that needs to be undone as well.
+        let replaced: Vec<Entity> = (&self.world.entities(), &self.world.read_storage::<ApproachSignal>()).join()
+            .filter(|(_, approach)| approach.by_hand && approach.junction_signal == signal && approach.via == via)
+            .map(|(ent, _)| ent)
+            .collect();
+        self.history.begin();
+        for approach in replaced {
+            self.take_down_approach_signal(approach);
+        }
+        let placed = self.put_up_approach_signal(signal, via, distance);
+        self.refresh_approach_signals();
+        self.history.commit();
+        placed
+    }
+
+    /**
+     * Take down an approach signal that was put up by hand. The ones we put up ourselves
+     * would only come right back, so they stay.
+     */
+    pub fn remove_approach_signal(&mut self, pos: &Position) -> bool {
+        let found = {
+            let entities = self.world.entities();
+            let positions = self.world.read_storage::<Position>();
+            let approaches = self.world.read_storage::<ApproachSignal>();
+            (&entities, &positions, &approaches).join()
+                .filter(|(_, _, approach)| approach.by_hand)
+                .map(|(ent, approach_pos, _)| (ent, pos.distance_length_to(approach_pos)))
+                .filter(|&(_, distance)| distance < 6.0)
+                .min_by(|(_, left), (_, right)| left.partial_cmp(right).unwrap())
+                .map(|(ent, _)| ent)
+        };
+        match found {
+            Some(approach) => {
+                self.history.begin();
+                self.take_down_approach_signal(approach);
+                self.refresh_approach_signals();
+                self.history.commit();
+                true
+            },
+            None => false,
+        }
+    }
+
+    /**
+     * Put up approach signals wherever they're missing now that the map has changed. If
+     * some that were put up by hand had to go because their line is gone, that's part of
+     * the change. They can only come back once the rest of it has been undone though, so
+     * that goes at the very start of it.
+     */
+    pub(crate) fn refresh_approach_signals(&mut self) {
+        for (signal, pos) in put_up_approach_signals(&mut self.world) {
+            println!("Approach signal for {:?} lost its line", signal);
+            self.history.record_up_front(EditOp::RemoveApproachSignal { signal, pos });
+        }
+    }
+
+    fn put_up_approach_signal(&mut self, signal: Entity, via: Entity, distance: f64) -> bool {
+        let pos = match place_approach_signal(&mut self.world, signal, via, distance) {
+            Some(approach) => self.world.read_storage::<Position>().get(approach).cloned()
+                .expect("approach signal without a place"),
+            None => return false,
+        };
+        self.history.record(EditOp::AddApproachSignal { signal, pos });
+        true
+    }
+
+    fn take_down_approach_signal(&mut self, approach: Entity) {
+        println!("Taking down approach signal {:?}", approach);
+        let pos = self.world.read_storage::<Position>().get(approach).cloned()
+            .expect("approach signal without a place");
+        let signal = self.world.read_storage::<ApproachSignal>().get(approach)
+            .expect("approach signal that isn't one")
+            .junction_signal;
+        self.world.delete_entity(approach).expect("approach signal was already gone");
+        list_approach_signals(&mut self.world);
+        self.history.record(EditOp::RemoveApproachSignal { signal, pos });
+    }
+
+    /**
+     * Bring back an approach signal for the junction signal that was put up by hand at pos.
+     */
+    pub(crate) fn put_up_approach_signal_at(&mut self, signal: Entity, pos: &Position) -> bool {
+        let line = line_to(
+            signal,
+            pos,
+            &self.world.read_storage::<Junction>(),
+            &self.world.read_storage::<JunctionSignal>(),
+            &self.world.read_resource::<Tracks>()
+        );
+        match line {
+            Some((via, distance)) => self.put_up_approach_signal(signal, via, distance),
+            None => false,
+        }
+    }
+
+    /**
+     * Take down the approach signal for the junction signal that was put up by hand at pos.
+     */
+    pub(crate) fn take_down_approach_signal_at(&mut self, signal: Entity, pos: &Position) -> bool {
+        let found = (&self.world.entities(), &self.world.read_storage::<Position>(), &self.world.read_storage::<ApproachSignal>()).join()
+            .find(|(_, approach_pos, approach)| {
+                approach.by_hand && approach.junction_signal == signal && pos.distance_length_to(approach_pos) < 1.0
+            })
+            .map(|(ent, _, _)| ent);
+        match found {
+            Some(approach) => {
+                self.take_down_approach_signal(approach);
+                true
+            },
+            None => false,
+        }
+    }
+}
@@ -1,7 +1,12 @@
 use specs::prelude::*;
 use std::collections::HashMap;
+use std::str::FromStr;
+use serde::{Serialize, Deserialize};
 
-#[derive(Debug,PartialEq,Eq,Hash,Clone,Copy)]
+use super::routing::TrainIsInStation;
+use super::train::{Consist, Wagon};
+
+#[derive(Debug,PartialEq,Eq,Hash,Clone,Copy,Serialize,Deserialize)]
 pub enum CargoKind {
     Coal,
     /*Oil,
@@ -12,7 +17,18 @@ pub enum CargoKind {
     Planks,*/
 }
 
-#[derive(Debug)]
+impl FromStr for CargoKind {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "coal" => Ok(CargoKind::Coal),
+            _      => Err(()),
+        }
+    }
+}
+
+#[derive(Debug,Clone,Serialize,Deserialize)]
 pub struct CargoStorage {
     pub quantities: HashMap<CargoKind, f64>,
 }
@@ -25,12 +41,18 @@ impl CargoStorage {
     }
 }
 
+impl Default for CargoStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Component for CargoStorage {
     type Storage = VecStorage<Self>;
 }
 
 
-#[derive(Debug)]
+#[derive(Debug,Clone,Serialize,Deserialize)]
 pub struct CargoProducer {
     pub quantities: HashMap<CargoKind, f64>,
 }
@@ -48,16 +70,41 @@ impl CargoProducer {
     }
 }
 
+impl Default for CargoProducer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Component for CargoProducer {
     type Storage = VecStorage<Self>;
 }
 
 
-#[derive(Debug)]
+#[derive(Debug,Clone,Serialize,Deserialize)]
 pub struct CargoConsumer {
     pub quantities: HashMap<CargoKind, f64>,
 }
 
+impl CargoConsumer {
+    pub fn new() -> Self {
+        Self {
+            quantities: HashMap::new()
+        }
+    }
+
+    pub fn with(mut self, kind: CargoKind, consumption: f64) -> Self {
+        self.quantities.insert(kind, consumption);
+        self
+    }
+}
+
+impl Default for CargoConsumer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Component for CargoConsumer {
     type Storage = VecStorage<Self>;
 }
@@ -103,3 +150,124 @@ impl<'a> System<'a> for CargoConsumptionSystem {
         }
     }
 }
+
+
+/**
+ * How long it takes to shovel one unit of cargo from the station into a wagon, or
+ * back out of it.
+ */
+pub const DWELL_TIME_PER_UNIT: f64 = 0.2;
+
+/**
+ * A train that is having cargo loaded or unloaded. It stays put until that's done, and
+ * keeps this around with nothing remaining until it leaves.
+ */
+#[derive(Debug,Clone,Serialize,Deserialize)]
+pub struct TrainIsDwelling {
+    pub remaining: f64,
+}
+
+impl TrainIsDwelling {
+    pub fn is_done(&self) -> bool {
+        self.remaining <= 0.0
+    }
+}
+
+impl Component for TrainIsDwelling {
+    type Storage = HashMapStorage<Self>;
+}
+
+
+/**
+ * Whenever a train comes into a station, the CargoHandler fills its wagons with whatever
+ * the industry there produces, and takes out whatever it consumes. The more that is moved
+ * around, the longer the train has to wait. As long as the train sticks around, whatever
+ * the industry produces in the meantime goes into the wagons as well, without holding
+ * the train up any longer.
+ */
+pub struct CargoHandler;
+
+impl<'a> System<'a> for CargoHandler {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, TrainIsInStation>,
+        WriteStorage<'a, TrainIsDwelling>,
+        ReadStorage<'a, Consist>,
+        ReadStorage<'a, Wagon>,
+        WriteStorage<'a, CargoStorage>,
+        ReadStorage<'a, CargoProducer>,
+        ReadStorage<'a, CargoConsumer>,
+        Read<'a, super::DeltaTime>,
+    );
+
+    fn run(&mut self, sys_data: Self::SystemData) {
+        let (
+            entities,
+            trains_in_station,
+            mut dwelling,
+            consists,
+            wagons,
+            mut storages,
+            producers,
+            consumers,
+            delta,
+        ) = sys_data;
+        for (dwell, _) in (&mut dwelling, &trains_in_station).join() {
+            dwell.remaining = (dwell.remaining - delta.fraction).max(0.0);
+        }
+
+        let mut handled = vec![];
+        for (train, station, consist, dwell) in (&entities, &trains_in_station, &consists, dwelling.maybe()).join() {
+            if dwell.is_some_and(|dwell| !dwell.is_done()) {
+                continue;
+            }
+            let just_arrived = dwell.is_none();
+            let station = station.station;
+            let mut transferred = 0.0;
+            for &wagon_ent in &consist.wagons {
+                let wagon = match wagons.get(wagon_ent) {
+                    Some(wagon) => wagon,
+                    None => continue,
+                };
+                let kind = wagon.cargo;
+                let in_wagon = storages.get(wagon_ent)
+                    .and_then(|storage| storage.quantities.get(&kind).cloned())
+                    .unwrap_or(0.0);
+                let in_station = storages.get(station)
+                    .and_then(|storage| storage.quantities.get(&kind).cloned())
+                    .unwrap_or(0.0);
+                // Positive amounts go into the wagon, negative ones come out of it.
+                let amount =
+                    if consumers.get(station).is_some_and(|c| c.quantities.contains_key(&kind)) {
+                        -in_wagon
+                    } else if producers.get(station).is_some_and(|p| p.quantities.contains_key(&kind)) {
+                        (wagon.capacity - in_wagon).min(in_station).max(0.0)
+                    } else {
+                        0.0
+                    };
+                if amount == 0.0 || !storages.contains(station) {
+                    continue;
+                }
+                if !storages.contains(wagon_ent) {
+                    storages.insert(wagon_ent, CargoStorage::new()).expect("wagon fell off");
+                }
+                *storages.get_mut(wagon_ent).unwrap().quantities.entry(kind).or_insert(0.0) += amount;
+                *storages.get_mut(station).unwrap().quantities.entry(kind).or_insert(0.0) -= amount;
+                transferred += amount.abs();
+            }
+            // Only the first load counts towards the dwell time. Topping up with whatever
+            // trickles in afterwards must not keep a train from ever leaving.
+            if just_arrived {
+                if transferred > 0.0 {
+                    println!("Train {:?} moved {:.1} units of cargo at {:?}", train, transferred, station);
+                }
+                handled.push((train, transferred * DWELL_TIME_PER_UNIT));
+            }
+        }
+        for (train, dwell_time) in handled {
+            dwelling
+                .insert(train, TrainIsDwelling { remaining: dwell_time })
+                .expect("train left without waiting");
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::BTreeSet;
+use specs::prelude::*;
+
+use super::physics::{Position, SpeedLimit, TrainEngine};
+use super::routing::{TrainIsInStation, TrainRoute};
+use super::signals::{
+    SignalIsReservedByTrain,
+    SignalIsBlockedByTrain,
+    TrainIsBlockingSignals,
+    SpeedLimitFromNextSignal,
+    CrossingIsReservedByTrain,
+    JunctionIsReservedByTrain,
+    release_reservations,
+};
+use super::track::{Tracks, TrackPosition};
+use super::train::{CarOutline, Consist, TrainTrail, Wagon};
+use super::simulation::Simulation;
+use super::SimClock;
+
+/**
+ * Cars that get closer to each other than this, in pixels, have crashed.
+ */
+pub const CRASH_DISTANCE: f64 = 1.0;
+
+/**
+ * Two or more trains that ran into each other, and the rails that their wreck is
+ * lying on. Nobody gets through there until somebody comes and clears it away.
+ */
+#[derive(Debug, Clone)]
+pub struct Crash {
+    pub at:         Position,
+    pub time:       f64,
+    pub trains:     Vec<Entity>,
+    pub blocked:    Vec<(Entity, Entity)>,
+    pub cleared_at: Option<f64>,
+}
+
+impl Crash {
+    pub fn new(at: Position, time: f64) -> Self {
+        Self {
+            at,
+            time,
+            trains:     vec![],
+            blocked:    vec![],
+            cleared_at: None,
+        }
+    }
+
+    pub fn is_cleared(&self) -> bool {
+        self.cleared_at.is_some()
+    }
+}
+
+/**
+ * Every crash that ever happened, the ones that have been cleared away included, so
+ * that we can tell how bad things have been.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Crashes {
+    pub log: Vec<Crash>,
+}
+
+impl Crashes {
+    /**
+     * Write down a new crash. Returns its number, which is what the trains involved
+     * remember it by.
+     */
+    pub fn record(&mut self, crash: Crash) -> usize {
+        self.log.push(crash);
+        self.log.len() - 1
+    }
+
+    /**
+     * The crashes whose wrecks are still lying around.
+     */
+    pub fn active(&self) -> impl Iterator<Item = (usize, &Crash)> {
+        self.log.iter().enumerate().filter(|(_, crash)| !crash.is_cleared())
+    }
+
+    /**
+     * All the rails that a wreck is lying on, as the junctions at both of their ends.
+     */
+    pub fn blocked_rails(&self) -> impl Iterator<Item = (Entity, Entity)> + '_ {
+        self.active().flat_map(|(_, crash)| crash.blocked.iter().cloned())
+    }
+
+    pub fn is_blocked(&self, from: Entity, to: Entity) -> bool {
+        self.blocked_rails().any(|rail| rail == (from, to) || rail == (to, from))
+    }
+
+    pub fn trains_involved(&self) -> usize {
+        self.log.iter().map(|crash| crash.trains.len()).sum()
+    }
+
+    /**
+     * For how many seconds, all crashes taken together, rails have been blocked by wrecks.
+     */
+    pub fn downtime(&self, now: f64) -> f64 {
+        self.log.iter().map(|crash| crash.cleared_at.unwrap_or(now) - crash.time).sum()
+    }
+}
+
+/**
+ * The train is a wreck, and it's part of the crash with the given number.
+ */
+#[derive(Debug, Clone)]
+pub struct TrainHasCrashed {
+    pub crash: usize,
+}
+impl Component for TrainHasCrashed {
+    type Storage = HashMapStorage<Self>;
+}
+
+/**
+ * Signals are supposed to keep trains apart, but they can't help where there aren't
+ * any. The CrashDetector looks at where every car is after the Shunter has moved them,
+ * and if two trains touch, that's a crash. Both come to a halt right there and don't
+ * go anywhere ever again.
+ */
+pub struct CrashDetector;
+
+impl<'a> System<'a> for CrashDetector {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, CarOutline>,
+        ReadStorage<'a, Wagon>,
+        ReadStorage<'a, TrainIsInStation>,
+        ReadStorage<'a, TrainTrail>,
+        WriteStorage<'a, TrainHasCrashed>,
+        WriteStorage<'a, TrainEngine>,
+        WriteStorage<'a, TrainRoute>,
+        WriteStorage<'a, TrackPosition>,
+        WriteStorage<'a, SpeedLimit>,
+        WriteStorage<'a, SpeedLimitFromNextSignal>,
+        WriteStorage<'a, SignalIsReservedByTrain>,
+        WriteStorage<'a, CrossingIsReservedByTrain>,
+        WriteStorage<'a, JunctionIsReservedByTrain>,
+        Read<'a, Tracks>,
+        Read<'a, SimClock>,
+        Write<'a, Crashes>,
+    );
+
+    fn run(&mut self, sys_data: Self::SystemData) {
+        let (
+            entities,
+            outlines,
+            wagons,
+            trains_in_station,
+            trails,
+            mut crashed,
+            mut engines,
+            mut routes,
+            mut track_positions,
+            mut speed_limits_current,
+            mut speed_limits_upcoming,
+            mut reservations,
+            mut crossing_reservations,
+            mut junction_reservations,
+            tracks,
+            clock,
+            mut crashes,
+        ) = sys_data;
+        // Stations are big enough for everyone, so trains in there don't count.
+        let cars: Vec<(Entity, &CarOutline)> = (&entities, &outlines, wagons.maybe()).join()
+            .map(|(car, outline, wagon)| (wagon.map_or(car, |wagon| wagon.train), outline))
+            .filter(|(train, _)| !trains_in_station.contains(*train))
+            .collect();
+        let mut collisions: Vec<(Entity, Entity, Position)> = vec![];
+        for (idx, &(left, left_car)) in cars.iter().enumerate() {
+            for &(right, right_car) in &cars[idx + 1..] {
+                // A wreck running into itself is not news.
+                if left == right || (crashed.contains(left) && crashed.contains(right)) {
+                    continue;
+                }
+                if collisions.iter().any(|&(a, b, _)| (a, b) == (left, right) || (a, b) == (right, left)) {
+                    continue;
+                }
+                if left_car.distance_to(right_car) < CRASH_DISTANCE {
+                    let at = Position::new(
+                        (left_car.front.x + left_car.rear.x + right_car.front.x + right_car.rear.x) / 4.0,
+                        (left_car.front.y + left_car.rear.y + right_car.front.y + right_car.rear.y) / 4.0,
+                    );
+                    collisions.push((left, right, at));
+                }
+            }
+        }
+
+        for (left, right, at) in collisions {
+            println!("Crash! Trains {:?} and {:?} ran into each other at ({:.0}, {:.0})", left, right, at.x, at.y);
+            // Running into a wreck makes you part of it.
+            let number = match crashed.get(left).or(crashed.get(right)) {
+                Some(wreck) => wreck.crash,
+                None => crashes.record(Crash::new(at, clock.now)),
+            };
+            for train in [left, right] {
+                if crashed.contains(train) {
+                    continue;
+                }
+                // The rails under the engine and the ones the wagons are strewn across.
+                let crash = &mut crashes.log[number];
+                let under_engine = track_positions.get(train)
+                    .and_then(|track_pos| tracks.get(track_pos.segment))
+                    .map(|segment| (segment.from, segment.to));
+                let under_wagons = trails.get(train)
+                    .map(|trail| trail.passed.iter().zip(trail.passed.iter().skip(1)).map(|(&a, &b)| (b, a)).collect())
+                    .unwrap_or_else(Vec::new);
+                for rail in under_engine.into_iter().chain(under_wagons) {
+                    if !crash.blocked.contains(&rail) {
+                        crash.blocked.push(rail);
+                    }
+                }
+                crash.trains.push(train);
+
+                if let Some(engine) = engines.get_mut(train) {
+                    engine.speed = 0.0;
+                    engine.acceleration = 0.0;
+                }
+                routes.remove(train);
+                track_positions.remove(train);
+                speed_limits_current.remove(train);
+                speed_limits_upcoming.remove(train);
+                // Whatever is in front of us, we're not going there. We do keep blocking the
+                // signals behind us and the switches we're on though, since we're lying right
+                // in those blocks.
+                release_reservations(
+                    &entities, train, None, trails.get(train),
+                    &mut reservations, &mut crossing_reservations, &mut junction_reservations,
+                );
+                crashed
+                    .insert(train, TrainHasCrashed { crash: number })
+                    .expect("too wrecked to be a wreck");
+            }
+        }
+    }
+}
+
+impl Simulation {
+    /**
+     * Find a wreck close to the given position.
+     */
+    pub fn crash_at(&self, pos: &Position, max_distance: f64) -> Option<usize> {
+        self.world.read_resource::<Crashes>().active()
+            .map(|(number, crash)| (number, crash.at.distance_length_to(pos)))
+            .filter(|&(_, distance)| distance < max_distance)
+            .min_by(|(_, left), (_, right)| left.partial_cmp(right).unwrap())
+            .map(|(number, _)| number)
+    }
+
+    /**
+     * Haul away the wreck of a crash, trains, wagons and all, so that the rails are free
+     * again. Returns false if it was cleared already.
+     */
+    pub fn clear_crash(&mut self, number: usize) -> bool {
+        let now = self.clock().now;
+        let trains = {
+            let mut crashes = self.world.write_resource::<Crashes>();
+            let crash = match crashes.log.get_mut(number) {
+                Some(crash) if !crash.is_cleared() => crash,
+                _ => return false,
+            };
+            crash.cleared_at = Some(now);
+            println!("Clearing the wreck at ({:.0}, {:.0})", crash.at.x, crash.at.y);
+            crash.trains.clone()
+        };
+        // Freed entity ids get handed out again, last one first, so the order in which we
+        // delete things decides what the next trains will be called. That had better be
+        // the same every time.
+        let mut doomed = BTreeSet::new();
+        {
+            let entities = self.world.entities();
+            let consists = self.world.read_storage::<Consist>();
+            let mut train_blockages = self.world.write_storage::<TrainIsBlockingSignals>();
+            let mut signal_blockages = self.world.write_storage::<SignalIsBlockedByTrain>();
+            let mut reservations = self.world.write_storage::<SignalIsReservedByTrain>();
+            let mut crossing_reservations = self.world.write_storage::<CrossingIsReservedByTrain>();
+            let mut junction_reservations = self.world.write_storage::<JunctionIsReservedByTrain>();
+            for train in trains.into_iter().filter(|&train| entities.is_alive(train)) {
+                if let Some(blockage) = train_blockages.remove(train) {
+                    for signal in blockage.signals {
+                        if signal_blockages.get(signal).is_some_and(|blk| blk.train == train) {
+                            signal_blockages.remove(signal);
+                        }
+                    }
+                }
+                release_reservations(
+                    &entities, train, None, None,
+                    &mut reservations, &mut crossing_reservations, &mut junction_reservations,
+                );
+                doomed.extend(consists.get(train).map(|consist| consist.wagons.clone()).unwrap_or_default());
+                doomed.insert(train);
+            }
+        }
+        for ent in doomed {
+            self.world.delete_entity(ent).expect("wreck refused to be hauled away");
+        }
+        true
+    }
+
+    /**
+     * Every crash there has been so far.
+     */
+    pub fn crashes(&self) -> specs::shred::Fetch<'_, Crashes> {
+        self.world.read_resource::<Crashes>()
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::BTreeMap;
+use specs::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use super::physics::Position;
+use super::routing::{DiamondCrossing, Junction, RailNetwork, RouteCosts, TrainRoute};
+use super::signals::{
+    JunctionSignal,
+    SignalKind,
+    SignalIsReservedByTrain,
+    SignalIsBlockedByTrain,
+    TrainIsBlockingSignals,
+    SpeedLimitFromNextSignal,
+    CrossingIsReservedByTrain,
+    JunctionIsReservedByTrain,
+    release_reservations,
+};
+use super::collision::Crashes;
+use super::train::TrainTrail;
+use super::{DeltaTime, SimClock};
+
+/**
+ * How long a train that was told to give way keeps its hands off the signals, in
+ * seconds. That's plenty for the others to grab what they need.
+ */
+const GIVE_WAY_TIME: f64 = 2.0;
+
+/**
+ * What to do once trains are stuck waiting for each other.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum DeadlockResolution {
+    // Tell everyone about it and leave it at that.
+    #[default]
+    Report,
+    // One of the trains lets go of its reservations, so that the others can go first.
+    RollBack,
+    // One of the trains goes some other way, if there is one. If not, it rolls back.
+    Reroute,
+}
+
+/**
+ * Trains that are all waiting for each other in a circle, and the signals and crossings
+ * each of them is waiting for, in the same order.
+ */
+#[derive(Debug, Clone)]
+pub struct Deadlock {
+    pub since:       f64,
+    pub trains:      Vec<Entity>,
+    pub signals:     Vec<Entity>,
+    pub resolved_by: Option<DeadlockResolution>,
+    pub over_at:     Option<f64>,
+}
+
+/**
+ * Every deadlock there has been, the ones that are over included.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Deadlocks {
+    pub log: Vec<Deadlock>,
+}
+
+impl Deadlocks {
+    pub fn current(&self) -> impl Iterator<Item = &Deadlock> {
+        self.log.iter().filter(|deadlock| deadlock.over_at.is_none())
+    }
+}
+
+/**
+ * The train has been told to give way, and doesn't get any reservations for a while.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrainIsGivingWay {
+    pub remaining: f64,
+}
+impl Component for TrainIsGivingWay {
+    type Storage = HashMapStorage<Self>;
+}
+
+/**
+ * The Fahrdienstleiter hands out reservations first come, first served. Two trains
+ * that each hold what the other one needs would wait for each other until the end of
+ * time, so somebody needs to keep an eye on who is waiting for whom.
+ *
+ * Every train waits for at most one other train, the one holding the first signal or
+ * crossing that it can't have. If following those leads us around in a circle, we've
+ * got a deadlock.
+ */
+pub struct DeadlockDetector;
+
+impl<'a> System<'a> for DeadlockDetector {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, TrainRoute>,
+        ReadStorage<'a, Junction>,
+        ReadStorage<'a, JunctionSignal>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, DiamondCrossing>,
+        WriteStorage<'a, SignalIsReservedByTrain>,
+        ReadStorage<'a, SignalIsBlockedByTrain>,
+        ReadStorage<'a, TrainIsBlockingSignals>,
+        WriteStorage<'a, CrossingIsReservedByTrain>,
+        WriteStorage<'a, SpeedLimitFromNextSignal>,
+        WriteStorage<'a, TrainIsGivingWay>,
+        WriteStorage<'a, JunctionIsReservedByTrain>,
+        ReadStorage<'a, TrainTrail>,
+        Read<'a, RouteCosts>,
+        Read<'a, Crashes>,
+        Read<'a, DeadlockResolution>,
+        Read<'a, DeltaTime>,
+        Read<'a, SimClock>,
+        Write<'a, Deadlocks>,
+    );
+
+    fn run(&mut self, sys_data: Self::SystemData) {
+        let (
+            entities,
+            mut routes,
+            junctions,
+            signals,
+            positions,
+            crossings,
+            mut reservations,
+            blockages,
+            train_blockages,
+            mut crossing_reservations,
+            mut speed_limits_upcoming,
+            mut giving_way,
+            mut junction_reservations,
+            trails,
+            costs,
+            crashes,
+            resolution,
+            delta,
+            clock,
+            mut deadlocks,
+        ) = sys_data;
+        for wait in (&mut giving_way).join() {
+            wait.remaining -= delta.fraction;
+        }
+        let done: Vec<Entity> = (&entities, &giving_way).join()
+            .filter(|(_, wait)| wait.remaining <= 0.0)
+            .map(|(train, _)| train)
+            .collect();
+        for train in done {
+            giving_way.remove(train);
+        }
+
+        // Who's waiting for whom, and for which signal or crossing.
+        let mut waits_for: BTreeMap<Entity, (Entity, Entity)> = BTreeMap::new();
+        for (train, route, _) in (&entities, &routes, !&giving_way).join() {
+            let two_signals: Vec<Entity> = route.hops.iter()
+                .filter(|&&hop| signals.contains(hop))
+                .take(2)
+                .cloned()
+                .collect();
+            let someone_else = |holder: Option<Entity>| holder.filter(|&holder| holder != train);
+            // We can't go into the block behind our first signal while someone is still in it,
+            // and we can't have either signal while someone else has reserved it. Path signals
+            // don't mind company in their block, so that's not what we're waiting for there.
+            let holder = two_signals.first().and_then(|&first| {
+                let is_path = signals.get(first).is_some_and(|signal| signal.kind == SignalKind::Path);
+                someone_else(reservations.get(first).map(|rsvp| rsvp.train))
+                    .or_else(|| someone_else(blockages.get(first).filter(|_| !is_path).map(|blk| blk.train)))
+                    .map(|holder| (holder, first))
+            }).or_else(|| two_signals.get(1).and_then(|&second| {
+                someone_else(reservations.get(second).map(|rsvp| rsvp.train)).map(|holder| (holder, second))
+            })).or_else(|| {
+                // Same goes for the diamond crossings and switches between the two.
+                if two_signals.len() < 2 {
+                    return None;
+                }
+                let first  = route.hops.iter().position(|&hop| hop == two_signals[0]).unwrap();
+                let second = route.hops.iter().position(|&hop| hop == two_signals[1]).unwrap();
+                route.hops.range(first + 1..second)
+                    .filter_map(|&hop| crossings.get(hop).map(|crossing| [hop, crossing.other]))
+                    .flatten()
+                    .find_map(|side| {
+                        someone_else(crossing_reservations.get(side).map(|rsvp| rsvp.train)).map(|holder| (holder, side))
+                    })
+                    .or_else(|| route.hops.range(first..=second).find_map(|&hop| {
+                        someone_else(junction_reservations.get(hop).map(|rsvp| rsvp.train)).map(|holder| (holder, hop))
+                    }))
+            });
+            if let Some(holder) = holder {
+                waits_for.insert(train, holder);
+            }
+        }
+
+        // Every train has at most one train it waits for, so we just follow along until we
+        // end up somewhere we've been before.
+        let mut cycles: Vec<Vec<Entity>> = vec![];
+        let mut seen: Vec<Entity> = vec![];
+        for &start in waits_for.keys() {
+            let mut path = vec![];
+            let mut here = Some(start);
+            while let Some(train) = here.filter(|train| !seen.contains(train)) {
+                seen.push(train);
+                path.push(train);
+                here = waits_for.get(&train).map(|&(next, _)| next);
+            }
+            // If we ran out of trains to follow, whoever we got to isn't waiting for anyone.
+            if let Some(idx) = here.and_then(|here| path.iter().position(|&train| train == here)) {
+                let mut cycle = path.split_off(idx);
+                // Start with the lowest id, so that we recognize the same circle next time.
+                let lowest = (0..cycle.len()).min_by_key(|&idx| cycle[idx]).unwrap();
+                cycle.rotate_left(lowest);
+                cycles.push(cycle);
+            }
+        }
+
+        // Deadlocks that we don't see anymore are over.
+        for deadlock in deadlocks.log.iter_mut().filter(|deadlock| deadlock.over_at.is_none()) {
+            if !cycles.contains(&deadlock.trains) {
+                println!("Trains {:?} are moving again", deadlock.trains);
+                deadlock.over_at = Some(clock.now);
+            }
+        }
+
+        for cycle in cycles {
+            // One we know about already might still need resolving, if we weren't supposed to
+            // do anything about it back then.
+            let known = deadlocks.log.iter().position(|deadlock| deadlock.over_at.is_none() && deadlock.trains == cycle);
+            let fresh = known.is_none();
+            let idx = known.unwrap_or_else(|| {
+                let waited_for: Vec<Entity> = cycle.iter().map(|train| waits_for[train].1).collect();
+                println!("Deadlock! Trains {:?} are waiting for each other at {:?}", cycle, waited_for);
+                deadlocks.log.push(Deadlock {
+                    since:       clock.now,
+                    trains:      cycle,
+                    signals:     waited_for,
+                    resolved_by: None,
+                    over_at:     None,
+                });
+                deadlocks.log.len() - 1
+            });
+            let deadlock = &mut deadlocks.log[idx];
+            if *resolution == DeadlockResolution::Report || deadlock.resolved_by.is_some() {
+                continue;
+            }
+
+            if *resolution == DeadlockResolution::Reroute {
+                let mut network = RailNetwork::new(&junctions, &positions, &signals, costs.clone());
+                for (signal, rsvp) in (&entities, &reservations).join() {
+                    network.claim(signal, rsvp.train);
+                }
+                for (signal, blockage) in (&entities, &blockages).join() {
+                    network.claim(signal, blockage.train);
+                }
+                network.avoid_wrecks(&crashes);
+                for &train in &deadlock.trains {
+                    let route = routes.get_mut(train).unwrap();
+                    if route.next_hop() == route.dest {
+                        continue;
+                    }
+                    let detour = network.find_path(Some(train), route.next_hop(), route.dest);
+                    // A detour that still goes through what we've been waiting for is no detour.
+                    if detour.is_empty() || detour == route.hops || detour.contains(&waits_for[&train].1) {
+                        continue;
+                    }
+                    println!("Sending train {:?} around the deadlock via {:?}", train, detour);
+                    route.hops = detour;
+                    release_reservations(
+                        &entities, train, Some(&route.hops), trails.get(train),
+                        &mut reservations, &mut crossing_reservations, &mut junction_reservations,
+                    );
+                    speed_limits_upcoming.remove(train);
+                    deadlock.resolved_by = Some(DeadlockResolution::Reroute);
+                    break;
+                }
+            }
+
+            if deadlock.resolved_by.is_none() {
+                // Someone needs to give back what the train before them in the circle waits
+                // for. Blocks that a train is standing in can't be given back, so it has to be
+                // one that only holds a reservation. And if the other train's way leads through
+                // the blocks we're standing in, it would only run into us.
+                let count = deadlock.trains.len();
+                let victim = (0..count)
+                    .map(|idx| (deadlock.trains[(idx + count - 1) % count], deadlock.trains[idx]))
+                    .find(|&(waiting, holder)| {
+                        let wanted = waits_for[&waiting].1;
+                        let gives_back = reservations.get(wanted).is_some_and(|rsvp| rsvp.train == holder) ||
+                            crossing_reservations.get(wanted).is_some_and(|rsvp| rsvp.train == holder) ||
+                            junction_reservations.get(wanted).is_some_and(|rsvp| rsvp.train == holder);
+                        let in_the_way = train_blockages.get(holder).is_some_and(|blk| {
+                            routes.get(waiting).is_some_and(|route| blk.signals.iter().any(|signal| route.hops.contains(signal)))
+                        });
+                        gives_back && !in_the_way
+                    })
+                    .map(|(_, holder)| holder);
+                match victim {
+                    Some(train) => {
+                        println!("Train {:?} gives way to break the deadlock", train);
+                        release_reservations(
+                            &entities, train, None, trails.get(train),
+                            &mut reservations, &mut crossing_reservations, &mut junction_reservations,
+                        );
+                        speed_limits_upcoming.remove(train);
+                        giving_way
+                            .insert(train, TrainIsGivingWay { remaining: GIVE_WAY_TIME })
+                            .expect("too stubborn to give way");
+                        deadlock.resolved_by = Some(DeadlockResolution::RollBack);
+                    },
+                    None if fresh => println!("Nobody in the deadlock can give way, the trains are stuck in each other's blocks"),
+                    None => {},
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,656 @@
+use std::collections::VecDeque;
+use specs::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use super::physics::{Position, SpeedLimit, TrainEngine};
+use super::routing::{Junction, DiamondCrossing, RailNetwork, RouteCosts, TrainRoute, TrainWantsToTravelTo};
+use super::orders::TrainOrders;
+use super::signals::{
+    JunctionSignal,
+    SignalKind,
+    SignalIsReservedByTrain,
+    SignalIsBlockedByTrain,
+    TrainIsBlockingSignals,
+    SpeedLimitFromNextSignal,
+    CrossingIsReservedByTrain,
+    JunctionIsReservedByTrain,
+    release_reservations,
+};
+use super::history::{EditOp, Change};
+use super::collision::Crashes;
+use super::simulation::Simulation;
+use super::train::TrainTrail;
+use super::track::{Tracks, TrackSegment, TrackPosition, SegmentId};
+use super::{Role, RoleKind};
+
+/**
+ * What to build where a new rail runs across an existing one: Either a switch that
+ * connects both lines, or a diamond crossing where trains go straight across.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CrossingKind {
+    Switch,
+    Diamond,
+}
+
+/**
+ * Everything the user can do to the map. Adding is easy, but when removing things we
+ * need to make sure that no train is left with a route that leads into nothing, and
+ * no signal stays reserved by someone who's never going to show up.
+ *
+ * Every edit is made from a handful of small steps (see history::EditOp) which get
+ * recorded, so that it can be undone and redone later.
+ */
+impl Simulation {
+    /**
+     * Build a rail from one position to another. If either end is close to an existing
+     * junction, the rail is attached to it. If it's in the middle of some other rail,
+     * that one is split in two so that the new rail can join it there. Otherwise a new
+     * waypoint is created. Wherever the rail runs across other rails on its way, we
+     * build the given kind of crossing. Returns the junctions at both ends of the rail.
+     */
+    pub fn add_rail(&mut self, from: Position, to: Position, crossing: CrossingKind) -> (Entity, Entity) {
+        self.history.begin();
+        let start = self.attach_point(&from, &to);
+        let end = self.attach_point(&to, &from);
+        let mut stops = vec![start];
+        for stop in self.cross_rails(start, end, crossing).into_iter().chain(Some(end)) {
+            if stops.last() != Some(&stop) {
+                stops.push(stop);
+            }
+        }
+        for pair in stops.windows(2) {
+            self.lay_rail(pair[0], pair[1]);
+        }
+        self.refresh_approach_signals();
+        self.history.commit();
+        (start, end)
+    }
+
+    /**
+     * Cut a rail in two by putting a new waypoint at the given position, and rewire
+     * the junctions and the routes of any trains on it accordingly.
+     * Returns the new waypoint, or None if the rail doesn't exist.
+     */
+    pub fn split_rail(&mut self, segment: SegmentId, at: Position) -> Option<Entity> {
+        self.history.begin();
+        let waypoint = self.tear_out_rail(segment).map(|track| {
+            println!("Splitting rail from {:?} to {:?} at {:?}", track.from, track.to, at);
+            let waypoint = self.create_waypoint(at);
+            self.lay_rail(track.from, waypoint);
+            self.lay_rail(waypoint, track.to);
+            self.insert_into_routes(track.from, track.to, waypoint);
+            self.put_trains_back_on_track();
+            waypoint
+        });
+        self.refresh_approach_signals();
+        self.history.commit();
+        waypoint
+    }
+
+    /**
+     * Find the junction that a rail ending at pos, coming from the other end, should be
+     * attached to.
+     */
+    fn attach_point(&mut self, pos: &Position, other_end: &Position) -> Entity {
+        if let Some(junction) = self.junction_at(pos, 4.0) {
+            return self.side_of_crossing(junction, pos, other_end);
+        }
+        if let Some(hit) = self.track_at(pos, 5.0) {
+            // Right next to the end of that rail, it's nicer to use the junction that's
+            // already there than to split off a tiny stump.
+            if let Some(junction) = self.junction_at(&hit.point, 4.0) {
+                return self.side_of_crossing(junction, pos, other_end);
+            }
+            if let Some(waypoint) = self.split_rail(hit.segment, hit.point) {
+                return waypoint;
+            }
+        }
+        self.create_waypoint(pos.clone())
+    }
+
+    /**
+     * Build crossings everywhere that a rail from start to end would run across the
+     * rails that are already there. Returns the junctions on the new line, in the
+     * order in which a train going from start to end would pass them.
+     */
+    fn cross_rails(&mut self, start: Entity, end: Entity, crossing: CrossingKind) -> Vec<Entity> {
+        let (line, crossings) = {
+            let positions = self.world.read_storage::<Position>();
+            let line = TrackSegment {
+                from:  start,
+                to:    end,
+                start: positions.get(start).unwrap().clone(),
+                end:   positions.get(end).unwrap().clone(),
+            };
+            let tracks = self.world.read_resource::<Tracks>();
+            let crossings = tracks.crossings(&line).into_iter()
+                .filter(|hit| !tracks.get(hit.segment).unwrap().connects(start))
+                .filter(|hit| !tracks.get(hit.segment).unwrap().connects(end))
+                .filter(|hit| {
+                    hit.point.distance_length_to(&line.start) >= 4.0 &&
+                        hit.point.distance_length_to(&line.end) >= 4.0
+                })
+                .collect::<Vec<_>>();
+            (line, crossings)
+        };
+        let mut stops = vec![];
+        for hit in crossings {
+            let track = match self.world.read_resource::<Tracks>().get(hit.segment) {
+                Some(track) => track.clone(),
+                None => continue,
+            };
+            // If we're running across the end of that rail, there's a junction already,
+            // and we just join it no matter what kind of crossing we wanted.
+            if hit.point.distance_length_to(&track.start) < 4.0 {
+                stops.push(self.side_of_crossing(track.from, &line.start, &line.end));
+                continue;
+            }
+            if hit.point.distance_length_to(&track.end) < 4.0 {
+                stops.push(self.side_of_crossing(track.to, &line.start, &line.end));
+                continue;
+            }
+            let theirs = match self.split_rail(hit.segment, hit.point.clone()) {
+                Some(waypoint) => waypoint,
+                None => continue,
+            };
+            match crossing {
+                CrossingKind::Switch => stops.push(theirs),
+                CrossingKind::Diamond => {
+                    let ours = self.create_waypoint(hit.point);
+                    self.put_up_crossing(theirs, ours);
+                    stops.push(ours);
+                },
+            }
+        }
+        stops
+    }
+
+    /**
+     * Both halves of a diamond crossing sit on the very same spot, so the junction we
+     * found there may well belong to the other line. Of the two, pick the one whose rails
+     * run the most like the line that's being drawn from one position to the other.
+     */
+    fn side_of_crossing(&self, junction: Entity, from: &Position, to: &Position) -> Entity {
+        let other = match self.world.read_storage::<DiamondCrossing>().get(junction) {
+            Some(crossing) => crossing.other,
+            None => return junction,
+        };
+        let positions = self.world.read_storage::<Position>();
+        let junctions = self.world.read_storage::<Junction>();
+        let line = to.distance_to(from);
+        let alignment = |half: Entity| {
+            let here = positions.get(half).unwrap();
+            junctions.get(half).unwrap().connections.iter()
+                .filter_map(|&next| positions.get(next))
+                .map(|there| {
+                    let rail = there.distance_to(here);
+                    ((rail.x * line.x + rail.y * line.y) / (rail.length() * line.length())).abs()
+                })
+                .filter(|cos| cos.is_finite())
+                .fold(0.0, f64::max)
+        };
+        if alignment(other) > alignment(junction) { other } else { junction }
+    }
+
+    /**
+     * Put a signal of the given kind onto the given junction.
+     */
+    pub fn place_signal(&mut self, junction: Entity, kind: SignalKind) {
+        println!("We should make a {:?} signal at {:?}", kind, junction);
+        self.history.begin();
+        self.put_up_signal(junction, kind);
+        self.refresh_approach_signals();
+        self.history.commit();
+    }
+
+    /**
+     * Remove whatever is under the cursor: A wreck if there is one, otherwise an approach
+     * signal that was put up by hand, otherwise a signal, otherwise a waypoint along with
+     * all of its rails, otherwise the piece of rail that's there. Industries can't be
+     * bulldozed. Returns false if there was nothing to remove.
+     */
+    pub fn bulldoze(&mut self, pos: &Position) -> bool {
+        if let Some(crash) = self.crash_at(pos, 10.0) {
+            return self.clear_crash(crash);
+        }
+        if self.remove_approach_signal(pos) {
+            return true;
+        }
+        if let Some(junction) = self.junction_at(pos, 10.0) {
+            if self.world.read_storage::<JunctionSignal>().contains(junction) {
+                return self.remove_signal(junction);
+            }
+            if self.remove_junction(junction) {
+                return true;
+            }
+        }
+        if let Some(hit) = self.track_at(pos, 5.0) {
+            return self.remove_rail(hit.segment);
+        }
+        false
+    }
+
+    /**
+     * Take down the signal at a junction, along with any reservations or blockages
+     * it had.
+     */
+    pub fn remove_signal(&mut self, junction: Entity) -> bool {
+        self.history.begin();
+        let removed = self.take_down_signal(junction);
+        self.refresh_approach_signals();
+        self.history.commit();
+        removed
+    }
+
+    /**
+     * Tear out a piece of rail. Waypoints that are left without any rails are removed
+     * as well.
+     */
+    pub fn remove_rail(&mut self, segment: SegmentId) -> bool {
+        self.history.begin();
+        let removed = match self.tear_out_rail(segment) {
+            Some(segment) => {
+                self.remove_if_orphaned(segment.from);
+                self.remove_if_orphaned(segment.to);
+                true
+            },
+            None => false,
+        };
+        self.refresh_approach_signals();
+        self.history.commit();
+        self.repair_routes();
+        removed
+    }
+
+    /**
+     * Remove a waypoint along with all the rails that lead to it.
+     */
+    pub fn remove_junction(&mut self, junction: Entity) -> bool {
+        let is_waypoint = self.world.read_storage::<Junction>().get(junction)
+            .is_some_and(|j| !j.is_terminal);
+        if !is_waypoint {
+            return false;
+        }
+        println!("Removing junction {:?}", junction);
+        self.history.begin();
+        let segments = self.world.read_resource::<Tracks>().segments_at(junction);
+        let mut neighbours = vec![];
+        for segment in segments {
+            if let Some(segment) = self.tear_out_rail(segment) {
+                neighbours.push(if segment.from == junction { segment.to } else { segment.from });
+            }
+        }
+        self.take_down_signal(junction);
+        self.take_down_crossing(junction);
+        self.delete_waypoint(junction);
+        for neighbour in neighbours {
+            self.remove_if_orphaned(neighbour);
+        }
+        self.refresh_approach_signals();
+        self.history.commit();
+        self.repair_routes();
+        true
+    }
+
+    /**
+     * Take back the last change. Returns false if there was nothing to undo.
+     */
+    pub fn undo(&mut self) -> bool {
+        let change = match self.history.pop_undo() {
+            Some(change) => change,
+            None => return false,
+        };
+        let inverse = change.iter().rev().map(EditOp::inverse).collect();
+        let undone: Change = self.replay(inverse);
+        // Redoing means doing the original change again, which is the inverse of what we
+        // just did, with all junctions that came back under a new name already renamed.
+        self.history.push_redo(undone.iter().rev().map(EditOp::inverse).collect());
+        self.repair_routes();
+        self.refresh_approach_signals();
+        true
+    }
+
+    /**
+     * Do the last change that was undone again. Returns false if there was nothing to redo.
+     */
+    pub fn redo(&mut self) -> bool {
+        let change = match self.history.pop_redo() {
+            Some(change) => change,
+            None => return false,
+        };
+        let redone = self.replay(change);
+        self.history.push_undo(redone);
+        self.repair_routes();
+        self.refresh_approach_signals();
+        true
+    }
+
+    /**
+     * Apply a list of steps without recording them. Junctions that have to be brought
+     * back get a new Entity, which we tell the rest of the steps and the history about.
+     * Returns the steps as they were actually applied.
+     */
+    fn replay(&mut self, mut ops: Change) -> Change {
+        for idx in 0..ops.len() {
+            let op = ops[idx].clone();
+            if let Some((old, new)) = self.apply(op) {
+                for later in ops.iter_mut() {
+                    later.replace_entity(old, new);
+                }
+                self.history.replace_entity(old, new);
+            }
+        }
+        ops
+    }
+
+    fn apply(&mut self, op: EditOp) -> Option<(Entity, Entity)> {
+        match op {
+            EditOp::AddWaypoint { junction, pos } => {
+                return Some((junction, self.create_waypoint(pos)));
+            },
+            EditOp::RemoveWaypoint { junction, .. } => self.delete_waypoint(junction),
+            EditOp::Connect { segment, track } => {
+                self.connect(track.from, track.to);
+                self.world.write_resource::<Tracks>().insert(segment, track);
+            },
+            EditOp::Disconnect { segment, .. } => { self.tear_out_rail(segment); },
+            EditOp::AddSignal { junction, kind } => { self.put_up_signal(junction, kind); },
+            EditOp::RemoveSignal { junction, .. } => { self.take_down_signal(junction); },
+            EditOp::AddCrossing { junction, other } => self.put_up_crossing(junction, other),
+            EditOp::RemoveCrossing { junction, .. } => { self.take_down_crossing(junction); },
+            EditOp::AddApproachSignal { signal, pos } => { self.put_up_approach_signal_at(signal, &pos); },
+            EditOp::RemoveApproachSignal { signal, pos } => { self.take_down_approach_signal_at(signal, &pos); },
+        }
+        None
+    }
+
+    fn create_waypoint(&mut self, pos: Position) -> Entity {
+        let junction = self.world.create_entity()
+            .with(pos.clone())
+            .with(Role(RoleKind::WayPoint))
+            .with(Junction::new())
+            .build();
+        self.history.record(EditOp::AddWaypoint { junction, pos });
+        junction
+    }
+
+    // Only ever called on waypoints that don't have any rails, signals or crossings left.
+    // Trains may still remember passing it though, or have it reserved.
+    fn delete_waypoint(&mut self, junction: Entity) {
+        let pos = self.world.read_storage::<Position>().get(junction).cloned()
+            .expect("waypoint without a position");
+        for trail in (&mut self.world.write_storage::<TrainTrail>()).join() {
+            trail.passed.retain(|&passed| passed != junction);
+        }
+        self.world.write_storage::<JunctionIsReservedByTrain>().remove(junction);
+        self.world.delete_entity(junction).expect("waypoint was already gone");
+        self.history.record(EditOp::RemoveWaypoint { junction, pos });
+    }
+
+    fn lay_rail(&mut self, left: Entity, right: Entity) -> SegmentId {
+        self.connect(left, right);
+        let track = {
+            let positions = self.world.read_storage::<Position>();
+            TrackSegment {
+                from:  left,
+                to:    right,
+                start: positions.get(left).unwrap().clone(),
+                end:   positions.get(right).unwrap().clone(),
+            }
+        };
+        let segment = self.world.write_resource::<Tracks>().add(track.clone());
+        self.history.record(EditOp::Connect { segment, track });
+        segment
+    }
+
+    fn tear_out_rail(&mut self, segment: SegmentId) -> Option<TrackSegment> {
+        let track = self.world.write_resource::<Tracks>().remove(segment)?;
+        println!("Removing rail from {:?} to {:?}", track.from, track.to);
+        self.disconnect(track.from, track.to);
+        self.history.record(EditOp::Disconnect { segment, track: track.clone() });
+        Some(track)
+    }
+
+    fn put_up_signal(&mut self, junction: Entity, kind: SignalKind) -> bool {
+        let placed = self.world.write_storage::<JunctionSignal>()
+            .insert(junction, JunctionSignal::of_kind(kind))
+            .expect("Sad signalling panda")
+            .is_none();
+        if placed {
+            self.history.record(EditOp::AddSignal { junction, kind });
+        }
+        placed
+    }
+
+    fn take_down_signal(&mut self, junction: Entity) -> bool {
+        let kind = match self.world.write_storage::<JunctionSignal>().remove(junction) {
+            Some(signal) => signal.kind,
+            None => return false,
+        };
+        println!("Removing signal at {:?}", junction);
+        self.world.write_storage::<SignalIsReservedByTrain>().remove(junction);
+        self.world.write_storage::<SignalIsBlockedByTrain>().remove(junction);
+        {
+            let entities = self.world.entities();
+            let mut train_blockages = self.world.write_storage::<TrainIsBlockingSignals>();
+            let mut unblocked_trains = vec![];
+            for (train, blk) in (&entities, &mut train_blockages).join() {
+                blk.signals.retain(|&signal| signal != junction);
+                if blk.signals.is_empty() {
+                    unblocked_trains.push(train);
+                }
+            }
+            for train in unblocked_trains {
+                train_blockages.remove(train);
+            }
+        }
+        self.history.record(EditOp::RemoveSignal { junction, kind });
+        true
+    }
+
+    fn put_up_crossing(&mut self, junction: Entity, other: Entity) {
+        let mut crossings = self.world.write_storage::<DiamondCrossing>();
+        crossings.insert(junction, DiamondCrossing { other }).expect("crossing into the void");
+        crossings.insert(other, DiamondCrossing { other: junction }).expect("crossing into the void");
+        self.history.record(EditOp::AddCrossing { junction, other });
+    }
+
+    /**
+     * Turn a diamond crossing back into two waypoints that don't know about each other.
+     * Whoever had reserved it won't need to anymore.
+     */
+    fn take_down_crossing(&mut self, junction: Entity) -> bool {
+        let other = match self.world.write_storage::<DiamondCrossing>().remove(junction) {
+            Some(crossing) => crossing.other,
+            None => return false,
+        };
+        println!("Removing diamond crossing at {:?}", junction);
+        self.world.write_storage::<DiamondCrossing>().remove(other);
+        self.world.write_storage::<CrossingIsReservedByTrain>().remove(junction);
+        self.world.write_storage::<CrossingIsReservedByTrain>().remove(other);
+        self.history.record(EditOp::RemoveCrossing { junction, other });
+        true
+    }
+
+    fn connect(&mut self, left: Entity, right: Entity) {
+        let mut junctions = self.world.write_storage::<Junction>();
+        junctions.get_mut(left).unwrap().connections.push(right);
+        junctions.get_mut(right).unwrap().connections.push(left);
+    }
+
+    fn disconnect(&mut self, left: Entity, right: Entity) {
+        let mut junctions = self.world.write_storage::<Junction>();
+        for (here, there) in &[(left, right), (right, left)] {
+            if let Some(junction) = junctions.get_mut(*here) {
+                if let Some(idx) = junction.connections.iter().position(|c| c == there) {
+                    junction.connections.remove(idx);
+                }
+            }
+        }
+    }
+
+    fn remove_if_orphaned(&mut self, junction: Entity) {
+        let orphaned = self.world.read_storage::<Junction>().get(junction)
+            .is_some_and(|j| !j.is_terminal && j.connections.is_empty());
+        if orphaned {
+            println!("Removing orphaned waypoint {:?}", junction);
+            self.take_down_signal(junction);
+            self.take_down_crossing(junction);
+            self.delete_waypoint(junction);
+        }
+    }
+
+    /**
+     * A new waypoint has been put between two junctions that used to be connected
+     * directly. Every train that wanted to go from one to the other now needs to pass
+     * the waypoint on its way, including those that are on that rail right now.
+     */
+    fn insert_into_routes(&mut self, left: Entity, right: Entity, waypoint: Entity) {
+        let positions = self.world.read_storage::<Position>();
+        let junctions = self.world.read_storage::<Junction>();
+        let mut routes = self.world.write_storage::<TrainRoute>();
+        let tracks = self.world.read_resource::<Tracks>();
+        for (train_pos, route) in (&positions, &mut routes).join() {
+            let mut idx = 1;
+            while idx < route.hops.len() {
+                let (here, next) = (route.hops[idx - 1], route.hops[idx]);
+                if (here == left && next == right) || (here == right && next == left) {
+                    route.hops.insert(idx, waypoint);
+                    idx += 1;
+                }
+                idx += 1;
+            }
+            // If we're on the half that doesn't touch our next hop, we need to pass the
+            // waypoint first.
+            let next = route.next_hop();
+            if (next == left || next == right) && !route.can_reach_next_hop(train_pos, &junctions, &tracks) {
+                route.hops.push_front(waypoint);
+            }
+        }
+    }
+
+    /**
+     * After rails went away, some trains may have routes that lead into nothing.
+     * Find those and send them on another way to their destination, or stop them
+     * where they are if there is none.
+     */
+    fn repair_routes(&mut self) {
+        self.put_trains_back_on_track();
+        let mut stranded = vec![];
+        let mut rerouted = vec![];
+        {
+            let entities = self.world.entities();
+            let positions = self.world.read_storage::<Position>();
+            let junctions = self.world.read_storage::<Junction>();
+            let signals = self.world.read_storage::<JunctionSignal>();
+            let mut routes = self.world.write_storage::<TrainRoute>();
+            let tracks = self.world.read_resource::<Tracks>();
+            let costs = self.world.read_resource::<RouteCosts>().clone();
+            let mut network = RailNetwork::new(&junctions, &positions, &signals, costs);
+            for (signal, rsvp) in (&entities, &self.world.read_storage::<SignalIsReservedByTrain>()).join() {
+                network.claim(signal, rsvp.train);
+            }
+            for (signal, blockage) in (&entities, &self.world.read_storage::<SignalIsBlockedByTrain>()).join() {
+                network.claim(signal, blockage.train);
+            }
+            network.avoid_wrecks(&self.world.read_resource::<Crashes>());
+            for (train, train_pos, route) in (&entities, &positions, &mut routes).join() {
+                if route.is_intact(train_pos, &junctions, &tracks) {
+                    continue;
+                }
+                let detour =
+                    if !route.can_reach_next_hop(train_pos, &junctions, &tracks) {
+                        // The rails under our feet are gone, there's no going anywhere.
+                        VecDeque::new()
+                    } else if route.next_hop() == route.dest {
+                        VecDeque::from(vec![route.dest])
+                    } else {
+                        network.find_path(Some(train), route.next_hop(), route.dest)
+                    };
+                if detour.is_empty() {
+                    stranded.push(train);
+                } else {
+                    println!("Rerouting train {:?} via {:?}", train, detour);
+                    route.hops = detour;
+                    rerouted.push(train);
+                }
+            }
+        }
+        // Stranded trains give up all their reservations, rerouted ones only those for signals,
+        // crossings and switches that are no longer on their way.
+        {
+            let entities = self.world.entities();
+            let routes = self.world.read_storage::<TrainRoute>();
+            let mut reservations = self.world.write_storage::<SignalIsReservedByTrain>();
+            let mut crossing_reservations = self.world.write_storage::<CrossingIsReservedByTrain>();
+            let mut junction_reservations = self.world.write_storage::<JunctionIsReservedByTrain>();
+            let trails = self.world.read_storage::<TrainTrail>();
+            for &train in stranded.iter().chain(rerouted.iter()) {
+                let hops = if stranded.contains(&train) { None } else { routes.get(train).map(|route| &route.hops) };
+                release_reservations(
+                    &entities, train, hops, trails.get(train),
+                    &mut reservations, &mut crossing_reservations, &mut junction_reservations,
+                );
+            }
+        }
+        for train in stranded {
+            self.stop_train(train);
+        }
+    }
+
+    /**
+     * Rails that trains are on may have been split or replaced. Find the rail under each
+     * train that leads to its next hop, and put it there. Trains for which there is none
+     * are left alone, they're going to be stranded anyway.
+     */
+    pub(crate) fn put_trains_back_on_track(&mut self) {
+        let entities = self.world.entities();
+        let positions = self.world.read_storage::<Position>();
+        let routes = self.world.read_storage::<TrainRoute>();
+        let mut track_positions = self.world.write_storage::<TrackPosition>();
+        let tracks = self.world.read_resource::<Tracks>();
+        for (train, train_pos, route) in (&entities, &positions, &routes).join() {
+            let next = route.next_hop();
+            if track_positions.get(train).is_some_and(|track_pos| track_pos.distance_to(next, &tracks).is_some()) {
+                continue;
+            }
+            let segment = tracks.segments_at(next).into_iter().find(|&id| {
+                tracks.get(id).unwrap().closest_point(train_pos).0.distance_length_to(train_pos) < 3.0
+            });
+            if let Some(track_pos) = segment.and_then(|id| TrackPosition::onto(id, train_pos, &tracks)) {
+                track_positions.insert(train, track_pos).expect("train fell off the map");
+            }
+        }
+    }
+
+    /**
+     * Bring a train to a halt right where it is and forget about where it wanted to go,
+     * along with its orders and the switches it had reserved on the way there. It keeps
+     * blocking its signals though, since it is still standing in those blocks.
+     */
+    fn stop_train(&mut self, train: Entity) {
+        println!("Train {:?} has nowhere to go, stopping it", train);
+        self.world.write_storage::<TrainRoute>().remove(train);
+        self.world.write_storage::<TrainWantsToTravelTo>().remove(train);
+        self.world.write_storage::<TrainOrders>().remove(train);
+        {
+            let entities = self.world.entities();
+            let mut junction_reservations = self.world.write_storage::<JunctionIsReservedByTrain>();
+            let reserved = (&entities, &junction_reservations).join()
+                .filter(|(_junction, rsvp)| rsvp.train == train)
+                .map(|(junction, _rsvp)| junction)
+                .collect::<Vec<_>>();
+            for junction in reserved {
+                junction_reservations.remove(junction);
+            }
+        }
+        self.world.write_storage::<TrackPosition>().remove(train);
+        self.world.write_storage::<SpeedLimit>().remove(train);
+        self.world.write_storage::<SpeedLimitFromNextSignal>().remove(train);
+        if let Some(engine) = self.world.write_storage::<TrainEngine>().get_mut(train) {
+            engine.speed = 0.0;
+            engine.acceleration = 0.0;
+        }
+    }
+}
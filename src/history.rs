@@ -0,0 +1,175 @@
+use specs::prelude::*;
+
+use super::physics::Position;
+use super::track::{SegmentId, TrackSegment};
+use super::signals::SignalKind;
+
+/**
+ * The smallest steps that a change to the map is made of. Each one of them can be
+ * turned around, which is what makes undo work: To take back a change, we run the
+ * inverse of its steps in reverse order.
+ */
+#[derive(Debug, Clone)]
+pub enum EditOp {
+    AddWaypoint    { junction: Entity, pos: Position },
+    RemoveWaypoint { junction: Entity, pos: Position },
+    Connect        { segment: SegmentId, track: TrackSegment },
+    Disconnect     { segment: SegmentId, track: TrackSegment },
+    AddSignal      { junction: Entity, kind: SignalKind },
+    RemoveSignal   { junction: Entity, kind: SignalKind },
+    AddCrossing    { junction: Entity, other: Entity },
+    RemoveCrossing { junction: Entity, other: Entity },
+    // Approach signals that were put up by hand, for the given junction signal. They get
+    // new entities all the time, so they're found by where they are.
+    AddApproachSignal    { signal: Entity, pos: Position },
+    RemoveApproachSignal { signal: Entity, pos: Position },
+}
+
+impl EditOp {
+    pub fn inverse(&self) -> EditOp {
+        match self.clone() {
+            EditOp::AddWaypoint { junction, pos } =>
+                EditOp::RemoveWaypoint { junction, pos },
+            EditOp::RemoveWaypoint { junction, pos } =>
+                EditOp::AddWaypoint { junction, pos },
+            EditOp::Connect { segment, track } =>
+                EditOp::Disconnect { segment, track },
+            EditOp::Disconnect { segment, track } =>
+                EditOp::Connect { segment, track },
+            EditOp::AddSignal { junction, kind } =>
+                EditOp::RemoveSignal { junction, kind },
+            EditOp::RemoveSignal { junction, kind } =>
+                EditOp::AddSignal { junction, kind },
+            EditOp::AddCrossing { junction, other } =>
+                EditOp::RemoveCrossing { junction, other },
+            EditOp::RemoveCrossing { junction, other } =>
+                EditOp::AddCrossing { junction, other },
+            EditOp::AddApproachSignal { signal, pos } =>
+                EditOp::RemoveApproachSignal { signal, pos },
+            EditOp::RemoveApproachSignal { signal, pos } =>
+                EditOp::AddApproachSignal { signal, pos },
+        }
+    }
+
+    /**
+     * A junction that is removed and then brought back comes back as a new Entity,
+     * so everything that talks about the old one needs to be told about the new one.
+     */
+    pub fn replace_entity(&mut self, old: Entity, new: Entity) {
+        let swap = |ent: &mut Entity| {
+            if *ent == old {
+                *ent = new;
+            }
+        };
+        match self {
+            EditOp::AddWaypoint { junction, .. } |
+            EditOp::RemoveWaypoint { junction, .. } |
+            EditOp::AddSignal { junction, .. } |
+            EditOp::RemoveSignal { junction, .. } => swap(junction),
+            EditOp::AddApproachSignal { signal, .. } |
+            EditOp::RemoveApproachSignal { signal, .. } => swap(signal),
+            EditOp::Connect { track, .. } |
+            EditOp::Disconnect { track, .. } => {
+                swap(&mut track.from);
+                swap(&mut track.to);
+            },
+            EditOp::AddCrossing { junction, other } |
+            EditOp::RemoveCrossing { junction, other } => {
+                swap(junction);
+                swap(other);
+            },
+        }
+    }
+}
+
+/**
+ * Everything that happened because of one thing the user did, e.g. drawing a rail
+ * may create two waypoints and connect them.
+ */
+pub type Change = Vec<EditOp>;
+
+/**
+ * Keeps the list of changes that can be undone, and those that have been undone and
+ * can be redone.
+ *
+ * Changes are recorded between begin() and commit(). Those can be nested, so that an
+ * edit can be built from other edits and still end up as a single change.
+ */
+#[derive(Default)]
+pub struct History {
+    undo_stack: Vec<Change>,
+    redo_stack: Vec<Change>,
+    recording:  Change,
+    depth:      usize,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn begin(&mut self) {
+        self.depth += 1;
+    }
+
+    pub fn commit(&mut self) {
+        self.depth -= 1;
+        if self.depth == 0 && !self.recording.is_empty() {
+            let change = std::mem::take(&mut self.recording);
+            self.undo_stack.push(change);
+            self.redo_stack.clear();
+        }
+    }
+
+    /**
+     * Remember that an edit happened. Outside of begin() and commit(), e.g. while
+     * undoing or redoing, this does nothing.
+     */
+    pub fn record(&mut self, op: EditOp) {
+        if self.depth > 0 {
+            self.recording.push(op);
+        }
+    }
+
+    /**
+     * Same as record(), but for something that can only be undone after everything else
+     * in the change has been.
+     */
+    pub fn record_up_front(&mut self, op: EditOp) {
+        if self.depth > 0 {
+            self.recording.insert(0, op);
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    pub fn pop_undo(&mut self) -> Option<Change> {
+        self.undo_stack.pop()
+    }
+
+    pub fn pop_redo(&mut self) -> Option<Change> {
+        self.redo_stack.pop()
+    }
+
+    pub fn push_undo(&mut self, change: Change) {
+        self.undo_stack.push(change);
+    }
+
+    pub fn push_redo(&mut self, change: Change) {
+        self.redo_stack.push(change);
+    }
+
+    pub fn replace_entity(&mut self, old: Entity, new: Entity) {
+        for change in self.undo_stack.iter_mut().chain(self.redo_stack.iter_mut()) {
+            for op in change.iter_mut() {
+                op.replace_entity(old, new);
+            }
+        }
+    }
+}
@@ -0,0 +1,179 @@
+
+extern crate specs;
+extern crate rand;
+
+use std::time::SystemTime;
+use rand_chacha::ChaCha20Rng;
+use rand::SeedableRng;
+use specs::prelude::*;
+use serde::{Serialize, Deserialize};
+
+pub mod physics;
+pub mod routing;
+pub mod signals;
+pub mod approach;
+pub mod cargo;
+pub mod world;
+pub mod map;
+pub mod track;
+pub mod train;
+pub mod orders;
+pub mod timetable;
+pub mod collision;
+pub mod deadlock;
+pub mod replay;
+pub mod simulation;
+pub mod editing;
+pub mod history;
+pub mod savegame;
+pub mod scenario;
+
+pub use simulation::Simulation;
+
+#[derive(Default)]
+pub struct DeltaTime {
+    pub fraction: f64,
+    last_updated_at: Option<SystemTime>
+}
+
+impl DeltaTime {
+    pub fn new() -> Self {
+        Self {
+            fraction: 0.05,
+            last_updated_at: Some(SystemTime::now())
+        }
+    }
+
+    pub fn update(&mut self) {
+        let now = SystemTime::now();
+        let dura = now.duration_since(self.last_updated_at.unwrap()).unwrap();
+        self.fraction = (dura.as_secs() as f64) + (dura.subsec_micros() as f64 / 1_000_000.0);
+        self.last_updated_at = Some(now);
+    }
+}
+
+/**
+ * How long the simulation has been running, in simulated seconds. It only moves when
+ * the simulation does, so that's what schedules are made against.
+ *
+ * It can also be paused or made to run faster than the wall clock, which is handy
+ * when you're waiting for a coal train that takes ages.
+ */
+#[derive(Debug, Clone)]
+pub struct SimClock {
+    pub now:    f64,
+    pub paused: bool,
+    pub speed:  f64,
+    /// Time that has passed on the wall clock, but not yet in the simulation.
+    pub(crate) backlog: f64,
+}
+
+impl SimClock {
+    /**
+     * The speeds you can choose from, in multiples of real time.
+     */
+    pub const SPEEDS: [f64; 4] = [1.0, 2.0, 4.0, 8.0];
+
+    pub fn new() -> Self {
+        Self {
+            now:    0.0,
+            paused: false,
+            speed:  1.0,
+            backlog: 0.0,
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+}
+
+impl Default for SimClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/**
+ * Whenever the simulation needs to roll the dice, it uses this one. It remembers the
+ * seed it was started with, so that whatever happened can be made to happen again.
+ *
+ * It's the same generator that StdRng wraps, but this one can tell how far along it is,
+ * so a saved game carries on rolling exactly the numbers it would have rolled anyway.
+ */
+pub struct SimRng {
+    pub seed: u64,
+    pub rng:  ChaCha20Rng,
+}
+
+impl SimRng {
+    pub fn from_seed(seed: u64) -> Self {
+        let mut rng = ChaCha20Rng::seed_from_u64(seed);
+        // A generator that hasn't drawn anything yet can't tell how far along it is, it
+        // falls over counting backwards from zero. Getting the first numbers ready doesn't
+        // change which ones we'll get.
+        rng.set_word_pos(0);
+        Self {
+            seed,
+            rng,
+        }
+    }
+
+    /** How many numbers were drawn so far, in words of 32 bits. */
+    pub fn position(&self) -> u128 {
+        self.rng.get_word_pos()
+    }
+
+    /** Starts over from the seed and skips ahead to where an earlier run left off. */
+    pub fn resume(seed: u64, position: u128) -> Self {
+        let mut rng = Self::from_seed(seed);
+        rng.rng.set_word_pos(position);
+        rng
+    }
+}
+
+impl Default for SimRng {
+    fn default() -> Self {
+        Self::from_seed(0)
+    }
+}
+
+
+#[derive(Debug,Clone,Serialize,Deserialize)]
+pub enum RoleKind {
+    CoalMine,
+    PowerPlant,
+    WayPoint,
+    DarkSignal,
+    RedSignal,
+    YellowSignal,
+    GreenSignal,
+    Train
+}
+#[derive(Debug)]
+pub struct Role(pub RoleKind);
+
+impl Component for Role {
+    type Storage = VecStorage<Self>;
+}
+
+
+pub struct SignalRenderer;
+
+impl<'a> System<'a> for SignalRenderer {
+    type SystemData = (
+        ReadStorage<'a, signals::JunctionSignal>,
+        WriteStorage<'a, Role>,
+    );
+
+    fn run(&mut self, (signals, mut roles): Self::SystemData) {
+        for (signal, role) in (&signals, &mut roles).join() {
+            role.0 = match signal.signal_state {
+                signals::SignalState::Dark => RoleKind::DarkSignal,
+                signals::SignalState::Halt => RoleKind::RedSignal,
+                signals::SignalState::Slow => RoleKind::YellowSignal,
+                signals::SignalState::Go   => RoleKind::GreenSignal,
+            };
+        }
+    }
+}
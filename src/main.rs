@@ -1,257 +1,288 @@
 extern crate piston_window;
-extern crate gfx_device_gl;
-extern crate image as im;
-extern crate imageproc as imp;
 extern crate specs;
-extern crate rand;
+extern crate niart;
 
 // I need a
 // https://raw.githubusercontent.com/PistonDevelopers/piston-examples/master/src/paint.rs
 // is what I need!
 
-use std::time::SystemTime;
+use std::env;
+use std::path::Path;
+use std::process;
 use specs::prelude::*;
 use piston_window::*;
-use rand::seq::IteratorRandom;
 
-mod physics;
-mod routing;
-mod signals;
-mod cargo;
-mod world;
-mod map;
+use niart::{physics, routing, map, track, train, Simulation, Role, RoleKind};
+use niart::editing::CrossingKind;
+use niart::replay::{Recording, StartingPoint, UserAction};
+use niart::deadlock::DeadlockResolution;
+use niart::signals::SignalKind;
 
-use world::populate;
+mod view;
 
-#[derive(Default)]
-pub struct DeltaTime {
-    pub fraction: f64,
-    last_updated_at: Option<SystemTime>
-}
-
-impl DeltaTime {
-    pub fn new() -> Self {
-        Self {
-            fraction: 0.05,
-            last_updated_at: Some(SystemTime::now())
-        }
-    }
-
-    pub fn update(&mut self) {
-        let now = SystemTime::now();
-        let dura = now.duration_since(self.last_updated_at.unwrap()).unwrap();
-        self.fraction = (dura.as_secs() as f64) + (dura.subsec_micros() as f64 / 1_000_000 as f64);
-        self.last_updated_at = Some(now);
-    }
-}
-
-
-#[derive(Debug)]
-enum RoleKind {
-    CoalMine,
-    PowerPlant,
-    WayPoint,
-    DarkSignal,
-    RedSignal,
-    YellowSignal,
-    GreenSignal,
-    Train
-}
-#[derive(Debug)]
-struct Role(RoleKind);
-
-impl Component for Role {
-    type Storage = VecStorage<Self>;
-}
-
-
-struct SignalRenderer;
+const QUICKSAVE_PATH: &str = "quicksave.ron";
+const RECORDING_PATH: &str = "replay.ron";
 
-impl<'a> System<'a> for SignalRenderer {
-    type SystemData = (
-        ReadStorage<'a, signals::JunctionSignal>,
-        WriteStorage<'a, Role>,
-    );
 
-    fn run(&mut self, (signals, mut roles): Self::SystemData) {
-        for (signal, mut role) in (&signals, &mut roles).join() {
-            role.0 = match signal.signal_state {
-                signals::SignalState::Dark => RoleKind::DarkSignal,
-                signals::SignalState::Halt => RoleKind::RedSignal,
-                signals::SignalState::Slow => RoleKind::YellowSignal,
-                signals::SignalState::Go   => RoleKind::GreenSignal,
-            };
+fn main() {
+    // niart [<scenario>] [--seed <n>] [--replay <recording> [--headless]]
+    let mut scenario = None;
+    let mut seed = None;
+    let mut replay = None;
+    let mut headless = false;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--headless" {
+            headless = true;
+        } else if arg == "--replay" {
+            match args.next() {
+                Some(path) => replay = Some(path),
+                None => {
+                    eprintln!("--replay needs a recording to play");
+                    process::exit(1);
+                }
+            }
+        } else if arg == "--seed" {
+            match args.next().and_then(|word| word.parse::<u64>().ok()) {
+                Some(number) => seed = Some(number),
+                None => {
+                    eprintln!("--seed needs a number");
+                    process::exit(1);
+                }
+            }
+        } else {
+            scenario = Some(arg);
         }
     }
-}
 
+    if headless && replay.is_none() {
+        eprintln!("--headless only works together with --replay");
+        process::exit(1);
+    }
 
+    // Without a scenario file, we'll fall back to our built-in default network.
+    let mut sim =
+        if let Some(path) = replay {
+            let recording = Recording::load(Path::new(&path))
+                .and_then(|recording| Simulation::from_recording(&recording).map(|sim| (recording, sim)));
+            match recording {
+                Ok((recording, sim)) => {
+                    println!("Replaying {} actions from {}", recording.actions.len(), path);
+                    if headless {
+                        run_headless(sim, &recording);
+                        return;
+                    }
+                    sim
+                },
+                Err(err) => {
+                    eprintln!("{}: {}", path, err);
+                    process::exit(1);
+                }
+            }
+        } else if let Some(path) = scenario {
+            match Simulation::from_scenario(Path::new(&path)) {
+                Ok(mut sim) => {
+                    if let Some(seed) = seed {
+                        sim.reseed(seed);
+                    }
+                    sim.start_recording(StartingPoint::Scenario(path.into()));
+                    sim
+                },
+                Err(err) => {
+                    eprintln!("{}: {}", path, err);
+                    process::exit(1);
+                }
+            }
+        } else {
+            let mut sim = Simulation::with_default_network(640, 480);
+            if let Some(seed) = seed {
+                sim.reseed(seed);
+            }
+            sim.start_recording(StartingPoint::DefaultNetwork { width: 640, height: 480 });
+            sim
+        };
+    println!("Rolling the dice with seed {}", sim.world.read_resource::<niart::SimRng>().seed);
 
-fn main() {
-    let mut rng = rand::thread_rng();
-    let (width, height) = (640, 480);
+    let (width, height) = sim.map.size();
     let mut window: PistonWindow =
         WindowSettings::new("niart", (width, height))
         .exit_on_esc(true)
         .build()
         .unwrap();
 
-    let mut map = map::Map::new(&mut window, width, height);
-
-    let mut world = World::new();
-    world.register::<physics::Position>();
-    world.register::<physics::TrainEngine>();
-    world.register::<physics::SpeedLimit>();
-    world.register::<routing::Junction>();
-    world.register::<signals::JunctionSignal>();
-    world.register::<signals::ApproachSignal>();
-    world.register::<signals::TrainIsBlockingSignal>();
-    world.register::<signals::SpeedLimitFromNextSignal>();
-    world.register::<cargo::CargoStorage>();
-    world.register::<cargo::CargoProducer>();
-    world.register::<cargo::CargoConsumer>();
-    world.register::<routing::TrainIsInStation>();
-    world.register::<routing::TrainWantsToTravelTo>();
-    world.register::<routing::TrainRoute>();
-    world.register::<Role>();
-
-    world.add_resource(DeltaTime::new());
-
-    populate(&mut world, &mut map);
-
-    let mut dispatcher = DispatcherBuilder::new()
-        .with(physics::TrainEngineSystem, "TrainEngineSystem", &[])
-        .with(physics::TrainDriver, "TrainDriver", &[])
-        .with(routing::TrainRouter, "TrainRouter", &[])
-        .with(routing::TrainNavigator, "TrainNavigator", &[])
-        .with(signals::Fahrdienstleiter, "Fahrdienstleiter", &[])
-        .with(signals::Fahrdienstputzfrau, "Fahrdienstputzfrau", &[])
-        .with(cargo::CargoProductionSystem, "CargoProductionSystem", &[])
-        .with(cargo::CargoConsumptionSystem, "CargoConsumptionSystem", &[])
-        .with(SignalRenderer, "SignalRenderer", &[])
-        .build();
-    dispatcher.setup(&mut world.res);
 
     let mut mouse_pos = physics::Position::zero();
+    // While bulldozing, left clicks tear stuff down instead of building rails.
+    let mut bulldozing = false;
+    let mut ctrl_pressed = false;
+    // What we build where a new rail runs across an existing one.
+    let mut crossing = CrossingKind::Switch;
+    // What kind of signal a right click puts up.
+    let mut signal_kind = SignalKind::Block;
+    // While putting up approach signals, right clicks on the rails put one there.
+    let mut approaching = false;
 
     while let Some(evt) = window.next() {
         if let Some(button) = evt.press_args() {
+            if button == Button::Keyboard(Key::F5) {
+                match sim.save(Path::new(QUICKSAVE_PATH)) {
+                    Ok(())   => println!("Saved to {}", QUICKSAVE_PATH),
+                    Err(err) => println!("Could not save to {}: {}", QUICKSAVE_PATH, err),
+                }
+            }
+            if button == Button::Keyboard(Key::F6) {
+                match sim.recording() {
+                    Some(recording) => match recording.save(Path::new(RECORDING_PATH)) {
+                        Ok(())   => println!("Saved {} actions to {}", recording.actions.len(), RECORDING_PATH),
+                        Err(err) => println!("Could not save to {}: {}", RECORDING_PATH, err),
+                    },
+                    None => println!("Not recording, nothing to save"),
+                }
+            }
+            if button == Button::Keyboard(Key::F9) {
+                match Simulation::load(Path::new(QUICKSAVE_PATH)) {
+                    Ok(loaded) => {
+                        // A savegame is no starting point a recording knows about, so we stop recording here.
+                        println!("Loaded {}, recording stopped", QUICKSAVE_PATH);
+                        sim = loaded;
+                    },
+                    Err(err) => println!("Could not load {}: {}", QUICKSAVE_PATH, err),
+                }
+            }
+            if button == Button::Keyboard(Key::LCtrl) || button == Button::Keyboard(Key::RCtrl) {
+                ctrl_pressed = true;
+            }
+            if ctrl_pressed && button == Button::Keyboard(Key::Z) && !sim.perform(UserAction::Undo) {
+                println!("Nothing to undo");
+            }
+            if ctrl_pressed && button == Button::Keyboard(Key::Y) && !sim.perform(UserAction::Redo) {
+                println!("Nothing to redo");
+            }
+            if button == Button::Keyboard(Key::B) {
+                bulldozing = !bulldozing;
+                println!("Bulldozer {}", if bulldozing { "on" } else { "off" });
+            }
+            if button == Button::Keyboard(Key::X) {
+                crossing = match crossing {
+                    CrossingKind::Switch  => CrossingKind::Diamond,
+                    CrossingKind::Diamond => CrossingKind::Switch,
+                };
+                println!("Rails that cross each other now get a {:?} crossing", crossing);
+            }
+            if button == Button::Keyboard(Key::S) {
+                signal_kind = match signal_kind {
+                    SignalKind::Block => SignalKind::Path,
+                    SignalKind::Path  => SignalKind::Block,
+                };
+                println!("Now putting up {:?} signals", signal_kind);
+            }
+            if button == Button::Keyboard(Key::A) {
+                approaching = !approaching;
+                println!("Putting up approach signals by hand {}", if approaching { "on" } else { "off" });
+            }
+            if button == Button::Keyboard(Key::K) {
+                // This changes how things play out, so it goes into the recording as well.
+                let resolution = match sim.deadlock_resolution() {
+                    DeadlockResolution::Report   => DeadlockResolution::RollBack,
+                    DeadlockResolution::RollBack => DeadlockResolution::Reroute,
+                    DeadlockResolution::Reroute  => DeadlockResolution::Report,
+                };
+                sim.perform(UserAction::SetDeadlockResolution { resolution });
+                println!("Deadlocks now get resolved by {:?}", resolution);
+            }
+            if button == Button::Keyboard(Key::Space) {
+                let paused = sim.toggle_pause();
+                println!("{}", if paused { "Paused" } else { "Running" });
+            }
+            if button == Button::Keyboard(Key::Period) && sim.clock().paused {
+                sim.single_step();
+            }
+            let speed_keys = [Key::D1, Key::D2, Key::D3, Key::D4];
+            if let Some(idx) = speed_keys.iter().position(|&key| button == Button::Keyboard(key)) {
+                let speed = niart::SimClock::SPEEDS[idx];
+                sim.set_speed(speed);
+                println!("Running at {}x", speed);
+            }
+            if button == Button::Keyboard(Key::P) {
+                let punctuality = sim.punctuality();
+                for (train, stats) in punctuality.by_train() {
+                    println!("Train {:?}: {:?}", train, stats);
+                }
+                for (station, stats) in punctuality.by_station() {
+                    println!("Station {:?}: {:?}", station, stats);
+                }
+                let overall = punctuality.overall();
+                println!("{} of {} stops on time ({:.0}%)", overall.on_time, overall.stops, overall.punctuality() * 100.0);
+                let crashes = sim.crashes();
+                println!(
+                    "{} crashes with {} trains involved, {} wrecks still lying around, rails blocked for {:.0}s",
+                    crashes.log.len(),
+                    crashes.trains_involved(),
+                    crashes.active().count(),
+                    crashes.downtime(sim.clock().now)
+                );
+                for deadlock in sim.deadlocks().current() {
+                    println!("Trains {:?} are deadlocked at {:?} since {:.0}s", deadlock.trains, deadlock.signals, deadlock.since);
+                }
+                println!("{} deadlocks so far", sim.deadlocks().log.len());
+            }
             if button == Button::Mouse(MouseButton::Left) {
-                map.start_drawing();
+                if bulldozing {
+                    if !sim.perform(UserAction::Bulldoze { at: mouse_pos.clone() }) {
+                        println!("Nothing to bulldoze at {:?}", mouse_pos);
+                    }
+                } else {
+                    sim.map.start_drawing();
+                }
             }
-            if button == Button::Mouse(MouseButton::Right) {
-                let entities = world.entities();
-                let positions = world.read_storage::<physics::Position>();
-                let junctions = world.read_storage::<routing::Junction>();
-                let mut signals = world.write_storage::<signals::JunctionSignal>();
-                let lazyupdt = world.read_resource::<LazyUpdate>();
-                for (junction, junction_pos, junction_j) in (&entities, &positions, &junctions).join() {
-                    if mouse_pos.distance_length_to(junction_pos) < 10.0 {
-                        if junction_j.is_terminal {
-                            // Choose any random terminal and leave it to the Router to figure out if this works
-                            let dest = (&entities, &junctions).join()
-                                .filter(|(e, _j)| *e != junction)
-                                .filter(|(_e, j)| j.is_terminal)
-                                .map(|(e, _j)| e)
-                                .choose(&mut rng);
-                            if let Some(destination) = dest {
-                                println!("Planting train at junction {:?} heading towards {:?}", junction, destination);
-                                lazyupdt.create_entity(&entities)
-                                    .with(junction_pos.clone())
-                                    .with(Role(RoleKind::Train))
-                                    .with(routing::TrainIsInStation { station: junction })
-                                    .with(routing::TrainWantsToTravelTo { destination: destination })
-                                    .with(physics::TrainEngine {
-                                        velocity: physics::Vector { x: 0., y: 0. },
-                                        acceleration: physics::Vector { x: 0., y: 0. },
-                                        vmax: 30.0,
-                                        amax: 5.0
-                                    })
-                                    .build();
-                            } else {
-                                println!("Planting train at junction {:?} is not possible, junction does not have connections", junction);
-                            }
-                        } else {
-                            println!("We should make a signal at {:?}", junction);
-                            signals
-                                .insert(junction, signals::JunctionSignal::new())
-                                .expect("Sad signalling panda");
+            if button == Button::Mouse(MouseButton::Right) && approaching {
+                if !sim.perform(UserAction::PlaceApproachSignal { at: mouse_pos.clone() }) {
+                    println!("No signal down the line from {:?} to put up an approach signal for", mouse_pos);
+                }
+            } else if button == Button::Mouse(MouseButton::Right) {
+                if let Some(junction) = sim.junction_at(&mouse_pos, 10.0) {
+                    let is_terminal = sim.world.read_storage::<routing::Junction>()
+                        .get(junction)
+                        .is_some_and(|j| j.is_terminal);
+                    if is_terminal {
+                        if sim.plant_train(junction).is_none() {
+                            println!("Planting train at junction {:?} is not possible, junction does not have connections", junction);
                         }
+                    } else {
+                        let junction_pos = sim.world.read_storage::<physics::Position>().get(junction).unwrap().clone();
+                        sim.perform(UserAction::PlaceSignal { junction: junction_pos, kind: signal_kind });
                     }
                 }
             }
         }
         if let Some(button) = evt.release_args() {
+            if button == Button::Keyboard(Key::LCtrl) || button == Button::Keyboard(Key::RCtrl) {
+                ctrl_pressed = false;
+            }
             if button == Button::Mouse(MouseButton::Left) {
-                map.stop_drawing();
+                sim.map.stop_drawing();
             }
         }
         if let Some(pos) = evt.mouse_cursor_args() {
             mouse_pos = physics::Position::from(pos);
-            map.mouse_moved(pos);
+            sim.map.mouse_moved(pos, &sim.world.read_resource::<track::Tracks>());
         }
 
-        if let Some(map_event) = map.next_event() {
-            if let map::MapEvent::NewRail(from, to) = map_event {
-                println!("New rail created! Goes los from {:?} to {:?}", from, to);
-                let mut start = None;
-                let mut end = None;
-                {
-                    let entities = world.entities();
-                    let positions = world.read_storage::<physics::Position>();
-                    let junctions = world.read_storage::<routing::Junction>();
-                    for (ent, pos, junction) in (&entities, &positions, &junctions).join() {
-                        if from.distance_length_to(pos) < 4.0 {
-                            println!("Start is near a junction {:?}! {:?}", ent, junction);
-                            start = Some(ent);
-                        }
-                        if to.distance_length_to(pos) < 4.0 {
-                            println!("End is near a junction {:?}! {:?}", ent, junction);
-                            end = Some(ent);
-                        }
-                    }
-                }
-                if start.is_none() {
-                    start = Some(
-                        world.create_entity()
-                            .with(from)
-                            .with(Role(RoleKind::WayPoint))
-                            .with(routing::Junction::new())
-                            .build()
-                    );
-                }
-                if end.is_none() {
-                    end = Some(
-                        world.create_entity()
-                            .with(to)
-                            .with(Role(RoleKind::WayPoint))
-                            .with(routing::Junction::new())
-                            .build()
-                    );
-                }
-                let start = start.unwrap();
-                let end = end.unwrap();
-                world.write_storage::<routing::Junction>()
-                    .get_mut(start).unwrap()
-                    .connections.push(end);
-                world.write_storage::<routing::Junction>()
-                    .get_mut(end).unwrap()
-                    .connections.push(start);
-            }
+        if let Some(map::MapEvent::NewRail(from, to)) = sim.map.next_event() {
+            println!("New rail created! Goes los from {:?} to {:?}", from, to);
+            sim.perform(UserAction::AddRail { from, to, crossing });
         }
 
-        if let Some(_) = evt.update_args() {
-            world.write_resource::<DeltaTime>().update();
-            dispatcher.dispatch(&mut world.res);
-            world.maintain();
+        if evt.update_args().is_some() {
+            sim.update();
         }
 
-        window.draw_2d(&evt, |c, g, device| {
+        window.draw_2d(&evt, |c, g, _device| {
             clear([1.0; 4], g);
-            map.render(c, g, device);
+            view::render_map(&sim.map, &sim.world.read_resource::<track::Tracks>(), c, g);
 
-            let positions = world.read_storage::<physics::Position>();
-            let roles = world.read_storage::<Role>();
+            let positions = sim.world.read_storage::<physics::Position>();
+            let roles = sim.world.read_storage::<Role>();
             for (pos, role) in (&positions, &roles).join() {
                 ellipse_from_to(
                     match role {
@@ -270,6 +301,44 @@ fn main() {
                     g
                 );
             }
+            view::render_approach_signals(
+                &sim.world.read_storage::<niart::signals::ApproachSignal>(),
+                &positions,
+                c,
+                g
+            );
+            view::render_path_signals(
+                &sim.world.read_storage::<niart::signals::JunctionSignal>(),
+                &positions,
+                c,
+                g
+            );
+            view::render_cars(
+                &sim.world.read_storage::<train::CarOutline>(),
+                &sim.world.read_storage::<train::Wagon>(),
+                c,
+                g
+            );
+            view::render_crashes(&sim.world.read_resource::<niart::collision::Crashes>(), c, g);
         });
     }
 }
+
+/**
+ * Play a recording back as fast as we can without ever opening a window, then tell
+ * what came of it.
+ */
+fn run_headless(mut sim: Simulation, recording: &Recording) {
+    while sim.is_replaying() {
+        sim.single_step();
+    }
+    let trains = sim.world.read_storage::<Role>()
+        .join()
+        .filter(|role| matches!(role, Role(RoleKind::Train)))
+        .count();
+    let overall = sim.punctuality().overall();
+    println!("Replayed {} actions over {:.2}s", recording.actions.len(), sim.clock().now);
+    println!("{} trains on the map, {} of {} stops on time", trains, overall.on_time, overall.stops);
+    println!("{} crashes with {} trains involved", sim.crashes().log.len(), sim.crashes().trains_involved());
+    println!("{} deadlocks, {} still going on", sim.deadlocks().log.len(), sim.deadlocks().current().count());
+}
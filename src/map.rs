@@ -1,10 +1,7 @@
-use piston_window::*;
-use gfx_device_gl::Device;
-use im::{ImageBuffer,Rgba};
-use imp::drawing::draw_line_segment_mut;
 use std::collections::VecDeque;
 
 use super::physics::Position;
+use super::track::Tracks;
 
 pub enum MapEvent {
     NewRail(Position, Position),
@@ -22,48 +19,40 @@ enum State {
     DrawingFrom(Position)
 }
 
+/**
+ * The Map is where the user draws rails. It keeps track of the mouse and turns drags
+ * into MapEvents; the rails themselves live in the Tracks resource. It does not know
+ * anything about windows or textures, so that it can be used without a display.
+ */
 pub struct Map {
-    canvas:          ImageBuffer<Rgba<u8>,Vec<u8>>,
+    width:           u32,
+    height:          u32,
     state:           State,
-    texture_context: G2dTextureContext,
-    texture:         G2dTexture,
     mouse_pos:       Position,
-    events:          VecDeque<MapEvent>
+    events:          VecDeque<MapEvent>,
 }
 
 impl Map {
-    pub fn new(window: &mut PistonWindow, width: u32, height: u32) -> Self {
-        let canvas = ImageBuffer::new(width, height);
-        let mut texture_context = window.create_texture_context();
-        let texture: G2dTexture = Texture::from_image(
-            &mut texture_context,
-            &canvas,
-            &TextureSettings::new()
-        ).unwrap();
+    pub fn new(width: u32, height: u32) -> Self {
         Self {
-            canvas: canvas,
-            state:  State::NotDrawing,
-            texture_context: texture_context,
-            texture:   texture,
+            width,
+            height,
+            state:     State::NotDrawing,
             mouse_pos: Position::zero(),
             events:    VecDeque::new(),
         }
     }
 
-    pub fn render(&mut self, c: Context, g: &mut G2d, device: &mut Device) {
-        // Update texture before rendering.
-        self.texture.update(&mut self.texture_context, &mut self.canvas).unwrap();
-        self.texture_context.encoder.flush(device);
-        image(&self.texture, c.transform, g);
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    // The rail the user is currently drawing, if any.
+    pub fn drawing_line(&self) -> Option<(Position, Position)> {
         if let State::DrawingFrom(ref pos) = self.state {
-            line_from_to(
-                [0., 0., 0., 1.],
-                2.0,
-                pos.as_f64_array(),
-                self.mouse_pos.as_f64_array(),
-                c.transform,
-                g
-            );
+            Some((pos.clone(), self.mouse_pos.clone()))
+        } else {
+            None
         }
     }
 
@@ -73,7 +62,7 @@ impl Map {
 
     pub fn stop_drawing(&mut self) {
         if let State::DrawingFrom(start_pos) = self.state.clone() {
-            self.draw_rails_at(&start_pos, &self.mouse_pos.clone());
+            // The rail will be laid once the Simulation has added it.
             self.events.push_back(
                 MapEvent::NewRail(start_pos.clone(), self.mouse_pos.clone())
             );
@@ -81,55 +70,15 @@ impl Map {
         self.state = State::NotDrawing;
     }
 
-    pub fn mouse_moved(&mut self, pos: [f64; 2]) {
+    pub fn mouse_moved(&mut self, pos: [f64; 2], tracks: &Tracks) {
         self.mouse_pos = Position::from(pos);
-        if let Some(better_pos) = self.find_rails_at(&self.mouse_pos) {
-            self.mouse_pos = better_pos;
+        // If we're close to some rails, snap onto them.
+        if let Some(hit) = tracks.hit_test(&self.mouse_pos, 5.0) {
+            self.mouse_pos = hit.point;
         }
     }
 
     pub fn next_event(&mut self) -> Option<MapEvent> {
         self.events.pop_front()
     }
-
-    pub fn draw_rails_at(&mut self, start: &Position, end: &Position) {
-        draw_line_segment_mut(
-            &mut self.canvas,
-            start.as_f32_tuple(),
-            end.as_f32_tuple(),
-            Rgba([0, 0, 0, 255])
-        );
-    }
-
-    fn find_rails_at(&self, start: &Position) -> Option<Position> {
-        let sx = start.x as u32;
-        let sy = start.y as u32;
-        let mut min_dst = None; // Minimal distance to our mouse cursor
-        let mut min_pos = None; // Position at which we observed min_dst
-        for check_x in (sx - 5)..(sx + 5) {
-            for check_y in (sy - 5)..(sy + 5) {
-                // Make sure the pixel actually exists
-                if check_x >= self.canvas.width() ||
-                   check_y >= self.canvas.height() {
-                    continue;
-                }
-                let px = self.canvas.get_pixel(check_x, check_y);
-                if px[3] == 0xFF {
-                    // Pixel is a track
-                    let here = Position::new(check_x as f64, check_y as f64);
-                    if min_dst.is_none() {
-                        min_dst = Some(start.distance_length_to(&here));
-                        min_pos = Some(here);
-                    } else {
-                        let check_dst = start.distance_length_to(&here);
-                        if check_dst < min_dst.unwrap() {
-                            min_dst = Some(check_dst);
-                            min_pos = Some(here);
-                        }
-                    }
-                }
-            }
-        }
-        min_pos
-    }
 }
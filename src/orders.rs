@@ -0,0 +1,226 @@
+use specs::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use super::routing::{TrainIsInStation, TrainWantsToTravelTo};
+use super::train::{Consist, Wagon};
+use super::cargo::{CargoStorage, TrainIsDwelling};
+use super::timetable::{Punctuality, TimetableEntry, StopEvent};
+use super::SimClock;
+
+/**
+ * What a train waits for once it has reached a stop, before it sets off to the next one.
+ * Loading and unloading always happen, this only says what else needs to be true.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum WaitFor {
+    /// Leave as soon as the cargo has been handled.
+    Nothing,
+    /// Stay until every wagon is full to the brim.
+    FullLoad,
+    /// Stay for this many seconds.
+    Timeout(f64),
+    /// Leave this many seconds after the schedule started over.
+    DepartureTime(f64),
+}
+
+#[derive(Debug, Clone)]
+pub struct Order {
+    pub destination: Entity,
+    pub wait_for:    WaitFor,
+    /// When the timetable says we should be there, in seconds after the schedule started
+    /// over. When we're supposed to leave is what WaitFor::DepartureTime is for.
+    pub arrival:     Option<f64>,
+}
+
+impl Order {
+    /**
+     * Go somewhere and leave once the cargo is handled, whenever that is.
+     */
+    pub fn to(destination: Entity) -> Self {
+        Self {
+            destination,
+            wait_for:    WaitFor::Nothing,
+            arrival:     None,
+        }
+    }
+}
+
+/**
+ * The list of stops a train works through, one after the other. When it gets to the end,
+ * it either starts over, or it's done and stays where it is.
+ */
+#[derive(Debug, Clone)]
+pub struct TrainOrders {
+    pub orders:  Vec<Order>,
+    pub current: usize,
+    pub repeat:  bool,
+    /// If we're running on a timetable, how long one round is supposed to take. Each
+    /// round then starts on schedule, no matter how late the last one was.
+    pub period:  Option<f64>,
+    /// When we started on the first order, so that departure times have something to go by.
+    pub round_started_at: f64,
+    /// When we got to the stop we're currently at, if we're at one.
+    pub waiting_since: Option<f64>,
+}
+impl Component for TrainOrders {
+    type Storage = HashMapStorage<Self>;
+}
+
+impl TrainOrders {
+    pub fn repeating(orders: Vec<Order>, now: f64) -> Self {
+        Self {
+            orders,
+            current: 0,
+            repeat:  true,
+            period:  None,
+            round_started_at: now,
+            waiting_since:    None,
+        }
+    }
+
+    pub fn current_order(&self) -> Option<&Order> {
+        self.orders.get(self.current)
+    }
+
+    /**
+     * Go on to the next order. Returns false if there is none, because we're at the end
+     * and we're not supposed to start over.
+     */
+    fn advance(&mut self, now: f64) -> bool {
+        self.waiting_since = None;
+        self.current += 1;
+        if self.current < self.orders.len() {
+            return true;
+        }
+        if !self.repeat || self.orders.is_empty() {
+            return false;
+        }
+        self.current = 0;
+        self.round_started_at = match self.period {
+            Some(period) => self.round_started_at + period,
+            None => now,
+        };
+        true
+    }
+}
+
+/**
+ * The Conductor keeps an eye on trains that are standing in a station with nowhere to
+ * go. If they have orders, he (or she) checks whether they're done waiting, and then
+ * tells them where to go next. The Router takes it from there.
+ * Whenever a train is supposed to arrive or leave by the timetable, the Conductor writes
+ * down when it actually did.
+ */
+pub struct Conductor;
+
+impl<'a> System<'a> for Conductor {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, TrainOrders>,
+        ReadStorage<'a, TrainIsInStation>,
+        WriteStorage<'a, TrainWantsToTravelTo>,
+        ReadStorage<'a, TrainIsDwelling>,
+        ReadStorage<'a, Consist>,
+        ReadStorage<'a, Wagon>,
+        ReadStorage<'a, CargoStorage>,
+        Read<'a, SimClock>,
+        Write<'a, Punctuality>,
+    );
+
+    fn run(&mut self, sys_data: Self::SystemData) {
+        let (
+            entities,
+            mut orders,
+            trains_in_station,
+            mut trains_that_want_to_travel,
+            dwelling,
+            consists,
+            wagons,
+            storages,
+            clock,
+            mut punctuality,
+        ) = sys_data;
+        let now = clock.now;
+        let mut done_trains = vec![];
+        let mut departures = vec![];
+        for (train, train_orders, station, _) in (&entities, &mut orders, &trains_in_station, !&trains_that_want_to_travel).join() {
+            let order = match train_orders.current_order() {
+                Some(order) => order.clone(),
+                None => {
+                    done_trains.push(train);
+                    continue;
+                }
+            };
+            let is_at_stop = order.destination == station.station;
+            if is_at_stop && train_orders.waiting_since.is_none() {
+                train_orders.waiting_since = Some(now);
+                if let Some(arrival) = order.arrival {
+                    punctuality.record(TimetableEntry {
+                        train,
+                        station:   station.station,
+                        event:     StopEvent::Arrival,
+                        scheduled: train_orders.round_started_at + arrival,
+                        actual:    now,
+                    });
+                }
+            }
+            // Still busy with the cargo? Then there's nothing to decide yet.
+            if dwelling.get(train).is_some_and(|dwell| !dwell.is_done()) {
+                continue;
+            }
+            if is_at_stop {
+                let waiting_since = train_orders.waiting_since.unwrap();
+                let done_waiting = match order.wait_for {
+                    WaitFor::Nothing => true,
+                    WaitFor::FullLoad => consists.get(train).is_none_or(|consist| {
+                        consist.wagons.iter().all(|&wagon| {
+                            wagons.get(wagon).is_none_or(|w| {
+                                let load = storages.get(wagon)
+                                    .and_then(|storage| storage.quantities.get(&w.cargo).cloned())
+                                    .unwrap_or(0.0);
+                                load >= w.capacity
+                            })
+                        })
+                    }),
+                    WaitFor::Timeout(seconds) => now - waiting_since >= seconds,
+                    WaitFor::DepartureTime(at) => now - train_orders.round_started_at >= at,
+                };
+                if !done_waiting {
+                    continue;
+                }
+                if let WaitFor::DepartureTime(at) = order.wait_for {
+                    punctuality.record(TimetableEntry {
+                        train,
+                        station:   station.station,
+                        event:     StopEvent::Departure,
+                        scheduled: train_orders.round_started_at + at,
+                        actual:    now,
+                    });
+                }
+                if !train_orders.advance(now) {
+                    done_trains.push(train);
+                    continue;
+                }
+            }
+            // Off to wherever we're supposed to be next, unless someone bulldozed it.
+            let destination = train_orders.current_order().unwrap().destination;
+            if !entities.is_alive(destination) {
+                println!("Train {:?} was ordered to a station that doesn't exist anymore", train);
+                done_trains.push(train);
+                continue;
+            }
+            if destination != station.station {
+                departures.push((train, destination));
+            }
+        }
+        for (train, destination) in departures {
+            trains_that_want_to_travel
+                .insert(train, TrainWantsToTravelTo { destination })
+                .expect("train doesn't want to go anywhere");
+        }
+        for train in done_trains {
+            println!("Train {:?} has carried out all its orders", train);
+            orders.remove(train);
+        }
+    }
+}
@@ -1,9 +1,23 @@
+use std::str::FromStr;
 use specs::prelude::*;
+use serde::{Serialize, Deserialize};
 
 use super::routing::{TrainRoute,TrainIsInStation};
-use super::signals::{JunctionSignal, SpeedLimitFromNextSignal};
-
-#[derive(Clone,Debug,PartialEq)]
+use super::signals::{
+    JunctionSignal,
+    ApproachSignal,
+    ApproachAspect,
+    TrainExpectsSignal,
+    SignalIsReservedByTrain,
+    SpeedLimitFromNextSignal,
+};
+use super::approach::SIGHTING_DISTANCE;
+use super::track::{Tracks, TrackPosition};
+use super::train::{Consist, Wagon, ENGINE_MASS};
+use super::cargo::CargoStorage;
+use super::collision::Crashes;
+
+#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
 pub struct Vector {
     pub x: f64,
     pub y: f64,
@@ -27,7 +41,7 @@ impl Vector {
     }
 }
 
-#[derive(Clone,Debug,PartialEq)]
+#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
 pub struct Position {
     pub x: f64,
     pub y: f64
@@ -39,7 +53,7 @@ impl Position {
     }
 
     pub fn new(x: f64, y: f64) -> Self {
-        Self { x: x, y: y }
+        Self { x, y }
     }
 
     pub fn distance_to(&self, other: &Position) -> Vector {
@@ -97,6 +111,7 @@ impl Component for Position {
 }
 
 
+#[derive(Debug,Clone,Serialize,Deserialize)]
 pub struct SpeedLimit {
     pub vmax: f64
 }
@@ -104,36 +119,120 @@ impl Component for SpeedLimit {
     type Storage = HashMapStorage<Self>;
 }
 
-#[derive(Debug)]
+/**
+ * What holds a train back while it's rolling along, Davis style: a + b*v + c*v². The first
+ * two come from the wheels and bearings, so they grow with how heavy the train is, and are
+ * given per unit of mass. The last one is the air, which doesn't care what's in the wagons.
+ */
+#[derive(Debug,Clone,Serialize,Deserialize)]
+pub struct RunningResistance {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+}
+
+impl Default for RunningResistance {
+    fn default() -> Self {
+        Self { a: 0.02, b: 0.001, c: 0.05 }
+    }
+}
+
+impl RunningResistance {
+    /**
+     * The force that a train of the given mass has to fight at the given speed.
+     */
+    pub fn force(&self, speed: f64, mass: f64) -> f64 {
+        (self.a + self.b * speed) * mass + self.c * speed * speed
+    }
+}
+
+/**
+ * Trains can only ever go forwards along the rails, so all there is to know about how
+ * fast they're going is a number. Acceleration is negative when braking.
+ * How fast a train gets going depends on how hard the engine can pull and how heavy the
+ * train is. Brakes are on every wagon though, so braking works the same for any load.
+ */
+#[derive(Debug,Clone,Serialize,Deserialize)]
 pub struct TrainEngine {
-    pub velocity:     Vector,
-    pub acceleration: Vector,
-    pub vmax: f64,
-    pub amax: f64,
+    pub speed:           f64,
+    pub acceleration:    f64,
+    pub vmax:            f64,
+    pub tractive_effort: f64,
+    pub braking:         f64,
+    pub resistance:      RunningResistance,
 }
 
 impl Component for TrainEngine {
     type Storage = HashMapStorage<Self>;
 }
 
+/**
+ * The kinds of engines we know how to build. Freight engines are what everybody gets if
+ * nobody says otherwise. Heavy ones pull harder but take their time, both going and
+ * stopping. Passenger engines are fast and have good brakes.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum EngineKind {
+    #[default]
+    Freight,
+    HeavyFreight,
+    Passenger,
+}
+
+impl FromStr for EngineKind {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "freight"   => Ok(EngineKind::Freight),
+            "heavy"     => Ok(EngineKind::HeavyFreight),
+            "passenger" => Ok(EngineKind::Passenger),
+            _           => Err(()),
+        }
+    }
+}
+
+impl TrainEngine {
+    pub fn of_kind(kind: EngineKind) -> Self {
+        let (vmax, tractive_effort, braking) = match kind {
+            EngineKind::Freight      => (30.0, 400.0, 4.0),
+            EngineKind::HeavyFreight => (25.0, 600.0, 2.5),
+            EngineKind::Passenger    => (45.0, 350.0, 7.0),
+        };
+        Self {
+            speed:           0.0,
+            acceleration:    0.0,
+            vmax,
+            tractive_effort,
+            braking,
+            resistance:      RunningResistance::default(),
+        }
+    }
+}
+
 pub struct TrainEngineSystem;
 
 impl<'a> System<'a> for TrainEngineSystem {
     type SystemData = (
         WriteStorage<'a, Position>,
+        WriteStorage<'a, TrackPosition>,
         WriteStorage<'a, TrainEngine>,
+        ReadStorage<'a, TrainRoute>,
+        Read<'a, Tracks>,
         Read<'a, super::DeltaTime>,
     );
 
-    fn run(&mut self, (mut positions, mut engines, delta): Self::SystemData) {
-        for (position, engine) in (&mut positions, &mut engines).join() {
-            engine.velocity.x += engine.acceleration.x * delta.fraction;
-            engine.velocity.y += engine.acceleration.y * delta.fraction;
-
-            // TODO: Being a machine that runs on rails, I should probably make sure I'm actually on the tracks
+    fn run(&mut self, (mut positions, mut track_positions, mut engines, routes, tracks, delta): Self::SystemData) {
+        for (position, track_pos, engine, route) in (&mut positions, &mut track_positions, &mut engines, &routes).join() {
+            engine.speed = (engine.speed + engine.acceleration * delta.fraction).max(0.0);
 
-            position.x += engine.velocity.x * delta.fraction;
-            position.y += engine.velocity.y * delta.fraction;
+            // Being a machine that runs on rails, I can only go where they lead: Towards the
+            // next hop, and not an inch further. The Navigator takes it from there.
+            if track_pos.advance_towards(route.next_hop(), engine.speed * delta.fraction, &tracks) {
+                if let Some(pos) = track_pos.world_position(&tracks) {
+                    *position = pos;
+                }
+            }
         }
     }
 }
@@ -147,37 +246,87 @@ pub struct TrainDriver;
 impl<'a> System<'a> for TrainDriver {
     type SystemData = (
         Entities<'a>,                      // I'm a guy
-        ReadStorage<'a, Position>,         // I'm somewhere
+        ReadStorage<'a, TrackPosition>,    // I'm somewhere on the rails
         WriteStorage<'a, TrainEngine>,     // I haz an engine that I can play with
         ReadStorage<'a, TrainRoute>,       // I wanna go somewhere
         ReadStorage<'a, TrainIsInStation>, // or I'm in a station
         ReadStorage<'a, JunctionSignal>,   // and I may be looking at a signal
+        ReadStorage<'a, ApproachSignal>,   // or at one that tells me about it
+        WriteStorage<'a, TrainExpectsSignal>, // which I'd better remember
+        ReadStorage<'a, SignalIsReservedByTrain>, // that may or may not be talking to me
         ReadStorage<'a, SpeedLimit>,
         ReadStorage<'a, SpeedLimitFromNextSignal>,
+        Read<'a, Tracks>,
+        ReadStorage<'a, Consist>,          // and I'm pulling a bunch of wagons
+        ReadStorage<'a, Wagon>,
+        ReadStorage<'a, CargoStorage>,     // that may be full of stuff
+        Read<'a, Crashes>,                 // and I'd rather not end up like those guys
+        Read<'a, super::DeltaTime>,        // and I know how long until I look again
     );
 
     fn run(&mut self, sys_data: Self::SystemData) {
         let (
             entities,
-            positions,
+            track_positions,
             mut engines,
             routes,
             trains_in_station,
             junction_signals,
+            approach_signals,
+            mut expectations,
+            reservations,
             speed_limits_current,
             speed_limits_upcoming,
+            tracks,
+            consists,
+            wagons,
+            storages,
+            crashes,
+            delta,
         ) = sys_data;
         // Open Road
-        for (train, train_pos, mut engine, route) in (&entities, &positions, &mut engines, &routes).join() {
-            let next_pos = positions.get(route.next_hop()).unwrap();
-            //println!("I'm in ur {:?}, going to {:?}", train_pos, next_pos);
-            let direction = next_pos.distance_to(train_pos);
-
+        for (train, track_pos, engine, route) in (&entities, &track_positions, &mut engines, &routes).join() {
             // How far away is the next hop?
-            let distance = direction.length();
+            let distance = match track_pos.distance_to(route.next_hop(), &tracks) {
+                Some(distance) => distance,
+                None => {
+                    // Wherever we're going, these rails don't lead there. Better stop.
+                    engine.acceleration = -engine.braking;
+                    continue;
+                }
+            };
+
+            // The next signal may be a few hops down the line. Whatever comes after our
+            // destination is none of our business though.
+            let until_dest = route.hops.iter().position(|&hop| hop == route.dest).unwrap_or(route.hops.len());
+            let next_signal = route.hops.range(..until_dest).position(|&hop| junction_signals.contains(hop)).map(|idx| {
+                let further = route.hops.iter().zip(route.hops.iter().skip(1))
+                    .take(idx)
+                    .filter_map(|(&here, &next)| tracks.segment_between(here, next).and_then(|id| tracks.get(id)))
+                    .map(|segment| segment.length())
+                    .sum::<f64>();
+                (route.hops[idx], distance + further)
+            });
+            // Whatever we heard about some other signal doesn't matter anymore.
+            if expectations.get(train).is_some_and(|expect| Some(expect.signal) != next_signal.map(|(signal, _)| signal)) {
+                expectations.remove(train);
+            }
+            // If we're passing an approach signal for the next one, we take note of what it says.
+            if let Some((signal, _)) = next_signal {
+                let passing = junction_signals.get(signal).unwrap().appr_signals.iter()
+                    .filter_map(|&approach| approach_signals.get(approach))
+                    .find(|approach| approach.is_passed_by(route, track_pos, &tracks));
+                if let Some(approach) = passing {
+                    expectations
+                        .insert(train, TrainExpectsSignal { signal, aspect: approach.aspect })
+                        .expect("driver looked the other way");
+                }
+            }
 
-            // Make sure we're going in the right direction.
-            engine.velocity = direction.scale_to_length(engine.velocity.length());
+            // The heavier the train, the harder it is to get going, and the more the rails
+            // hold it back.
+            let mass = consists.get(train).map_or(ENGINE_MASS, |consist| consist.mass(&wagons, &storages));
+            let resisting = engine.resistance.force(engine.speed, mass);
 
             // What speed should we be going?
             let v_target =
@@ -188,70 +337,93 @@ impl<'a> System<'a> for TrainDriver {
                     engine.vmax
                 };
 
+            // Whatever we do, we do it until we look again, so we'd better not overdo it and end
+            // up on the other side of v_target.
+            let towards_target = |acceleration: f64| -> f64 {
+                if delta.fraction <= 0.0 {
+                    return acceleration;
+                }
+                let to_target = (v_target - engine.speed) / delta.fraction;
+                if acceleration > 0.0 { acceleration.min(to_target) } else { acceleration.max(to_target) }
+            };
+
             // if we're doing more than that already, no need to bother with anything else -> brake
-            if engine.velocity.length() > v_target {
-                engine.acceleration = direction.scale_to_length(-engine.amax);
+            if engine.speed > v_target {
+                engine.acceleration = towards_target(-engine.braking - resisting / mass);
                 continue;
             }
 
             // Let's see if going vmax is safe. It is if we can stop in time for the next signal.
-            // Find out if that means braking to zero, or if we just need to slow down.
-            let v_upcoming =
-                if junction_signals
-                    .get(route.next_hop())
-                    .map_or(false, |sig| sig.is_halt()) {
-                    // First, we look at the signal. If signal says stop, we stop.
-                    0.0
-                } else if let Some(limit) = speed_limits_upcoming.get(train) {
-                    // Then we ask the Fahrdienstleiter.
-                    limit.vmax
-                } else {
-                    // If all else fails, yolo
-                    engine.vmax
-                };
+            // Find out if that means braking to zero, or if we just need to slow down, and
+            // how far away that is.
+            let v_further = speed_limits_upcoming.get(train).map_or(engine.vmax, |limit| limit.vmax);
+            let mut slow_downs = vec![];
+            match next_signal {
+                Some((signal, signal_distance)) => {
+                    let may_go =
+                        if signal_distance < SIGHTING_DISTANCE {
+                            // We're close enough to see the signal for ourselves. A signal stands
+                            // at a junction where several lines meet, so if it says go but was
+                            // reserved by someone else, it's talking to them and not to us.
+                            !junction_signals.get(signal).unwrap().is_halt() &&
+                                reservations.get(signal).is_some_and(|rsvp| rsvp.train == train)
+                        } else {
+                            // Otherwise, all we have to go by is the approach signal we passed. If
+                            // there wasn't one, we'd better be ready to stop.
+                            expectations.get(train).is_some_and(|expect| {
+                                matches!(expect.aspect, ApproachAspect::ExpectGo | ApproachAspect::ExpectSlow)
+                            })
+                        };
+                    // If it lets us go, the Fahrdienstleiter may still want us to slow down.
+                    slow_downs.push((if may_go { v_further } else { 0.0 }, signal_distance));
+                },
+                // No signals ahead, if all else fails, yolo
+                None => slow_downs.push((v_further, distance)),
+            }
+            if route.hops.get(1).is_some_and(|&after| crashes.is_blocked(route.next_hop(), after)) {
+                // There's a wreck on the rails behind the next hop. Not going there.
+                slow_downs.push((0.0, distance));
+            }
 
-            // If we're going that speed or below, no need to worry.
+            // If we're going that speed or below, no need to worry, unless we have to stop.
             // Otherwise, we need to act.
-            if engine.velocity.length() > v_upcoming {
-                // Ok, it seems we need to slow down in time for the next signal.
-                // Let's find out how far that is away.
-                // 1. Number of accelerations I need to brake away is velocity/acceleration;
-                //    since acceleration = change in velocity per second, this is in seconds
-                // 2. how far I'm travelling in that time is given by the velocity
-                // 3. thus t = v/a, s = v * t -> s = v²/a
-                //    if we're closer than this distance, brake furiously
-                let braking_distance = (engine.velocity.length() - v_upcoming).powi(2) / engine.amax;
-                // If we're approaching a signal and that signal shows red, we'll need
-                // to stop some ways away in front of it, so that our Navigator doesn't
-                // conclude we passed it already and people don't get uncomfortable.
-                if distance < braking_distance + 12.0 {
-                    engine.acceleration = direction.scale_to_length(-engine.amax);
-                    continue;
+            let must_brake = slow_downs.iter().any(|&(v_upcoming, distance)| {
+                if engine.speed <= v_upcoming && v_upcoming > 0.0 {
+                    return false;
                 }
+                // Ok, it seems we need to slow down in time.
+                // 1. Braking from v to u takes t = (v - u)/a seconds
+                // 2. in that time, we're going (v + u)/2 on average
+                // 3. thus s = (v + u)/2 * (v - u)/a -> s = (v² - u²)/2a
+                //    if we're closer than this distance, brake furiously. Whatever the
+                //    rails do to hold us back comes on top, so that's on the safe side.
+                let braking_distance = (engine.speed.powi(2) - v_upcoming.powi(2)).max(0.0) / (2.0 * engine.braking);
+                // If we're approaching a signal and that signal shows red, we'll need
+                // to stop some ways away in front of it, so that we don't roll past it
+                // and people don't get uncomfortable. If we're already standing there,
+                // this keeps us from creeping any closer.
+                distance < braking_distance + 12.0
+            });
+            if must_brake {
+                engine.acceleration = -engine.braking - resisting / mass;
+                continue;
             }
 
             // Ok, no need to brake. Let's see if we want to accelerate.
-            if engine.velocity.length() < v_target {
-                engine.acceleration = direction.scale_to_length(engine.amax);
+            if engine.speed < v_target {
+                engine.acceleration = towards_target((engine.tractive_effort - resisting) / mass);
                 continue;
             }
 
-            engine.acceleration = Vector::zero();
+            // We're going just fast enough, so we only pull as hard as it takes to keep it
+            // that way, if we can.
+            engine.acceleration = ((engine.tractive_effort - resisting) / mass).min(0.0);
         }
-        // In a station
-        for (train_pos, mut engine, station) in (&positions, &mut engines, &trains_in_station).join() {
-            let next_pos = positions.get(station.station).unwrap();
-            //println!("I'm in ur {:?}, going to {:?}", train_pos, next_pos);
-            let direction = next_pos.distance_to(train_pos);
-            let distance = direction.length();
-
-            if distance < 0.2 {
-                // We'll consider this "arrived"
-                engine.velocity = Vector::zero();
-            } else {
-                engine.velocity = direction; // -f
-            }
-            engine.acceleration = Vector::zero();
+        // In a station, we just sit there until someone tells us where to go next.
+        for (train, engine, _) in (&entities, &mut engines, &trains_in_station).join() {
+            engine.speed = 0.0;
+            engine.acceleration = 0.0;
+            expectations.remove(train);
         }
     }
 }
@@ -0,0 +1,241 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+
+use super::physics::Position;
+use super::editing::CrossingKind;
+use super::signals::SignalKind;
+use super::deadlock::DeadlockResolution;
+use super::scenario::ScenarioError;
+use super::simulation::Simulation;
+use super::SimRng;
+
+/**
+ * Bump this whenever the format changes in a way that older recordings can't be
+ * played back anymore.
+ */
+pub const REPLAY_VERSION: u32 = 4;
+
+/**
+ * Everything a user can do that changes how the simulation plays out. Entities get
+ * different ids every time, so junctions are referred to by where they are.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum UserAction {
+    AddRail { from: Position, to: Position, crossing: CrossingKind },
+    PlaceSignal { junction: Position, kind: SignalKind },
+    PlaceApproachSignal { at: Position },
+    PlantTrain { station: Position, destination: Position },
+    Bulldoze { at: Position },
+    SetDeadlockResolution { resolution: DeadlockResolution },
+    Undo,
+    Redo,
+}
+
+/**
+ * Something the user did, and when on the simulation clock they did it.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedAction {
+    pub at:     f64,
+    pub action: UserAction,
+}
+
+/**
+ * What the world looked like before the user started clicking around.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum StartingPoint {
+    DefaultNetwork { width: u32, height: u32 },
+    Scenario(PathBuf),
+}
+
+/**
+ * All it takes to make a simulation play out the same way again: Where it started,
+ * how the dice were seeded and everything the user did along the way.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    pub version: u32,
+    pub start:   StartingPoint,
+    pub seed:    u64,
+    pub actions: Vec<RecordedAction>,
+}
+
+impl Recording {
+    pub fn new(start: StartingPoint, seed: u64) -> Self {
+        Self {
+            version: REPLAY_VERSION,
+            start,
+            seed,
+            actions: vec![],
+        }
+    }
+
+    /**
+     * When the last thing happened, in simulated seconds.
+     */
+    pub fn duration(&self) -> f64 {
+        self.actions.last().map_or(0.0, |recorded| recorded.at)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ReplayError> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| ReplayError::Format(err.to_string()))?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ReplayError> {
+        let recording: Recording = ron::from_str(&fs::read_to_string(path)?)
+            .map_err(|err| ReplayError::Format(err.to_string()))?;
+        if recording.version != REPLAY_VERSION {
+            return Err(ReplayError::UnsupportedVersion(recording.version));
+        }
+        Ok(recording)
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Format(String),
+    UnsupportedVersion(u32),
+    Scenario(ScenarioError),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(err)                  => write!(f, "I/O error: {}", err),
+            ReplayError::Format(err)              => write!(f, "malformed recording: {}", err),
+            ReplayError::UnsupportedVersion(vers) =>
+                write!(f, "recording version {} is not supported (expected {})", vers, REPLAY_VERSION),
+            ReplayError::Scenario(err)            => write!(f, "scenario of the recording: {}", err),
+        }
+    }
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(err: io::Error) -> Self {
+        ReplayError::Io(err)
+    }
+}
+
+impl From<ScenarioError> for ReplayError {
+    fn from(err: ScenarioError) -> Self {
+        ReplayError::Scenario(err)
+    }
+}
+
+/**
+ * Playing back and recording both hook into the Simulation, so that actions always
+ * happen right between the same two steps.
+ */
+impl Simulation {
+    /**
+     * Set up the world the recording started from, ready to play it back. Actions are
+     * carried out as the simulation clock gets to them.
+     */
+    pub fn from_recording(recording: &Recording) -> Result<Self, ReplayError> {
+        let mut sim = match &recording.start {
+            StartingPoint::DefaultNetwork { width, height } =>
+                Simulation::with_default_network(*width, *height),
+            StartingPoint::Scenario(path) => Simulation::from_scenario(path)?,
+        };
+        sim.reseed(recording.seed);
+        sim.replaying = recording.actions.iter().cloned().collect();
+        Ok(sim)
+    }
+
+    /**
+     * Start writing down everything the user does from here on. This only makes sense
+     * right after the simulation was created from the given starting point.
+     */
+    pub fn start_recording(&mut self, start: StartingPoint) {
+        let seed = self.world.read_resource::<SimRng>().seed;
+        self.recording = Some(Recording::new(start, seed));
+    }
+
+    pub fn recording(&self) -> Option<&Recording> {
+        self.recording.as_ref()
+    }
+
+    /**
+     * Whether there's anything left to play back.
+     */
+    pub fn is_replaying(&self) -> bool {
+        !self.replaying.is_empty()
+    }
+
+    /**
+     * Do what the user asked for, and write it down if we're recording. Returns false
+     * if that turned out not to be possible.
+     */
+    pub fn perform(&mut self, action: UserAction) -> bool {
+        let done = match &action {
+            UserAction::AddRail { from, to, crossing } => {
+                self.add_rail(from.clone(), to.clone(), *crossing);
+                true
+            },
+            UserAction::PlaceSignal { junction, kind } => match self.junction_at(junction, 1.0) {
+                Some(junction) => {
+                    self.place_signal(junction, *kind);
+                    true
+                },
+                None => false,
+            },
+            UserAction::PlantTrain { station, destination } => {
+                match self.junction_at(station, 1.0).and_then(|station| self.plant_train_anywhere(station)) {
+                    Some((_train, picked)) => {
+                        if self.junction_at(destination, 1.0) != Some(picked) {
+                            println!("The dice came up differently than in the recording, this replay is off the rails");
+                        }
+                        true
+                    },
+                    None => false,
+                }
+            },
+            UserAction::PlaceApproachSignal { at } => self.place_approach_signal(at),
+            UserAction::Bulldoze { at } => self.bulldoze(at),
+            UserAction::SetDeadlockResolution { resolution } => {
+                self.set_deadlock_resolution(*resolution);
+                true
+            },
+            UserAction::Undo => self.undo(),
+            UserAction::Redo => self.redo(),
+        };
+        self.record(action);
+        done
+    }
+
+    /**
+     * Write down something the user did, if we're recording.
+     */
+    pub(crate) fn record(&mut self, action: UserAction) {
+        let now = self.clock().now;
+        if let Some(recording) = self.recording.as_mut() {
+            recording.actions.push(RecordedAction { at: now, action });
+        }
+    }
+
+    /**
+     * Carry out everything from the recording that should have happened by now.
+     */
+    pub(crate) fn play_back_due_actions(&mut self) {
+        let now = self.clock().now;
+        while self.replaying.front().is_some_and(|recorded| recorded.at <= now) {
+            let recorded = self.replaying.pop_front().unwrap();
+            println!("Replaying {:?} at {:.2}s", recorded.action, recorded.at);
+            self.perform(recorded.action);
+        }
+    }
+}
+
+/**
+ * Recorded actions still waiting for the clock to get to them.
+ */
+pub type ReplayQueue = VecDeque<RecordedAction>;
@@ -1,13 +1,22 @@
-use std::collections::VecDeque;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
 use specs::prelude::*;
 
-use super::physics::SpeedLimit;
+use super::physics::{Position, SpeedLimit, TrainEngine};
+use super::track::{Tracks, TrackPosition};
+use super::train::{Consist, Wagon, TrainTrail};
+use super::cargo::TrainIsDwelling;
+use super::collision::Crashes;
+use super::DeltaTime;
 use super::signals::{
     JunctionSignal,
     SignalIsReservedByTrain,
     SignalIsBlockedByTrain,
-    TrainIsBlockingSignal,
+    TrainIsBlockingSignals,
     SpeedLimitFromNextSignal,
+    CrossingIsReservedByTrain,
+    JunctionIsReservedByTrain,
+    release_reservations,
 };
 
 #[derive(Debug, Clone, PartialEq)]
@@ -31,10 +40,28 @@ impl Junction {
         }
     }
 }
+impl Default for Junction {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl Component for Junction {
     type Storage = VecStorage<Self>;
 }
 
+/**
+ * Where two lines cross without being connected, each line gets a waypoint of its own
+ * at the crossing. Trains can't switch from one line to the other there, but they can't
+ * both go across at the same time either, so both waypoints know about each other.
+ */
+#[derive(Debug, Clone)]
+pub struct DiamondCrossing {
+    pub other: Entity
+}
+impl Component for DiamondCrossing {
+    type Storage = HashMapStorage<Self>;
+}
+
 #[derive(Debug, Clone)]
 pub struct TrainIsInStation {
     pub station: Entity
@@ -60,7 +87,7 @@ impl TrainRoute {
     pub fn new(path: VecDeque<Entity>, dest: Entity) -> Self {
         Self {
             hops: path,
-            dest: dest
+            dest
         }
     }
     pub fn next_hop(&self) -> Entity {
@@ -69,6 +96,24 @@ impl TrainRoute {
     pub fn arrived_at_hop(&mut self) -> Entity {
         self.hops.pop_front().expect("Sad panda")
     }
+    /**
+     * Check that there are still rails underneath the train that lead to the next hop,
+     * and that every hop after that is still connected to the one before it.
+     */
+    pub fn is_intact(&self, train_pos: &Position, junctions: &ReadStorage<Junction>, tracks: &Tracks) -> bool {
+        self.can_reach_next_hop(train_pos, junctions, tracks) &&
+            self.hops.iter().zip(self.hops.iter().skip(1)).all(|(&here, next)| {
+                junctions.get(here).is_some_and(|j| j.connections.contains(next))
+            })
+    }
+    pub fn can_reach_next_hop(&self, train_pos: &Position, junctions: &ReadStorage<Junction>, tracks: &Tracks) -> bool {
+        let next = self.next_hop();
+        junctions.contains(next) &&
+            tracks.segments_at(next).into_iter().any(|id| {
+                let segment = tracks.get(id).unwrap();
+                segment.closest_point(train_pos).0.distance_length_to(train_pos) < 3.0
+            })
+    }
 }
 impl Component for TrainRoute {
     type Storage = HashMapStorage<Self>;
@@ -76,50 +121,293 @@ impl Component for TrainRoute {
 
 
 /**
- * Helper enum used by the TrainRouter to efficiently evaluate paths while
- * searching for a route.
- * It represents a tree of Junctions that stores along which path we came
- * to the position where we are now.
- * The nice thing about it is that it does so without heap allocations
- * or copying data around, both of which would be required when storing
- * this information in a Vec or somesuch.
- * Getting those lifetime annotations correct did drive me crazy though :P
- * Since the tree exclusively stores references, it cannot outlive the
- * walk_the_line call stack that it was created in.
+ * How much the router dislikes certain things on a route, counted in pixels of track
+ * that it would rather drive instead. The route we pick is the one where its length plus
+ * all of these penalties adds up to the least.
+ */
+#[derive(Debug, Clone)]
+pub struct RouteCosts {
+    // Every signal we pass means another block that we need a reservation for.
+    pub signal: f64,
+    // Heading into a block that someone else has reserved or is standing in means waiting.
+    pub reserved: f64,
+    // Turning by more than 90 degrees means stopping and going backwards for a bit.
+    pub reversal: f64,
+    // When leaving a station, we'd really like to stay off the rails that trains use
+    // to come into it. Those have a signal right in front of the station, while the
+    // outgoing ones usually don't, so signals this close to the start cost extra.
+    pub departure_signal: f64,
+    pub departure_distance: f64,
+}
+impl Default for RouteCosts {
+    fn default() -> Self {
+        Self {
+            signal:             20.0,
+            reserved:          200.0,
+            reversal:          500.0,
+            departure_signal: 1000.0,
+            departure_distance: 100.0,
+        }
+    }
+}
+
+/**
+ * Where we are while searching for a route: At curr, having come from prev. We need to
+ * know both, because whether going on to the next junction means reversing depends on
+ * where we came from. While we're still close to the station we set off from, what the
+ * next signal costs depends on how far we've come, so that's part of it too. It's kept
+ * as the bits of the f64, which doesn't do Hash.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct SearchState {
+    prev:     Option<Entity>,
+    curr:     Entity,
+    departed: Option<u64>,
+}
+
+/**
+ * A state that the router still needs to look at, along with what it cost to get there.
+ * Ordered so that the BinaryHeap hands out the cheapest estimate first.
+ */
+struct Candidate {
+    estimate: f64,
+    cost:     f64,
+    state:    SearchState,
+}
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.estimate == other.estimate
+    }
+}
+impl Eq for Candidate {}
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.partial_cmp(&self.estimate).unwrap_or(Ordering::Equal)
+    }
+}
+
+/**
+ * Everything the router needs to know about the rails to find its way around.
+ * Claims tells which signals are reserved or blocked by which train, so that we can
+ * steer around others. Rails with a wreck on them are no good to anyone, and neither
+ * are junctions we've been told to avoid.
  */
-enum PathTree<'a> {
-    Root,
-    Node(&'a PathTree<'a>, &'a Entity)
+pub struct RailNetwork<'r, 'a> {
+    pub junctions: &'r ReadStorage<'a, Junction>,
+    pub positions: &'r ReadStorage<'a, Position>,
+    pub signals:   &'r ReadStorage<'a, JunctionSignal>,
+    pub claims:    HashMap<Entity, Entity>,
+    pub wrecked:   HashSet<(Entity, Entity)>,
+    pub avoided:   HashSet<Entity>,
+    pub costs:     RouteCosts,
 }
 
-impl<'a> PathTree<'a> {
-    // Create a new node that adds the hop to its path.
-    pub fn add(&'a self, hop: &'a Entity) -> Self {
-        PathTree::Node(self, hop)
+impl<'r, 'a> RailNetwork<'r, 'a> {
+    pub fn new(
+        junctions: &'r ReadStorage<'a, Junction>,
+        positions: &'r ReadStorage<'a, Position>,
+        signals: &'r ReadStorage<'a, JunctionSignal>,
+        costs: RouteCosts
+    ) -> Self {
+        Self {
+            junctions,
+            positions,
+            signals,
+            claims:    HashMap::new(),
+            wrecked:   HashSet::new(),
+            avoided:   HashSet::new(),
+            costs,
+        }
+    }
+
+    /**
+     * Tell the router that a signal has been reserved or blocked by some train.
+     */
+    pub fn claim(&mut self, signal: Entity, train: Entity) {
+        self.claims.insert(signal, train);
+    }
+
+    /**
+     * Tell the router about all the rails that wrecks are lying on, so it stays off them.
+     */
+    pub fn avoid_wrecks(&mut self, crashes: &Crashes) {
+        for (from, to) in crashes.blocked_rails() {
+            self.wrecked.insert((from, to));
+            self.wrecked.insert((to, from));
+        }
+    }
+
+    /**
+     * Tell the router to find a way that doesn't go through the given junction.
+     */
+    pub fn avoid(&mut self, junction: Entity) {
+        self.avoided.insert(junction);
+    }
+
+    /**
+     * Find the cheapest path from a junction to some destination, and from there on to
+     * the next signal behind it so that the train can correctly rsvp its way out again.
+     * The path starts with the junction we're starting from. If there is no way to get
+     * there, the path is empty.
+     *
+     * This is plain A*, with the straight line to the destination as our estimate,
+     * which works because no route can ever be shorter than that.
+     */
+    pub fn find_path(&self, train: Option<Entity>, from: Entity, dest: Entity) -> VecDeque<Entity> {
+        // Only trains that are leaving a station care about where the trains coming into
+        // it would go.
+        let departing = self.junctions.get(from).is_some_and(|j| j.is_terminal);
+        let start = SearchState { prev: None, curr: from, departed: departing.then_some(0f64.to_bits()) };
+        self.search(train, vec![(start, 0.0)], dest)
+    }
+
+    /**
+     * Find the cheapest path to some destination for a train that is somewhere along a
+     * rail, heading for one of its ends. It may go on to that end, or turn around and
+     * leave through the other one. The path starts with the end it leaves through.
+     */
+    pub fn find_path_from(&self, train: Option<Entity>, track_pos: &TrackPosition, heading: Entity, tracks: &Tracks, dest: Entity) -> VecDeque<Entity> {
+        let segment = match tracks.get(track_pos.segment) {
+            Some(segment) if segment.connects(heading) => segment,
+            _ => return VecDeque::new(),
+        };
+        let behind = if segment.to == heading { segment.from } else { segment.to };
+        let starts = [
+            (behind, heading, track_pos.distance_to(heading, tracks).unwrap(), 0.0),
+            (heading, behind, track_pos.distance_to(behind, tracks).unwrap(), self.costs.reversal),
+        ].iter()
+            // What it costs to get to the end of the rail is what it'd cost to drive all
+            // of it, less the part we're already past.
+            .filter_map(|&(behind, ahead, distance, extra)| {
+                self.step(train, None, behind, ahead, None).map(|(cost, length)| {
+                    (SearchState { prev: Some(behind), curr: ahead, departed: None }, cost - length + distance + extra)
+                })
+            })
+            .collect();
+        self.search(train, starts, dest)
+    }
+
+    /**
+     * The A* itself, setting out from any of the given states, each of which cost the
+     * given amount to get to.
+     */
+    fn search(&self, train: Option<Entity>, starts: Vec<(SearchState, f64)>, dest: Entity) -> VecDeque<Entity> {
+        let dest_pos = match self.positions.get(dest) {
+            Some(pos) => pos,
+            None => return VecDeque::new(),
+        };
+        let estimate = |state: &SearchState| {
+            if state.prev == Some(dest) {
+                // We're already past dest and at its exit signal. Nothing left to do.
+                return 0.0;
+            }
+            self.positions.get(state.curr).map_or(0.0, |pos| pos.distance_length_to(dest_pos))
+        };
+
+        let mut best: HashMap<SearchState, f64> = HashMap::new();
+        let mut came_from: HashMap<SearchState, SearchState> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        for (start, cost) in starts {
+            if best.get(&start).is_some_and(|&known| known <= cost) {
+                continue;
+            }
+            best.insert(start, cost);
+            queue.push(Candidate { estimate: cost + estimate(&start), cost, state: start });
+        }
+
+        while let Some(Candidate { cost, state, .. }) = queue.pop() {
+            if best.get(&state).is_some_and(|&known| cost > known) {
+                continue; // We've already been here for cheaper.
+            }
+            let SearchState { prev, curr, departed } = state;
+            let departed = departed.map(f64::from_bits);
+            if prev == Some(dest) {
+                return Self::unwind(&came_from, state);
+            }
+            let junction = match self.junctions.get(curr) {
+                Some(junction) => junction,
+                None => continue,
+            };
+            for &next in &junction.connections {
+                if curr == dest {
+                    // We're at the destination. Find the next signal, which has to be an
+                    // exit signal of the station, so it is directly attached to dest.
+                    // Going back where we came from doesn't count.
+                    if Some(next) == prev || !self.signals.contains(next) {
+                        continue;
+                    }
+                }
+                let (step_cost, step_length) = match self.step(train, prev, curr, next, departed) {
+                    Some(step) => step,
+                    None => continue,
+                };
+                // Once we're far enough from the station, it's all the same how far exactly.
+                let departed = departed
+                    .map(|length| length + step_length)
+                    .filter(|&length| length < self.costs.departure_distance);
+                let next_state = SearchState { prev: Some(curr), curr: next, departed: departed.map(f64::to_bits) };
+                let next_cost = cost + step_cost;
+                if best.get(&next_state).is_some_and(|&known| known <= next_cost) {
+                    continue;
+                }
+                best.insert(next_state, next_cost);
+                came_from.insert(next_state, state);
+                queue.push(Candidate {
+                    estimate: next_cost + estimate(&next_state),
+                    cost:     next_cost,
+                    state:    next_state,
+                });
+            }
+        }
+        VecDeque::new()
     }
-    // Check if the path contains a specific hop.
-    pub fn contains(&self, hop: &Entity) -> bool {
-        match self {
-            PathTree::Node(prev, this_hop) => *this_hop == hop || prev.contains(hop),
-            PathTree::Root => false
+
+    /**
+     * What it costs to go from curr to next, having come from prev. If we're leaving a
+     * station, departed tells how far we've come since. Returns the cost and the length
+     * of the step.
+     */
+    fn step(&self, train: Option<Entity>, prev: Option<Entity>, curr: Entity, next: Entity, departed: Option<f64>) -> Option<(f64, f64)> {
+        if self.wrecked.contains(&(curr, next)) || self.avoided.contains(&next) {
+            return None;
+        }
+        let here = self.positions.get(curr)?;
+        let there = self.positions.get(next)?;
+        let distance = here.distance_length_to(there);
+        let mut cost = distance;
+        if self.signals.contains(next) {
+            cost += self.costs.signal;
+            if departed.is_some_and(|length| length + distance < self.costs.departure_distance) {
+                cost += self.costs.departure_signal;
+            }
+            if self.claims.get(&next).is_some_and(|&claimant| Some(claimant) != train) {
+                cost += self.costs.reserved;
+            }
         }
+        if let Some(back) = prev.and_then(|prev| self.positions.get(prev)) {
+            let incoming = here.distance_to(back);
+            let outgoing = there.distance_to(here);
+            if incoming.x * outgoing.x + incoming.y * outgoing.y < 0.0 {
+                cost += self.costs.reversal;
+            }
+        }
+        Some((cost, distance))
     }
-    // Convert the whole path into a VecDeque to feed into the TrainRoute.
-    pub fn into_vec(self) -> VecDeque<Entity> {
-        fn inner(treenode: &PathTree, sz: usize) -> VecDeque<Entity> {
-            // Recursive function that determines how many hops are in our
-            // path while going down, and then joins them into a VecDeque
-            // while coming back up.
-            match treenode {
-                PathTree::Root => VecDeque::with_capacity(sz),
-                PathTree::Node(prev, this_hop) => {
-                    let mut vec = inner(prev, sz + 1);
-                    vec.push_back(**this_hop);
-                    vec
-                },
+
+    fn unwind(came_from: &HashMap<SearchState, SearchState>, mut state: SearchState) -> VecDeque<Entity> {
+        let mut path = VecDeque::new();
+        loop {
+            path.push_front(state.curr);
+            match came_from.get(&state) {
+                Some(&parent) => state = parent,
+                None => return path,
             }
         }
-        inner(&self, 0)
     }
 }
 
@@ -143,6 +431,16 @@ impl<'a> System<'a> for TrainRouter {
         WriteStorage<'a, TrainRoute>,
         ReadStorage<'a, Junction>,
         ReadStorage<'a, JunctionSignal>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, SignalIsReservedByTrain>,
+        ReadStorage<'a, SignalIsBlockedByTrain>,
+        Read<'a, RouteCosts>,
+        WriteStorage<'a, TrackPosition>,
+        Read<'a, Tracks>,
+        WriteStorage<'a, TrainTrail>,
+        ReadStorage<'a, Consist>,
+        WriteStorage<'a, TrainIsDwelling>,
+        Read<'a, Crashes>,
     );
 
     fn run(&mut self, sys_data: Self::SystemData) {
@@ -153,100 +451,46 @@ impl<'a> System<'a> for TrainRouter {
             mut routes,
             junctions,
             signals,
+            positions,
+            reservations,
+            blockages,
+            costs,
+            mut track_positions,
+            tracks,
+            mut trails,
+            consists,
+            mut dwelling,
+            crashes,
         ) = sys_data;
+        let mut network = RailNetwork::new(&junctions, &positions, &signals, costs.clone());
+        for (signal, rsvp) in (&entities, &reservations).join() {
+            network.claim(signal, rsvp.train);
+        }
+        for (signal, blockage) in (&entities, &blockages).join() {
+            network.claim(signal, blockage.train);
+        }
+        network.avoid_wrecks(&crashes);
         let mut trains_that_left_the_building = vec![];
         let mut doomed_trains = vec![];
         for (train, station, destination) in (&entities, &trains_in_station, &trains_that_want_to_travel).join() {
-            // We're coming from station.station -> Entity -> Junction.
-            // We wanna go to destination.destination -> Entity -> Junction.
-            // station_junction hopefully has connections that have connections to dest_junction.
-            // Let's find that connection.
-            // Once we're at dest, let's find the next signal which should be that station's
-            // exit signal, so that the train can correctly rsvp the path to its destination.
-            fn walk_the_line(
-                junctions: &ReadStorage<Junction>,
-                signals: &ReadStorage<JunctionSignal>,
-                prev: &PathTree,
-                curr: Entity,
-                dest: Option<Entity>,
-                ttl: u8
-            ) -> VecDeque<Entity> {
-                // Infinite loop protection
-                if ttl == 0 {
-                    return VecDeque::new();
-                }
-                let curr_j = junctions.get(curr).unwrap();
-                // When looking for a path to the destination, make two passes.
-                // It may or may not be the case, that there are two paths forward that
-                // lead to our destination: One goes via a signal, the other one doesn't.
-                // This frequently happens when we're leaving a station: We arrived at a
-                // junction where the incoming rail joins the outgoing one, and we'd like
-                // to keep that part of the rails free. Since usually the outgoing path
-                // does not have a signal while the incoming one does, let's see if we
-                // can avoid taking that route and still get to our destination.
-                // If we cannot find a path when ignoring those that go via signals, we
-                // make another pass that does not have this constraint. If that pass
-                // finds a path, we reluctantly take it because it's really the only
-                // option we have.
-                for &i_hate_signals in &[true, false] {
-                    for &next in &curr_j.connections {
-                        if prev.contains(&next) { // No loops plox
-                            continue;
-                        }
-                        if let Some(dest) = dest {
-                            // We have not even yet found the actual destination.
-                            // Recurse until we do.
-                            if i_hate_signals && signals.contains(next) {
-                                // Unless we're not in the mood for running across signals.
-                                continue;
-                            }
-                            let path_from_next =
-                                if next == dest {
-                                    // Hooray, we're here! Find the next signal.
-                                    walk_the_line(
-                                        junctions, signals,
-                                        &prev.add(&curr),
-                                        dest,
-                                        None,
-                                        ttl - 1
-                                    )
-                                } else {
-                                    // No luck. Let's see if that node finds a way to dest
-                                    walk_the_line(
-                                        junctions, signals,
-                                        &prev.add(&curr),
-                                        next,
-                                        Some(dest),
-                                        ttl - 1
-                                    )
-                                };
-                            if !path_from_next.is_empty() {
-                                return path_from_next;
-                            }
-                        } else {
-                            // We're at the original destination. Find the next signal.
-                            // This signal has to be an exit signal of the station, thus
-                            // it is directly attached to dest (or does not exist).
-                            // No more recursion.
-                            if signals.contains(next) {
-                                return prev.add(&curr).add(&next).into_vec();
-                            }
-                        }
-                    }
-                }
-                VecDeque::new()
+            // Don't leave while they're still loading us up.
+            if dwelling.get(train).is_some_and(|dwell| !dwell.is_done()) {
+                continue;
             }
-            let mut path_to_dest = walk_the_line(
-                &junctions,
-                &signals,
-                &PathTree::Root,
+            let mut path_to_dest = network.find_path(
+                Some(train),
                 station.station,
-                Some(destination.destination),
-                32
+                destination.destination
             );
             if !path_to_dest.is_empty() {
                 path_to_dest.pop_front(); // Pop _this_ station
                 println!("Path to enlightenment: {:?}", path_to_dest);
+                // Put the train onto the rails that lead out of the station.
+                if let Some(track_pos) = TrackPosition::leaving(station.station, path_to_dest[0], &tracks) {
+                    track_positions.insert(train, track_pos).expect("derailed before departure");
+                }
+                // The wagons come out of the station right behind us.
+                trails.insert(train, TrainTrail::starting_at(station.station)).expect("lost track");
                 routes
                     .insert(
                         train,
@@ -262,14 +506,162 @@ impl<'a> System<'a> for TrainRouter {
         for train in trains_that_left_the_building {
             trains_in_station.remove(train);
             trains_that_want_to_travel.remove(train);
+            dwelling.remove(train);
         }
         for train in doomed_trains {
+            // Its wagons have nowhere to go either.
+            for &wagon in consists.get(train).map(|consist| consist.wagons.as_slice()).unwrap_or(&[]) {
+                entities.delete(wagon).expect("no deleto");
+            }
             entities.delete(train).expect("no deleto");
         }
     }
 }
 
 
+/**
+ * How long a train waits in front of a red signal before it asks for another way to its
+ * destination, in seconds.
+ */
+const PATIENCE: f64 = 10.0;
+
+/**
+ * Counts how long a train has been standing in front of a red signal.
+ */
+#[derive(Debug, Clone)]
+pub struct TrainIsWaitingAtSignal {
+    pub signal: Entity,
+    pub waited: f64,
+}
+impl Component for TrainIsWaitingAtSignal {
+    type Storage = HashMapStorage<Self>;
+}
+
+/**
+ * TrainRerouter looks after trains that have been waiting at a red signal for a while.
+ * If there's some other way to get where they're going that doesn't run into whoever is
+ * keeping them waiting, it sends them that way instead.
+ */
+pub struct TrainRerouter;
+
+impl<'a> System<'a> for TrainRerouter {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, TrainEngine>,
+        WriteStorage<'a, TrainRoute>,
+        WriteStorage<'a, TrainIsWaitingAtSignal>,
+        ReadStorage<'a, Junction>,
+        ReadStorage<'a, JunctionSignal>,
+        ReadStorage<'a, Position>,
+        WriteStorage<'a, SignalIsReservedByTrain>,
+        ReadStorage<'a, SignalIsBlockedByTrain>,
+        WriteStorage<'a, CrossingIsReservedByTrain>,
+        WriteStorage<'a, JunctionIsReservedByTrain>,
+        WriteStorage<'a, SpeedLimitFromNextSignal>,
+        ReadStorage<'a, TrackPosition>,
+        ReadStorage<'a, TrainTrail>,
+        Read<'a, Tracks>,
+        Read<'a, DeltaTime>,
+        Read<'a, RouteCosts>,
+        Read<'a, Crashes>,
+    );
+
+    fn run(&mut self, sys_data: Self::SystemData) {
+        let (
+            entities,
+            engines,
+            mut routes,
+            mut waiting,
+            junctions,
+            signals,
+            positions,
+            mut reservations,
+            blockages,
+            mut crossing_reservations,
+            mut junction_reservations,
+            mut speed_limits_upcoming,
+            track_positions,
+            trails,
+            tracks,
+            delta,
+            costs,
+            crashes,
+        ) = sys_data;
+        // Trains that arrived or got stopped aren't waiting for anything anymore.
+        let done: Vec<Entity> = (&entities, &waiting, !&routes).join()
+            .map(|(train, _, _)| train)
+            .collect();
+        for train in done {
+            waiting.remove(train);
+        }
+
+        let mut fed_up = vec![];
+        for (train, engine, route) in (&entities, &engines, &routes).join() {
+            let signal = route.next_hop();
+            // Whether it's a red signal or a wreck on the rails right behind it, we're stuck.
+            let wrecked = route.hops.get(1).is_some_and(|&after| crashes.is_blocked(signal, after));
+            let held = (signals.get(signal).is_some_and(|sig| sig.is_halt()) || wrecked) &&
+                engine.speed < 1.0;
+            if !held {
+                waiting.remove(train);
+                continue;
+            }
+            let wait = match waiting.get_mut(train) {
+                Some(wait) if wait.signal == signal => wait,
+                _ => {
+                    waiting
+                        .insert(train, TrainIsWaitingAtSignal { signal, waited: 0.0 })
+                        .expect("can't even wait properly");
+                    continue;
+                }
+            };
+            wait.waited += delta.fraction;
+            if wait.waited >= PATIENCE {
+                // Whether or not we find something better, give it some time before asking again.
+                wait.waited = 0.0;
+                fed_up.push(train);
+            }
+        }
+        if fed_up.is_empty() {
+            return;
+        }
+
+        let mut network = RailNetwork::new(&junctions, &positions, &signals, costs.clone());
+        for (signal, rsvp) in (&entities, &reservations).join() {
+            network.claim(signal, rsvp.train);
+        }
+        for (signal, blockage) in (&entities, &blockages).join() {
+            network.claim(signal, blockage.train);
+        }
+        network.avoid_wrecks(&crashes);
+        for train in fed_up {
+            let route = routes.get_mut(train).unwrap();
+            let signal = route.next_hop();
+            let track_pos = match track_positions.get(train) {
+                Some(track_pos) if signal != route.dest => track_pos,
+                _ => continue,
+            };
+            // Going through the signal we've been waiting at is what we're doing already.
+            // Anything else may well mean backing up and going some other way.
+            network.avoided.clear();
+            network.avoid(signal);
+            let detour = network.find_path_from(Some(train), track_pos, signal, &tracks, route.dest);
+            if detour.is_empty() {
+                continue;
+            }
+            println!("Train {:?} is tired of waiting, rerouting via {:?}", train, detour);
+            route.hops = detour;
+            release_reservations(
+                &entities, train, Some(&route.hops), trails.get(train),
+                &mut reservations, &mut crossing_reservations, &mut junction_reservations,
+            );
+            // That limit was for the next signal on the old route.
+            speed_limits_upcoming.remove(train);
+        }
+    }
+}
+
+
 /**
  * TrainNavigator sits beside the TrainDriver and decides if we're close enough to the
  * next hop that it makes sense to start thinking one step further.
@@ -281,21 +673,29 @@ pub struct TrainNavigator;
 impl<'a> System<'a> for TrainNavigator {
     type SystemData = (
         Entities<'a>,                               // I'm a guy
-        ReadStorage<'a, super::physics::Position>,  // I'm somewhere
+        WriteStorage<'a, TrackPosition>,            // I'm somewhere on the rails
+        Read<'a, Tracks>,                           // and those rails go places
         WriteStorage<'a, TrainRoute>,               // I need to make sure my damn map is correct
         WriteStorage<'a, TrainIsInStation>,         // I may or may not have gotten somewhere
         WriteStorage<'a, JunctionSignal>,
-        WriteStorage<'a, TrainIsBlockingSignal>,
+        WriteStorage<'a, TrainIsBlockingSignals>,
         WriteStorage<'a, SignalIsBlockedByTrain>,
         WriteStorage<'a, SignalIsReservedByTrain>,
         WriteStorage<'a, SpeedLimitFromNextSignal>,
         WriteStorage<'a, SpeedLimit>,
+        WriteStorage<'a, CrossingIsReservedByTrain>,
+        ReadStorage<'a, Position>,                  // I need to know how long I am
+        ReadStorage<'a, Consist>,
+        ReadStorage<'a, Wagon>,
+        WriteStorage<'a, TrainTrail>,               // and what's behind me
+        WriteStorage<'a, JunctionIsReservedByTrain>,
     );
 
     fn run(&mut self, sys_data: Self::SystemData) {
         let (
             entities,
-            positions,
+            mut track_positions,
+            tracks,
             mut routes,
             mut trains_in_station,
             junction_signals,
@@ -304,30 +704,46 @@ impl<'a> System<'a> for TrainNavigator {
             mut reservations,
             mut speed_limits_upcoming,
             mut speed_limits_current,
+            mut crossing_reservations,
+            positions,
+            consists,
+            wagons,
+            mut trails,
+            mut junction_reservations,
         ) = sys_data;
         let mut arrived_trains = vec![];
-        for (train, train_pos, route) in (&entities, &positions, &mut routes).join() {
-            let next_pos = positions.get(route.next_hop()).unwrap();
-            //println!("I'm in ur {:?}, going to {:?}", train_pos, next_pos);
-            let direction = next_pos.distance_to(train_pos);
-            let distance = direction.length();
-            if distance < 2.0 {
-                // We'll consider this "arrived"
+        for (train, track_pos, route) in (&entities, &mut track_positions, &mut routes).join() {
+            // The engine never takes us past the next hop, so once we're at the end of the
+            // rail, we're there.
+            if track_pos.is_at(route.next_hop(), &tracks) {
                 let here = route.arrived_at_hop();
-                // The signal that we once approached, we are now blocking
+                // Once we're across a diamond crossing, the other line is free to go.
+                if crossing_reservations.get(here).is_some_and(|rsvp| rsvp.train == train) {
+                    crossing_reservations.remove(here);
+                }
+                // Remember where we've been, so the wagons know where to go.
+                if let Some(trail) = trails.get_mut(train) {
+                    trail.passed.push_front(here);
+                }
+                // The signal that we once approached, we are now blocking. The ones behind
+                // us stay blocked until our tail is out of their block. If the signal went up
+                // right where we were standing, we never got to reserve it, and whoever did
+                // still needs their reservation.
                 if junction_signals.contains(here) {
-                    if let Some(train_blockage) = train_blockages.remove(train) {
-                        signal_blockages.remove(train_blockage.signal);
+                    if reservations.get(here).is_some_and(|rsvp| rsvp.train == train) {
+                        reservations.remove(here);
                     }
-                    reservations
-                        .remove(here)
-                        .expect("couldn't remove registration o_0");
                     signal_blockages
-                        .insert(here, SignalIsBlockedByTrain { train: train })
+                        .insert(here, SignalIsBlockedByTrain { train })
                         .expect("couldn't block next signal");
-                    train_blockages
-                        .insert(train, TrainIsBlockingSignal { signal: here })
-                        .expect("we're doomed, aren't we");
+                    match train_blockages.get_mut(train) {
+                        Some(blk) => blk.signals.push_back(here),
+                        None => {
+                            train_blockages
+                                .insert(train, TrainIsBlockingSignals { signals: VecDeque::from(vec![here]) })
+                                .expect("we're doomed, aren't we");
+                        }
+                    }
                     let _ = speed_limits_current.remove(train);
                     if let Some(speed_limit) = speed_limits_upcoming.remove(train) {
                         let _ = speed_limits_current.insert(
@@ -342,11 +758,58 @@ impl<'a> System<'a> for TrainNavigator {
                     trains_in_station
                         .insert(train, TrainIsInStation { station: here })
                         .expect("station is full");
+                } else if let Some(next) = route.hops.front() {
+                    // Switch over to the rail that leads to the next hop.
+                    if let Some(next_track_pos) = TrackPosition::leaving(here, *next, &tracks) {
+                        *track_pos = next_track_pos;
+                    }
                 }
             }
         }
         for train in arrived_trains {
             routes.remove(train);
         }
+        // Once the tail of the train has made it past the newer signal, it has left the
+        // block behind the older one, and we can let go of that.
+        for (train, nose, consist, mut trail, blk) in (&entities, &positions, consists.maybe(), (&mut trails).maybe(), &mut train_blockages).join() {
+            let length = consist.map(|consist| consist.length(&wagons)).unwrap_or(0.0);
+            while blk.signals.len() >= 2 {
+                let tail_is_out = trail.as_ref()
+                    .and_then(|trail| trail.distance_back_to(blk.signals[1], nose, &positions))
+                    .is_none_or(|distance| distance >= length);
+                if !tail_is_out {
+                    break;
+                }
+                let signal = blk.signals.pop_front().unwrap();
+                if signal_blockages.get(signal).is_some_and(|blockage| blockage.train == train) {
+                    signal_blockages.remove(signal);
+                }
+            }
+            if let Some(trail) = trail.as_mut() {
+                trail.trim(nose, length, &positions);
+            }
+        }
+        // Switches are free again once we're through with them, or if we're not going that
+        // way after all.
+        let through: Vec<Entity> = (&entities, &junction_reservations).join()
+            .filter(|&(junction, rsvp)| {
+                let train = rsvp.train;
+                if routes.get(train).is_some_and(|route| route.hops.contains(&junction)) {
+                    return false;
+                }
+                let nose = match positions.get(train) {
+                    Some(nose) => nose,
+                    None => return true,
+                };
+                let length = consists.get(train).map(|consist| consist.length(&wagons)).unwrap_or(0.0);
+                trails.get(train)
+                    .and_then(|trail| trail.distance_back_to(junction, nose, &positions))
+                    .is_none_or(|distance| distance >= length)
+            })
+            .map(|(junction, _)| junction)
+            .collect();
+        for junction in through {
+            junction_reservations.remove(junction);
+        }
     }
 }
@@ -0,0 +1,688 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use specs::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use super::physics::{Position, TrainEngine, SpeedLimit};
+use super::routing::{Junction, DiamondCrossing, TrainIsInStation, TrainWantsToTravelTo, TrainRoute, TrainIsWaitingAtSignal};
+use super::signals::{
+    JunctionSignal,
+    ApproachSignal,
+    ApproachAspect,
+    TrainExpectsSignal,
+    SignalState,
+    SignalKind,
+    TrainIsBlockingSignals,
+    SignalIsReservedByTrain,
+    SignalIsBlockedByTrain,
+    SpeedLimitFromNextSignal,
+    CrossingIsReservedByTrain,
+    JunctionIsReservedByTrain,
+};
+use super::train::{Wagon, Consist, TrainTrail};
+use super::cargo::{CargoKind, CargoStorage, CargoProducer, CargoConsumer, TrainIsDwelling};
+use super::simulation::Simulation;
+use super::orders::{Order, TrainOrders, WaitFor};
+use super::timetable::{Punctuality, TimetableEntry, StopEvent};
+use super::collision::{Crash, Crashes, TrainHasCrashed};
+use super::deadlock::{Deadlock, Deadlocks, DeadlockResolution, TrainIsGivingWay};
+use super::{Role, RoleKind, SimClock, SimRng};
+
+/**
+ * Bump this whenever the format changes in a way that older files can't be read anymore.
+ */
+pub const SAVEGAME_VERSION: u32 = 15;
+
+#[derive(Debug)]
+pub enum SaveGameError {
+    Io(io::Error),
+    Format(String),
+    UnsupportedVersion(u32),
+    UnknownEntity(u32),
+}
+
+impl fmt::Display for SaveGameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveGameError::Io(err)                  => write!(f, "I/O error: {}", err),
+            SaveGameError::Format(err)              => write!(f, "malformed savegame: {}", err),
+            SaveGameError::UnsupportedVersion(vers) =>
+                write!(f, "savegame version {} is not supported (expected {})", vers, SAVEGAME_VERSION),
+            SaveGameError::UnknownEntity(id)        => write!(f, "reference to unknown entity {}", id),
+        }
+    }
+}
+
+impl From<io::Error> for SaveGameError {
+    fn from(err: io::Error) -> Self {
+        SaveGameError::Io(err)
+    }
+}
+
+impl From<ron::Error> for SaveGameError {
+    fn from(err: ron::Error) -> Self {
+        SaveGameError::Format(err.to_string())
+    }
+}
+
+impl From<ron::error::SpannedError> for SaveGameError {
+    fn from(err: ron::error::SpannedError) -> Self {
+        SaveGameError::Format(err.to_string())
+    }
+}
+
+/**
+ * Specs hands out Entity ids as it sees fit, so we can't write those to disk and expect
+ * them to mean the same thing after loading. Instead, every entity gets numbered in the
+ * order in which we save it, and all references between entities use that number.
+ */
+type SavedId = u32;
+
+#[derive(Serialize, Deserialize)]
+struct SavedJunction {
+    connections: Vec<SavedId>,
+    is_terminal: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedJunctionSignal {
+    signal_state: SignalState,
+    appr_signals: Vec<SavedId>,
+    kind:         SignalKind,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedApproachSignal {
+    junction_signal: SavedId,
+    via:             SavedId,
+    distance:        f64,
+    rail:            (SavedId, SavedId),
+    offset:          f64,
+    aspect:          ApproachAspect,
+    by_hand:         bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedTrainRoute {
+    hops: Vec<SavedId>,
+    dest: SavedId,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedWagon {
+    train:    SavedId,
+    length:   f64,
+    tare:     f64,
+    cargo:    CargoKind,
+    capacity: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedConsist {
+    engine_length: f64,
+    engine_mass:   f64,
+    wagons:        Vec<SavedId>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedOrder {
+    destination: SavedId,
+    wait_for:    WaitFor,
+    arrival:     Option<f64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedTrainOrders {
+    orders:           Vec<SavedOrder>,
+    current:          usize,
+    repeat:           bool,
+    period:           Option<f64>,
+    round_started_at: f64,
+    waiting_since:    Option<f64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedTimetableEntry {
+    train:     SavedId,
+    station:   SavedId,
+    event:     StopEvent,
+    scheduled: f64,
+    actual:    f64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedCrash {
+    at:         Position,
+    time:       f64,
+    trains:     Vec<SavedId>,
+    blocked:    Vec<(SavedId, SavedId)>,
+    cleared_at: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedDeadlock {
+    since:       f64,
+    trains:      Vec<SavedId>,
+    signals:     Vec<SavedId>,
+    resolved_by: Option<DeadlockResolution>,
+    over_at:     Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct SavedEntity {
+    id: SavedId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<Position>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<RoleKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    junction: Option<SavedJunction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diamond_crossing: Option<SavedId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    junction_signal: Option<SavedJunctionSignal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    approach_signal: Option<SavedApproachSignal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expects_signal: Option<(SavedId, ApproachAspect)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    train_engine: Option<TrainEngine>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    speed_limit: Option<SpeedLimit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    speed_limit_from_next_signal: Option<SpeedLimitFromNextSignal>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    blocking_signals: Vec<SavedId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reserved_by_train: Option<SavedId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blocked_by_train: Option<SavedId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crossing_reserved_by_train: Option<SavedId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    junction_reserved_by_train: Option<SavedId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wagon: Option<SavedWagon>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    consist: Option<SavedConsist>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trail: Option<Vec<SavedId>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cargo_storage: Option<CargoStorage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cargo_producer: Option<CargoProducer>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cargo_consumer: Option<CargoConsumer>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dwelling: Option<TrainIsDwelling>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    in_station: Option<SavedId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    travelling_to: Option<SavedId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    route: Option<SavedTrainRoute>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    orders: Option<SavedTrainOrders>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crashed: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    giving_way: Option<TrainIsGivingWay>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    waiting_at_signal: Option<(SavedId, f64)>,
+}
+
+/**
+ * Only used to find out which version a file has before we try to make sense of the rest.
+ */
+#[derive(Deserialize)]
+struct SaveGameHeader {
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SaveGame {
+    version:  u32,
+    width:    u32,
+    height:   u32,
+    clock:    f64,
+    paused:   bool,
+    speed:    f64,
+    // The dice, and how often they were rolled, so a loaded game rolls the same numbers
+    // as one that was never saved.
+    rng_seed:     u64,
+    rng_position: u64,
+    entities: Vec<SavedEntity>,
+    timetable: Vec<SavedTimetableEntry>,
+    crashes:  Vec<SavedCrash>,
+    deadlock_resolution: DeadlockResolution,
+    deadlocks: Vec<SavedDeadlock>,
+}
+
+pub fn save(sim: &Simulation, path: &Path) -> Result<(), SaveGameError> {
+    fs::write(path, save_to_string(sim)?)?;
+    Ok(())
+}
+
+pub fn load(path: &Path) -> Result<Simulation, SaveGameError> {
+    load_from_str(&fs::read_to_string(path)?)
+}
+
+pub fn save_to_string(sim: &Simulation) -> Result<String, SaveGameError> {
+    let world = &sim.world;
+    let entities = world.entities();
+    let ids: HashMap<Entity, SavedId> = (&entities).join()
+        .enumerate()
+        .map(|(id, ent)| (ent, id as SavedId))
+        .collect();
+    // Components may still point at entities that were deleted in the meantime. Those
+    // references get dropped, along with anything that doesn't make sense without them.
+    let id_of = |ent: &Entity| ids.get(ent).cloned();
+
+    let positions          = world.read_storage::<Position>();
+    let roles              = world.read_storage::<Role>();
+    let junctions          = world.read_storage::<Junction>();
+    let diamond_crossings  = world.read_storage::<DiamondCrossing>();
+    let junction_signals   = world.read_storage::<JunctionSignal>();
+    let approach_signals   = world.read_storage::<ApproachSignal>();
+    let expectations       = world.read_storage::<TrainExpectsSignal>();
+    let engines            = world.read_storage::<TrainEngine>();
+    let speed_limits       = world.read_storage::<SpeedLimit>();
+    let speed_limits_next  = world.read_storage::<SpeedLimitFromNextSignal>();
+    let train_blockages    = world.read_storage::<TrainIsBlockingSignals>();
+    let reservations       = world.read_storage::<SignalIsReservedByTrain>();
+    let signal_blockages   = world.read_storage::<SignalIsBlockedByTrain>();
+    let crossing_rsvps     = world.read_storage::<CrossingIsReservedByTrain>();
+    let junction_rsvps     = world.read_storage::<JunctionIsReservedByTrain>();
+    let wagons             = world.read_storage::<Wagon>();
+    let consists           = world.read_storage::<Consist>();
+    let trails             = world.read_storage::<TrainTrail>();
+    let cargo_storages     = world.read_storage::<CargoStorage>();
+    let cargo_producers    = world.read_storage::<CargoProducer>();
+    let cargo_consumers    = world.read_storage::<CargoConsumer>();
+    let dwelling           = world.read_storage::<TrainIsDwelling>();
+    let trains_in_station  = world.read_storage::<TrainIsInStation>();
+    let trains_travelling  = world.read_storage::<TrainWantsToTravelTo>();
+    let routes             = world.read_storage::<TrainRoute>();
+    let orders             = world.read_storage::<TrainOrders>();
+    let crashed            = world.read_storage::<TrainHasCrashed>();
+    let giving_way         = world.read_storage::<TrainIsGivingWay>();
+    let waiting            = world.read_storage::<TrainIsWaitingAtSignal>();
+
+    let saved_entities = (&entities).join()
+        .map(|ent| SavedEntity {
+            id:       ids[&ent],
+            position: positions.get(ent).cloned(),
+            role:     roles.get(ent).map(|role| role.0.clone()),
+            junction: junctions.get(ent).map(|j| SavedJunction {
+                connections: j.connections.iter().filter_map(id_of).collect(),
+                is_terminal: j.is_terminal,
+            }),
+            diamond_crossing: diamond_crossings.get(ent).and_then(|crossing| id_of(&crossing.other)),
+            junction_signal: junction_signals.get(ent).map(|sig| SavedJunctionSignal {
+                signal_state: sig.signal_state.clone(),
+                appr_signals: sig.appr_signals.iter().filter_map(id_of).collect(),
+                kind:         sig.kind,
+            }),
+            approach_signal: approach_signals.get(ent).and_then(|sig| Some(SavedApproachSignal {
+                junction_signal: id_of(&sig.junction_signal)?,
+                via:             id_of(&sig.via)?,
+                distance:        sig.distance,
+                rail:            (id_of(&sig.rail.0)?, id_of(&sig.rail.1)?),
+                offset:          sig.offset,
+                aspect:          sig.aspect,
+                by_hand:         sig.by_hand,
+            })),
+            expects_signal:    expectations.get(ent).and_then(|expect| Some((id_of(&expect.signal)?, expect.aspect))),
+            train_engine:      engines.get(ent).cloned(),
+            speed_limit:       speed_limits.get(ent).cloned(),
+            speed_limit_from_next_signal: speed_limits_next.get(ent).cloned(),
+            blocking_signals:  train_blockages.get(ent)
+                .map(|blk| blk.signals.iter().filter_map(id_of).collect())
+                .unwrap_or_default(),
+            reserved_by_train: reservations.get(ent).and_then(|rsvp| id_of(&rsvp.train)),
+            blocked_by_train:  signal_blockages.get(ent).and_then(|blk| id_of(&blk.train)),
+            crossing_reserved_by_train: crossing_rsvps.get(ent).and_then(|rsvp| id_of(&rsvp.train)),
+            junction_reserved_by_train: junction_rsvps.get(ent).and_then(|rsvp| id_of(&rsvp.train)),
+            wagon:             wagons.get(ent).and_then(|wagon| Some(SavedWagon {
+                train:    id_of(&wagon.train)?,
+                length:   wagon.length,
+                tare:     wagon.tare,
+                cargo:    wagon.cargo,
+                capacity: wagon.capacity,
+            })),
+            consist:           consists.get(ent).map(|consist| SavedConsist {
+                engine_length: consist.engine_length,
+                engine_mass:   consist.engine_mass,
+                wagons:        consist.wagons.iter().filter_map(id_of).collect(),
+            }),
+            trail:             trails.get(ent).map(|trail| trail.passed.iter().filter_map(id_of).collect()),
+            cargo_storage:     cargo_storages.get(ent).cloned(),
+            cargo_producer:    cargo_producers.get(ent).cloned(),
+            cargo_consumer:    cargo_consumers.get(ent).cloned(),
+            dwelling:          dwelling.get(ent).cloned(),
+            in_station:        trains_in_station.get(ent).and_then(|st| id_of(&st.station)),
+            travelling_to:     trains_travelling.get(ent).and_then(|tr| id_of(&tr.destination)),
+            // A route with a hop missing would jump between junctions that aren't connected,
+            // so the train had better stop where it is.
+            route:             routes.get(ent).and_then(|route| Some(SavedTrainRoute {
+                hops: route.hops.iter().map(id_of).collect::<Option<_>>()?,
+                dest: id_of(&route.dest)?,
+            })),
+            orders:            orders.get(ent).map(|orders| {
+                let saved: Vec<(usize, SavedOrder)> = orders.orders.iter().enumerate()
+                    .filter_map(|(idx, order)| Some((idx, SavedOrder {
+                        destination: id_of(&order.destination)?,
+                        wait_for:    order.wait_for.clone(),
+                        arrival:     order.arrival,
+                    })))
+                    .collect();
+                // If the stop we're headed for is gone, we go on with the one after it.
+                let current = saved.iter().filter(|(idx, _)| *idx < orders.current).count();
+                let current = if orders.repeat && current >= saved.len() { 0 } else { current };
+                SavedTrainOrders {
+                    orders:           saved.into_iter().map(|(_, order)| order).collect(),
+                    current,
+                    repeat:           orders.repeat,
+                    period:           orders.period,
+                    round_started_at: orders.round_started_at,
+                    waiting_since:    orders.waiting_since,
+                }
+            }),
+            crashed:           crashed.get(ent).map(|wreck| wreck.crash),
+            giving_way:        giving_way.get(ent).cloned(),
+            waiting_at_signal: waiting.get(ent).and_then(|wait| Some((id_of(&wait.signal)?, wait.waited))),
+        })
+        .collect();
+
+    let savegame = SaveGame {
+        version:  SAVEGAME_VERSION,
+        width:    sim.map.size().0,
+        height:   sim.map.size().1,
+        clock:    world.read_resource::<SimClock>().now,
+        paused:   world.read_resource::<SimClock>().paused,
+        speed:    world.read_resource::<SimClock>().speed,
+        rng_seed:     world.read_resource::<SimRng>().seed,
+        rng_position: u64::try_from(world.read_resource::<SimRng>().position())
+            .expect("Rolled the dice more often than there are atoms in the savegame"),
+        entities: saved_entities,
+        // Whatever happened to trains or stations that are gone by now is history.
+        timetable: world.read_resource::<Punctuality>().entries.iter()
+            .filter_map(|entry| Some(SavedTimetableEntry {
+                train:     id_of(&entry.train)?,
+                station:   id_of(&entry.station)?,
+                event:     entry.event,
+                scheduled: entry.scheduled,
+                actual:    entry.actual,
+            }))
+            .collect(),
+        // Crashes are numbered, so they all stay, even when the trains are long gone.
+        crashes: world.read_resource::<Crashes>().log.iter()
+            .map(|crash| SavedCrash {
+                at:         crash.at.clone(),
+                time:       crash.time,
+                trains:     crash.trains.iter().filter_map(id_of).collect(),
+                blocked:    crash.blocked.iter()
+                    .filter_map(|(from, to)| Some((id_of(from)?, id_of(to)?)))
+                    .collect(),
+                cleared_at: crash.cleared_at,
+            })
+            .collect(),
+        deadlock_resolution: *world.read_resource::<DeadlockResolution>(),
+        // Same goes for deadlocks.
+        deadlocks: world.read_resource::<Deadlocks>().log.iter()
+            .map(|deadlock| SavedDeadlock {
+                since:       deadlock.since,
+                trains:      deadlock.trains.iter().filter_map(id_of).collect(),
+                signals:     deadlock.signals.iter().filter_map(id_of).collect(),
+                resolved_by: deadlock.resolved_by,
+                over_at:     deadlock.over_at,
+            })
+            .collect(),
+    };
+    Ok(ron::ser::to_string_pretty(&savegame, ron::ser::PrettyConfig::default())?)
+}
+
+pub fn load_from_str(data: &str) -> Result<Simulation, SaveGameError> {
+    let header: SaveGameHeader = ron::from_str(data)?;
+    if header.version != SAVEGAME_VERSION {
+        return Err(SaveGameError::UnsupportedVersion(header.version));
+    }
+    let savegame: SaveGame = ron::from_str(data)?;
+
+    let mut sim = Simulation::new(savegame.width, savegame.height);
+    {
+        let mut clock = sim.world.write_resource::<SimClock>();
+        clock.now    = savegame.clock;
+        clock.paused = savegame.paused;
+        clock.speed  = savegame.speed;
+    }
+    *sim.world.write_resource::<SimRng>() = SimRng::resume(savegame.rng_seed, savegame.rng_position as u128);
+
+    // First create all the entities, so that we know what to point our references to.
+    let ents: HashMap<SavedId, Entity> = savegame.entities.iter()
+        .map(|saved| (saved.id, sim.world.create_entity().build()))
+        .collect();
+    restore_components(&sim.world, savegame.entities, &ents)?;
+    let ent_of = |id: &SavedId| ents.get(id).cloned().ok_or(SaveGameError::UnknownEntity(*id));
+    sim.world.write_resource::<Punctuality>().entries = savegame.timetable.iter()
+        .map(|saved| Ok(TimetableEntry {
+            train:     ent_of(&saved.train)?,
+            station:   ent_of(&saved.station)?,
+            event:     saved.event,
+            scheduled: saved.scheduled,
+            actual:    saved.actual,
+        }))
+        .collect::<Result<_, SaveGameError>>()?;
+    sim.world.write_resource::<Crashes>().log = savegame.crashes.iter()
+        .map(|saved| Ok(Crash {
+            at:         saved.at.clone(),
+            time:       saved.time,
+            trains:     saved.trains.iter().map(&ent_of).collect::<Result<_, _>>()?,
+            blocked:    saved.blocked.iter()
+                .map(|(from, to)| Ok((ent_of(from)?, ent_of(to)?)))
+                .collect::<Result<_, SaveGameError>>()?,
+            cleared_at: saved.cleared_at,
+        }))
+        .collect::<Result<_, SaveGameError>>()?;
+    *sim.world.write_resource::<DeadlockResolution>() = savegame.deadlock_resolution;
+    sim.world.write_resource::<Deadlocks>().log = savegame.deadlocks.iter()
+        .map(|saved| Ok(Deadlock {
+            since:       saved.since,
+            trains:      saved.trains.iter().map(&ent_of).collect::<Result<_, _>>()?,
+            signals:     saved.signals.iter().map(&ent_of).collect::<Result<_, _>>()?,
+            resolved_by: saved.resolved_by,
+            over_at:     saved.over_at,
+        }))
+        .collect::<Result<_, SaveGameError>>()?;
+    sim.rebuild_tracks();
+    // Segments get new ids when the rails are rebuilt, so instead of saving where on the
+    // rails trains are, we find that out again from their positions.
+    sim.put_trains_back_on_track();
+    Ok(sim)
+}
+
+fn restore_components(
+    world: &World,
+    saved_entities: Vec<SavedEntity>,
+    ents: &HashMap<SavedId, Entity>
+) -> Result<(), SaveGameError> {
+    let ent_of = |id: &SavedId| ents.get(id).cloned().ok_or(SaveGameError::UnknownEntity(*id));
+
+    let mut positions          = world.write_storage::<Position>();
+    let mut roles              = world.write_storage::<Role>();
+    let mut junctions          = world.write_storage::<Junction>();
+    let mut diamond_crossings  = world.write_storage::<DiamondCrossing>();
+    let mut junction_signals   = world.write_storage::<JunctionSignal>();
+    let mut approach_signals   = world.write_storage::<ApproachSignal>();
+    let mut expectations       = world.write_storage::<TrainExpectsSignal>();
+    let mut engines            = world.write_storage::<TrainEngine>();
+    let mut speed_limits       = world.write_storage::<SpeedLimit>();
+    let mut speed_limits_next  = world.write_storage::<SpeedLimitFromNextSignal>();
+    let mut train_blockages    = world.write_storage::<TrainIsBlockingSignals>();
+    let mut reservations       = world.write_storage::<SignalIsReservedByTrain>();
+    let mut signal_blockages   = world.write_storage::<SignalIsBlockedByTrain>();
+    let mut crossing_rsvps     = world.write_storage::<CrossingIsReservedByTrain>();
+    let mut junction_rsvps     = world.write_storage::<JunctionIsReservedByTrain>();
+    let mut wagons             = world.write_storage::<Wagon>();
+    let mut consists           = world.write_storage::<Consist>();
+    let mut trails             = world.write_storage::<TrainTrail>();
+    let mut cargo_storages     = world.write_storage::<CargoStorage>();
+    let mut cargo_producers    = world.write_storage::<CargoProducer>();
+    let mut cargo_consumers    = world.write_storage::<CargoConsumer>();
+    let mut dwelling           = world.write_storage::<TrainIsDwelling>();
+    let mut trains_in_station  = world.write_storage::<TrainIsInStation>();
+    let mut trains_travelling  = world.write_storage::<TrainWantsToTravelTo>();
+    let mut routes             = world.write_storage::<TrainRoute>();
+    let mut orders             = world.write_storage::<TrainOrders>();
+    let mut crashed            = world.write_storage::<TrainHasCrashed>();
+    let mut giving_way         = world.write_storage::<TrainIsGivingWay>();
+    let mut waiting            = world.write_storage::<TrainIsWaitingAtSignal>();
+
+    // Inserting can only fail if the entity is dead, and we just created all of them.
+    const ALIVE: &str = "freshly loaded entity died";
+
+    for saved in saved_entities {
+        let ent = ent_of(&saved.id)?;
+        if let Some(pos) = saved.position {
+            positions.insert(ent, pos).expect(ALIVE);
+        }
+        if let Some(role) = saved.role {
+            roles.insert(ent, Role(role)).expect(ALIVE);
+        }
+        if let Some(j) = saved.junction {
+            junctions.insert(ent, Junction {
+                connections: j.connections.iter().map(&ent_of).collect::<Result<_, _>>()?,
+                is_terminal: j.is_terminal,
+            }).expect(ALIVE);
+        }
+        if let Some(id) = saved.diamond_crossing {
+            diamond_crossings.insert(ent, DiamondCrossing { other: ent_of(&id)? }).expect(ALIVE);
+        }
+        if let Some(sig) = saved.junction_signal {
+            junction_signals.insert(ent, JunctionSignal {
+                signal_state: sig.signal_state,
+                appr_signals: sig.appr_signals.iter().map(&ent_of).collect::<Result<_, _>>()?,
+                kind:         sig.kind,
+            }).expect(ALIVE);
+        }
+        if let Some(sig) = saved.approach_signal {
+            approach_signals.insert(ent, ApproachSignal {
+                junction_signal: ent_of(&sig.junction_signal)?,
+                via:             ent_of(&sig.via)?,
+                distance:        sig.distance,
+                rail:            (ent_of(&sig.rail.0)?, ent_of(&sig.rail.1)?),
+                offset:          sig.offset,
+                aspect:          sig.aspect,
+                by_hand:         sig.by_hand,
+            }).expect(ALIVE);
+        }
+        if let Some((id, aspect)) = saved.expects_signal {
+            expectations.insert(ent, TrainExpectsSignal { signal: ent_of(&id)?, aspect }).expect(ALIVE);
+        }
+        if let Some(engine) = saved.train_engine {
+            engines.insert(ent, engine).expect(ALIVE);
+        }
+        if let Some(limit) = saved.speed_limit {
+            speed_limits.insert(ent, limit).expect(ALIVE);
+        }
+        if let Some(limit) = saved.speed_limit_from_next_signal {
+            speed_limits_next.insert(ent, limit).expect(ALIVE);
+        }
+        if !saved.blocking_signals.is_empty() {
+            train_blockages.insert(ent, TrainIsBlockingSignals {
+                signals: saved.blocking_signals.iter().map(&ent_of).collect::<Result<_, _>>()?,
+            }).expect(ALIVE);
+        }
+        if let Some(id) = saved.reserved_by_train {
+            reservations.insert(ent, SignalIsReservedByTrain { train: ent_of(&id)? }).expect(ALIVE);
+        }
+        if let Some(id) = saved.blocked_by_train {
+            signal_blockages.insert(ent, SignalIsBlockedByTrain { train: ent_of(&id)? }).expect(ALIVE);
+        }
+        if let Some(id) = saved.crossing_reserved_by_train {
+            crossing_rsvps.insert(ent, CrossingIsReservedByTrain { train: ent_of(&id)? }).expect(ALIVE);
+        }
+        if let Some(id) = saved.junction_reserved_by_train {
+            junction_rsvps.insert(ent, JunctionIsReservedByTrain { train: ent_of(&id)? }).expect(ALIVE);
+        }
+        if let Some(wagon) = saved.wagon {
+            wagons.insert(ent, Wagon {
+                train:    ent_of(&wagon.train)?,
+                length:   wagon.length,
+                tare:     wagon.tare,
+                cargo:    wagon.cargo,
+                capacity: wagon.capacity,
+            }).expect(ALIVE);
+        }
+        if let Some(consist) = saved.consist {
+            consists.insert(ent, Consist {
+                engine_length: consist.engine_length,
+                engine_mass:   consist.engine_mass,
+                wagons:        consist.wagons.iter().map(&ent_of).collect::<Result<_, _>>()?,
+            }).expect(ALIVE);
+        }
+        if let Some(trail) = saved.trail {
+            trails.insert(ent, TrainTrail {
+                passed: trail.iter().map(&ent_of).collect::<Result<_, _>>()?,
+            }).expect(ALIVE);
+        }
+        if let Some(storage) = saved.cargo_storage {
+            cargo_storages.insert(ent, storage).expect(ALIVE);
+        }
+        if let Some(producer) = saved.cargo_producer {
+            cargo_producers.insert(ent, producer).expect(ALIVE);
+        }
+        if let Some(consumer) = saved.cargo_consumer {
+            cargo_consumers.insert(ent, consumer).expect(ALIVE);
+        }
+        if let Some(dwell) = saved.dwelling {
+            dwelling.insert(ent, dwell).expect(ALIVE);
+        }
+        if let Some(id) = saved.in_station {
+            trains_in_station.insert(ent, TrainIsInStation { station: ent_of(&id)? }).expect(ALIVE);
+        }
+        if let Some(id) = saved.travelling_to {
+            trains_travelling.insert(ent, TrainWantsToTravelTo { destination: ent_of(&id)? }).expect(ALIVE);
+        }
+        if let Some(route) = saved.route {
+            routes.insert(ent, TrainRoute::new(
+                route.hops.iter().map(&ent_of).collect::<Result<_, _>>()?,
+                ent_of(&route.dest)?
+            )).expect(ALIVE);
+        }
+        if let Some(saved_orders) = saved.orders {
+            let train_orders: Vec<Order> = saved_orders.orders.iter()
+                .map(|order| Ok(Order {
+                    destination: ent_of(&order.destination)?,
+                    wait_for:    order.wait_for.clone(),
+                    arrival:     order.arrival,
+                }))
+                .collect::<Result<_, SaveGameError>>()?;
+            orders.insert(ent, TrainOrders {
+                // Past the end means we're done, but not any further than that.
+                current:          saved_orders.current.min(train_orders.len()),
+                orders:           train_orders,
+                repeat:           saved_orders.repeat,
+                period:           saved_orders.period,
+                round_started_at: saved_orders.round_started_at,
+                waiting_since:    saved_orders.waiting_since,
+            }).expect(ALIVE);
+        }
+        if let Some(crash) = saved.crashed {
+            crashed.insert(ent, TrainHasCrashed { crash }).expect(ALIVE);
+        }
+        if let Some(give_way) = saved.giving_way {
+            giving_way.insert(ent, give_way).expect(ALIVE);
+        }
+        if let Some((id, waited)) = saved.waiting_at_signal {
+            waiting.insert(ent, TrainIsWaitingAtSignal { signal: ent_of(&id)?, waited }).expect(ALIVE);
+        }
+    }
+
+    Ok(())
+}
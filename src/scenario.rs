@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use specs::prelude::*;
+
+use super::physics::{Position, EngineKind};
+use super::cargo::{CargoStorage, CargoProducer, CargoConsumer, CargoKind};
+use super::routing::Junction;
+use super::orders::{Order, WaitFor};
+use super::signals::{JunctionSignal, SignalKind};
+use super::approach::place_approach_signal;
+use super::world::connect_junctions;
+use super::{Role, RoleKind};
+
+/**
+ * Scenario files describe a network line by line, so that we don't have to hard-code
+ * every layout in world.rs. Empty lines and everything after a '#' are ignored.
+ * Names have to be declared before they can be referred to.
+ *
+ * ```text
+ *     size      <width> <height>
+ *     industry  <name> coal_mine|power_plant <x> <y>
+ *     produces  <industry> <cargo> <per second>
+ *     consumes  <industry> <cargo> <per second>
+ *     junction  <name> <x> <y>
+ *     signal    <junction> [path]
+ *     approach  <junction> <junction> <distance>
+ *     connect   <junction> <junction> [<junction> ...]
+ *     train     <industry> <industry> [<wagons>] [freight | heavy | passenger]
+ *     order     <industry> [full | wait <seconds> | depart <seconds>] [arrive <seconds>]
+ *     period    <seconds>
+ * ```
+ *
+ * `connect` lays rails between each pair of consecutive junctions, and `train` plants
+ * a train in the first industry that wants to go to the second one, pulling the given
+ * number of wagons or the default if there's none, with a freight engine unless it asks
+ * for another kind. `order` adds a stop to the orders of
+ * the train declared last, which it works through over and over once it has arrived.
+ * `depart` and `arrive` times count from the start of each round, and `period` makes
+ * that train start a new round every so many seconds, timetable style. Signals are block
+ * signals unless they're marked as `path`. Every signal gets approach signals on the
+ * lines leading into it anyway, but `approach` puts one up by hand for trains coming from
+ * the second junction, the given number of pixels ahead of the signal at the first.
+ */
+#[derive(Debug)]
+pub struct ScenarioError {
+    pub line: usize,
+    pub kind: ScenarioErrorKind,
+}
+
+#[derive(Debug)]
+pub enum ScenarioErrorKind {
+    Io(io::Error),
+    UnknownDirective(String),
+    WrongArgumentCount { directive: String, expected: &'static str },
+    BadNumber(String),
+    UnknownIndustryKind(String),
+    UnknownCargo(String),
+    UnknownEngine(String),
+    UnknownJunction(String),
+    NotAnIndustry(String),
+    DuplicateName(String),
+    OrderWithoutTrain,
+    NothingToApproach { signal: String, from: String },
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let ScenarioErrorKind::Io(err) = &self.kind {
+            return write!(f, "cannot read scenario: {}", err);
+        }
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            ScenarioErrorKind::Io(_) => unreachable!(),
+            ScenarioErrorKind::UnknownDirective(word) =>
+                write!(f, "unknown directive '{}'", word),
+            ScenarioErrorKind::WrongArgumentCount { directive, expected } =>
+                write!(f, "'{}' expects {}", directive, expected),
+            ScenarioErrorKind::BadNumber(word) =>
+                write!(f, "'{}' is not a number", word),
+            ScenarioErrorKind::UnknownIndustryKind(word) =>
+                write!(f, "unknown industry kind '{}'", word),
+            ScenarioErrorKind::UnknownCargo(word) =>
+                write!(f, "unknown cargo '{}'", word),
+            ScenarioErrorKind::UnknownEngine(word) =>
+                write!(f, "unknown engine '{}'", word),
+            ScenarioErrorKind::UnknownJunction(name) =>
+                write!(f, "unknown junction '{}'", name),
+            ScenarioErrorKind::NotAnIndustry(name) =>
+                write!(f, "'{}' is not an industry", name),
+            ScenarioErrorKind::DuplicateName(name) =>
+                write!(f, "'{}' has already been declared", name),
+            ScenarioErrorKind::OrderWithoutTrain =>
+                write!(f, "orders need a train to give them to"),
+            ScenarioErrorKind::NothingToApproach { signal, from } =>
+                write!(f, "there's no signal at '{}' that can be approached from '{}'", signal, from),
+        }
+    }
+}
+
+/**
+ * A train that the scenario wants planted once the world has been set up.
+ */
+#[derive(Debug, Clone)]
+pub struct InitialTrain {
+    pub station:     Entity,
+    pub destination: Entity,
+    pub wagons:      Option<usize>,
+    pub engine:      EngineKind,
+    pub orders:      Vec<Order>,
+    pub period:      Option<f64>,
+}
+
+enum Directive<'a> {
+    Size(u32, u32),
+    Industry(&'a str, RoleKind, Position),
+    Produces(&'a str, CargoKind, f64),
+    Consumes(&'a str, CargoKind, f64),
+    Junction(&'a str, Position),
+    Signal(&'a str, SignalKind),
+    Approach(&'a str, &'a str, f64),
+    Connect(Vec<&'a str>),
+    Train(&'a str, &'a str, Option<usize>, EngineKind),
+    Order(&'a str, WaitFor, Option<f64>),
+    Period(f64),
+}
+
+fn parse_line(line: &str) -> Result<Option<Directive<'_>>, ScenarioErrorKind> {
+    let line = line.split('#').next().unwrap();
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (&directive, args) = match words.split_first() {
+        Some(split) => split,
+        None => return Ok(None),
+    };
+
+    let expect = |expected: &'static str, ok: bool| {
+        if ok {
+            Ok(())
+        } else {
+            Err(ScenarioErrorKind::WrongArgumentCount {
+                directive: directive.to_string(),
+                expected
+            })
+        }
+    };
+    fn number<T: std::str::FromStr>(word: &str) -> Result<T, ScenarioErrorKind> {
+        word.parse().map_err(|_| ScenarioErrorKind::BadNumber(word.to_string()))
+    }
+    fn cargo(word: &str) -> Result<CargoKind, ScenarioErrorKind> {
+        word.parse().map_err(|_| ScenarioErrorKind::UnknownCargo(word.to_string()))
+    }
+    fn engine(word: &str) -> Result<EngineKind, ScenarioErrorKind> {
+        word.parse().map_err(|_| ScenarioErrorKind::UnknownEngine(word.to_string()))
+    }
+
+    Ok(Some(match directive {
+        "size" => {
+            expect("<width> <height>", args.len() == 2)?;
+            Directive::Size(number(args[0])?, number(args[1])?)
+        },
+        "industry" => {
+            expect("<name> <kind> <x> <y>", args.len() == 4)?;
+            let kind = match args[1] {
+                "coal_mine"   => RoleKind::CoalMine,
+                "power_plant" => RoleKind::PowerPlant,
+                other => return Err(ScenarioErrorKind::UnknownIndustryKind(other.to_string())),
+            };
+            Directive::Industry(args[0], kind, Position::new(number(args[2])?, number(args[3])?))
+        },
+        "produces" => {
+            expect("<industry> <cargo> <per second>", args.len() == 3)?;
+            Directive::Produces(args[0], cargo(args[1])?, number(args[2])?)
+        },
+        "consumes" => {
+            expect("<industry> <cargo> <per second>", args.len() == 3)?;
+            Directive::Consumes(args[0], cargo(args[1])?, number(args[2])?)
+        },
+        "junction" => {
+            expect("<name> <x> <y>", args.len() == 3)?;
+            Directive::Junction(args[0], Position::new(number(args[1])?, number(args[2])?))
+        },
+        "signal" => {
+            expect("<junction> [path]", args.len() == 1 || (args.len() == 2 && args[1] == "path"))?;
+            Directive::Signal(args[0], if args.len() == 2 { SignalKind::Path } else { SignalKind::Block })
+        },
+        "approach" => {
+            expect("<junction> <junction> <distance>", args.len() == 3)?;
+            Directive::Approach(args[0], args[1], number(args[2])?)
+        },
+        "connect" => {
+            expect("at least two junctions", args.len() >= 2)?;
+            Directive::Connect(args.to_vec())
+        },
+        "train" => {
+            let usage = "<industry> <industry> [<wagons>] [freight | heavy | passenger]";
+            expect(usage, (2..=4).contains(&args.len()))?;
+            let (wagons, kind) = match &args[2..] {
+                [] => (None, EngineKind::default()),
+                // A lone word is either the number of wagons or the kind of engine.
+                [word] => match word.parse() {
+                    Ok(wagons) => (Some(wagons), EngineKind::default()),
+                    Err(_) => (None, engine(word)?),
+                },
+                [count, word] => (Some(number(count)?), engine(word)?),
+                _ => unreachable!(),
+            };
+            Directive::Train(args[0], args[1], wagons, kind)
+        },
+        "order" => {
+            let usage = "<industry> [full | wait <seconds> | depart <seconds>] [arrive <seconds>]";
+            expect(usage, !args.is_empty())?;
+            let mut rest = &args[1..];
+            let mut wait_for = WaitFor::Nothing;
+            let mut arrival = None;
+            loop {
+                rest = match rest {
+                    [] => break,
+                    ["full", rest @ ..] if wait_for == WaitFor::Nothing => {
+                        wait_for = WaitFor::FullLoad;
+                        rest
+                    },
+                    ["wait", seconds, rest @ ..] if wait_for == WaitFor::Nothing => {
+                        wait_for = WaitFor::Timeout(number(seconds)?);
+                        rest
+                    },
+                    ["depart", seconds, rest @ ..] if wait_for == WaitFor::Nothing => {
+                        wait_for = WaitFor::DepartureTime(number(seconds)?);
+                        rest
+                    },
+                    ["arrive", seconds, rest @ ..] if arrival.is_none() => {
+                        arrival = Some(number(seconds)?);
+                        rest
+                    },
+                    _ => return Err(ScenarioErrorKind::WrongArgumentCount {
+                        directive: directive.to_string(),
+                        expected: usage
+                    }),
+                };
+            }
+            Directive::Order(args[0], wait_for, arrival)
+        },
+        "period" => {
+            expect("<seconds>", args.len() == 1)?;
+            Directive::Period(number(args[0])?)
+        },
+        other => return Err(ScenarioErrorKind::UnknownDirective(other.to_string())),
+    }))
+}
+
+/**
+ * Check the whole scenario for errors without touching the world. This way, a broken
+ * file never leaves us with half a network.
+ */
+fn parse(text: &str) -> Result<Vec<(usize, Directive<'_>)>, ScenarioError> {
+    let mut directives = vec![];
+    let mut junctions: HashMap<&str, bool> = HashMap::new(); // name -> is industry
+    let mut have_train = false;
+    for (idx, line) in text.lines().enumerate() {
+        let line_no = idx + 1;
+        let err = |kind| ScenarioError { line: line_no, kind };
+        let directive = match parse_line(line).map_err(err)? {
+            Some(directive) => directive,
+            None => continue,
+        };
+        let known = |name: &str| {
+            junctions.get(name).cloned()
+                .ok_or_else(|| err(ScenarioErrorKind::UnknownJunction(name.to_string())))
+        };
+        let industry = |name: &str| {
+            if known(name)? {
+                Ok(())
+            } else {
+                Err(err(ScenarioErrorKind::NotAnIndustry(name.to_string())))
+            }
+        };
+        match &directive {
+            Directive::Size(..) => (),
+            Directive::Produces(name, ..) | Directive::Consumes(name, ..) => industry(name)?,
+            Directive::Signal(name, _) => { known(name)?; },
+            Directive::Approach(signal, from, _) => {
+                known(signal)?;
+                known(from)?;
+            },
+            Directive::Connect(names) => {
+                for name in names {
+                    known(name)?;
+                }
+            },
+            Directive::Train(station, destination, ..) => {
+                industry(station)?;
+                industry(destination)?;
+                have_train = true;
+            },
+            Directive::Order(stop, ..) => {
+                if !have_train {
+                    return Err(err(ScenarioErrorKind::OrderWithoutTrain));
+                }
+                industry(stop)?;
+            },
+            Directive::Period(_) => {
+                if !have_train {
+                    return Err(err(ScenarioErrorKind::OrderWithoutTrain));
+                }
+            },
+            Directive::Industry(name, ..) | Directive::Junction(name, ..) => {
+                if junctions.contains_key(name) {
+                    return Err(err(ScenarioErrorKind::DuplicateName(name.to_string())));
+                }
+                junctions.insert(name, matches!(directive, Directive::Industry(..)));
+            },
+        }
+        directives.push((line_no, directive));
+    }
+    Ok(directives)
+}
+
+/**
+ * Read the size of the map from a scenario, so that the map can be created before the
+ * scenario is loaded into it.
+ */
+pub fn read_size(text: &str) -> Result<Option<(u32, u32)>, ScenarioError> {
+    for (_, directive) in parse(text)? {
+        if let Directive::Size(width, height) = directive {
+            return Ok(Some((width, height)));
+        }
+    }
+    Ok(None)
+}
+
+pub fn read_file(path: &Path) -> Result<String, ScenarioError> {
+    fs::read_to_string(path).map_err(|err| ScenarioError { line: 0, kind: ScenarioErrorKind::Io(err) })
+}
+
+/**
+ * Build the network described in the scenario. Returns the trains that should be
+ * planted, since planting them is the Simulation's business.
+ */
+pub fn populate_from(world: &mut World, text: &str) -> Result<Vec<InitialTrain>, ScenarioError> {
+    let directives = parse(text)?;
+    let mut junctions: HashMap<&str, Entity> = HashMap::new();
+    let mut trains = vec![];
+    let mut approaches = vec![];
+    // parse() already made sure that all names are valid, so we can index without fear.
+    for (line, directive) in directives {
+        match directive {
+            Directive::Size(..) => (),
+            Directive::Industry(name, kind, pos) => {
+                let industry = world.create_entity()
+                    .with(pos)
+                    .with(CargoStorage::new())
+                    .with(Role(kind))
+                    .with(Junction::new_terminal())
+                    .build();
+                junctions.insert(name, industry);
+            },
+            Directive::Produces(name, kind, production) => {
+                let mut producers = world.write_storage::<CargoProducer>();
+                let industry = junctions[name];
+                if let Some(producer) = producers.get_mut(industry) {
+                    producer.quantities.insert(kind, production);
+                } else {
+                    producers
+                        .insert(industry, CargoProducer::new().with(kind, production))
+                        .expect("industry vanished");
+                }
+            },
+            Directive::Consumes(name, kind, consumption) => {
+                let mut consumers = world.write_storage::<CargoConsumer>();
+                let industry = junctions[name];
+                if let Some(consumer) = consumers.get_mut(industry) {
+                    consumer.quantities.insert(kind, consumption);
+                } else {
+                    consumers
+                        .insert(industry, CargoConsumer::new().with(kind, consumption))
+                        .expect("industry vanished");
+                }
+            },
+            Directive::Junction(name, pos) => {
+                let junction = world.create_entity()
+                    .with(pos)
+                    .with(Junction::new())
+                    .with(Role(RoleKind::WayPoint))
+                    .build();
+                junctions.insert(name, junction);
+            },
+            Directive::Signal(name, kind) => {
+                world.write_storage::<JunctionSignal>()
+                    .insert(junctions[name], JunctionSignal::of_kind(kind))
+                    .expect("junction vanished");
+            },
+            Directive::Approach(..) => approaches.push((line, directive)),
+            Directive::Connect(names) => {
+                for pair in names.windows(2) {
+                    connect_junctions(world, junctions[pair[0]], junctions[pair[1]]);
+                }
+            },
+            Directive::Train(station, destination, wagons, engine) => {
+                trains.push(InitialTrain {
+                    station:     junctions[station],
+                    destination: junctions[destination],
+                    wagons,
+                    engine,
+                    orders:      vec![],
+                    period:      None,
+                });
+            },
+            Directive::Order(stop, wait_for, arrival) => {
+                trains.last_mut().unwrap().orders.push(Order {
+                    destination: junctions[stop],
+                    wait_for,
+                    arrival,
+                });
+            },
+            Directive::Period(period) => {
+                trains.last_mut().unwrap().period = Some(period);
+            },
+        }
+    }
+    // Approach signals need the rails in front of their signal, so they go up last.
+    for (line, directive) in approaches {
+        if let Directive::Approach(signal, from, distance) = directive {
+            if place_approach_signal(world, junctions[signal], junctions[from], distance).is_none() {
+                return Err(ScenarioError {
+                    line,
+                    kind: ScenarioErrorKind::NothingToApproach { signal: signal.to_string(), from: from.to_string() },
+                });
+            }
+        }
+    }
+    Ok(trains)
+}
@@ -1,9 +1,16 @@
+use std::collections::{HashMap, VecDeque};
 use specs::prelude::*;
+use specs::world::EntitiesRes;
+use serde::{Serialize, Deserialize};
 
-use super::physics::Position;
-use super::routing::{TrainRoute, TrainIsInStation};
+use super::physics::{Position, TrainEngine};
+use super::routing::{TrainRoute, TrainIsInStation, DiamondCrossing};
+use super::track::{Tracks, TrackPosition};
+use super::train::TrainTrail;
+use super::collision::Crashes;
+use super::deadlock::TrainIsGivingWay;
 
-#[derive(Clone,Debug,PartialEq)]
+#[derive(Clone,Debug,PartialEq,Serialize,Deserialize)]
 pub enum SignalState {
     Dark,
     Halt,
@@ -17,18 +24,75 @@ pub enum SignalState {
  * because stopping a train takes way longer than stopping a car, and by the time
  * the driver sees the junction signal, it's already too late.
  *
- * Since our trains get to cheat and can just query the Junction Signal Entity for its
- * state instead of physically having to look at it, this signal serves only cosmetic
- * purposes by showing up on the map.
+ * Every approach signal stands on the line that leads into its junction signal from
+ * one of its neighbours (via), on the rail from rail.0 to rail.1, offset pixels before
+ * rail.1. Trains that pass it learn what the junction signal is going to show them,
+ * and that's all they know until they get close enough to see for themselves.
  */
 #[derive(Debug, Clone, PartialEq)]
 pub struct ApproachSignal {
     pub junction_signal: Entity,
+    pub via:             Entity,
+    pub distance:        f64,
+    pub rail:            (Entity, Entity),
+    pub offset:          f64,
+    pub aspect:          ApproachAspect,
+    pub by_hand:         bool,
 }
 impl Component for ApproachSignal {
     type Storage = HashMapStorage<Self>;
 }
 
+impl ApproachSignal {
+    /**
+     * Is the train on the stretch of rail between us and the junction signal rail.1 is
+     * the start of? That's where the driver has just seen us, or is standing right next
+     * to us.
+     */
+    pub fn is_passed_by(&self, route: &TrainRoute, track_pos: &TrackPosition, tracks: &Tracks) -> bool {
+        route.next_hop() == self.rail.1 &&
+            tracks.get(track_pos.segment).is_some_and(|segment| segment.connects(self.rail.0)) &&
+            track_pos.distance_to(self.rail.1, tracks).is_some_and(|distance| distance <= self.offset + 1e-6)
+    }
+}
+
+/**
+ * What an approach signal tells the driver about the junction signal that's coming up.
+ */
+#[derive(Clone,Copy,Debug,PartialEq,Default,Serialize,Deserialize)]
+pub enum ApproachAspect {
+    #[default]
+    Dark,
+    ExpectHalt,
+    ExpectSlow,
+    ExpectGo,
+}
+
+/**
+ * What the driver made of the last approach signal they passed, and which junction
+ * signal it was talking about.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrainExpectsSignal {
+    pub signal: Entity,
+    pub aspect: ApproachAspect,
+}
+impl Component for TrainExpectsSignal {
+    type Storage = HashMapStorage<Self>;
+}
+
+/**
+ * A block signal lets one train at a time into the block behind it, no matter where in
+ * the block it's going. A path signal lets in as many as like, as long as their paths to
+ * the next signal don't touch. That's what you want in front of a bunch of switches.
+ */
+#[derive(Clone,Copy,Debug,PartialEq,Default,Serialize,Deserialize)]
+pub enum SignalKind {
+    #[default]
+    Block,
+    Path,
+}
+
 /**
  * Junction signals are placed directly at a junction. They signal to the driver where
  * the train needs to stop, and when it can continue on its journey.
@@ -37,26 +101,40 @@ impl Component for ApproachSignal {
 pub struct JunctionSignal {
     pub signal_state: SignalState,
     pub appr_signals: Vec<Entity>,
+    pub kind:         SignalKind,
 }
 impl JunctionSignal {
     pub fn new() -> Self {
+        Self::of_kind(SignalKind::Block)
+    }
+    pub fn of_kind(kind: SignalKind) -> Self {
         Self {
             signal_state: SignalState::Halt,
-            appr_signals: vec![]
+            appr_signals: vec![],
+            kind,
         }
     }
     pub fn is_halt(&self) -> bool {
         self.signal_state == SignalState::Halt || self.signal_state == SignalState::Dark
     }
 }
+impl Default for JunctionSignal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl Component for JunctionSignal {
     type Storage = HashMapStorage<Self>;
 }
 
-pub struct TrainIsBlockingSignal {
-    pub signal: Entity
+/**
+ * The signals that a train is still blocking, oldest first. A long train can stretch
+ * across several blocks, and each of them only frees up once its tail is out.
+ */
+pub struct TrainIsBlockingSignals {
+    pub signals: VecDeque<Entity>
 }
-impl Component for TrainIsBlockingSignal {
+impl Component for TrainIsBlockingSignals {
     type Storage = HashMapStorage<Self>;
 }
 
@@ -74,6 +152,29 @@ impl Component for SignalIsBlockedByTrain {
     type Storage = HashMapStorage<Self>;
 }
 
+/**
+ * Once a signal lets a train go, every junction up to the next signal belongs to that
+ * train, until its tail is past it. Nobody else gets a green light through there.
+ */
+pub struct JunctionIsReservedByTrain {
+    pub train: Entity
+}
+impl Component for JunctionIsReservedByTrain {
+    type Storage = HashMapStorage<Self>;
+}
+
+/**
+ * A train that is about to go across a diamond crossing holds it until it has passed,
+ * so that nobody on the other line runs into its side.
+ */
+pub struct CrossingIsReservedByTrain {
+    pub train: Entity
+}
+impl Component for CrossingIsReservedByTrain {
+    type Storage = HashMapStorage<Self>;
+}
+
+#[derive(Debug,Clone,Serialize,Deserialize)]
 pub struct SpeedLimitFromNextSignal {
     pub vmax: f64
 }
@@ -82,9 +183,66 @@ impl Component for SpeedLimitFromNextSignal {
 }
 
 
-const ASSUMED_VMAX:  f64 = 30.0;
-const VMAX_FOR_SLOW: f64 = 20.0;
-const ASSUMED_AMAX:  f64 =  4.0;
+/**
+ * A train is going another way now. Let go of the signals, crossings and switches it had
+ * reserved that are no longer on its way, or of all of them if it's not going anywhere
+ * anymore. Switches that it's still standing on, which the trail tells, stay reserved
+ * until the Navigator sees its tail clear of them. The Fahrdienstleiter will make new
+ * reservations along the new route.
+ */
+pub fn release_reservations(
+    entities: &EntitiesRes,
+    train: Entity,
+    hops: Option<&VecDeque<Entity>>,
+    trail: Option<&TrainTrail>,
+    reservations: &mut WriteStorage<SignalIsReservedByTrain>,
+    crossing_reservations: &mut WriteStorage<CrossingIsReservedByTrain>,
+    junction_reservations: &mut WriteStorage<JunctionIsReservedByTrain>,
+) {
+    let still_needed = |ent: &Entity| hops.is_some_and(|hops| hops.contains(ent));
+    let outdated: Vec<Entity> = (entities, &*reservations).join()
+        .filter(|(signal, rsvp)| rsvp.train == train && !still_needed(signal))
+        .map(|(signal, _)| signal)
+        .collect();
+    for signal in outdated {
+        reservations.remove(signal);
+    }
+    let outdated: Vec<Entity> = (entities, &*crossing_reservations).join()
+        .filter(|(crossing, rsvp)| rsvp.train == train && !still_needed(crossing))
+        .map(|(crossing, _)| crossing)
+        .collect();
+    for crossing in outdated {
+        crossing_reservations.remove(crossing);
+    }
+    let standing_on = |ent: &Entity| trail.is_some_and(|trail| trail.passed.contains(ent));
+    let outdated: Vec<Entity> = (entities, &*junction_reservations).join()
+        .filter(|(junction, rsvp)| rsvp.train == train && !still_needed(junction) && !standing_on(junction))
+        .map(|(junction, _)| junction)
+        .collect();
+    for junction in outdated {
+        junction_reservations.remove(junction);
+    }
+}
+
+
+/**
+ * What we go by for trains that don't tell us what their engine can do.
+ */
+const ASSUMED_VMAX:    f64 = 30.0;
+const ASSUMED_BRAKING: f64 =  4.0;
+
+/**
+ * A train that has to keep below this share of its top speed to be able to stop at the
+ * next signal gets to see Slow instead of Go.
+ */
+const SHARE_OF_VMAX_FOR_SLOW: f64 = 2.0 / 3.0;
+
+/**
+ * How fast a train can go and how hard it can brake, as far as signalling is concerned.
+ */
+fn braking_performance(engines: &ReadStorage<TrainEngine>, train: Entity) -> (f64, f64) {
+    engines.get(train).map_or((ASSUMED_VMAX, ASSUMED_BRAKING), |engine| (engine.vmax, engine.braking))
+}
 
 
 /**
@@ -102,6 +260,16 @@ impl<'a> System<'a> for Fahrdienstleiter {
         WriteStorage<'a, SignalIsBlockedByTrain>,
         ReadStorage<'a,  Position>,
         WriteStorage<'a, SpeedLimitFromNextSignal>,
+        ReadStorage<'a,  DiamondCrossing>,
+        WriteStorage<'a, CrossingIsReservedByTrain>,
+        ReadStorage<'a,  TrainIsGivingWay>,
+        WriteStorage<'a, JunctionIsReservedByTrain>,
+        ReadStorage<'a,  TrackPosition>,
+        ReadStorage<'a,  TrainTrail>,
+        Read<'a, Tracks>,
+        Read<'a, Crashes>,
+        WriteStorage<'a, ApproachSignal>,
+        ReadStorage<'a,  TrainEngine>,
     );
 
     fn run(&mut self, sys_data: Self::SystemData) {
@@ -113,15 +281,39 @@ impl<'a> System<'a> for Fahrdienstleiter {
             blockages,
             positions,
             mut speed_limits_upcoming,
+            crossings,
+            mut crossing_reservations,
+            giving_way,
+            mut junction_reservations,
+            track_positions,
+            trails,
+            tracks,
+            crashes,
+            mut approach_signals,
+            engines,
         ) = sys_data;
+        // Path signals need to know which rails have a train on them. That's the rail
+        // under each engine, and the ones its wagons are still on.
+        let mut occupied: HashMap<(Entity, Entity), Entity> = HashMap::new();
+        for (train, trail) in (&entities, &trails).join() {
+            let under_engine = track_positions.get(train)
+                .and_then(|track_pos| tracks.get(track_pos.segment))
+                .map(|segment| (segment.from, segment.to));
+            let under_wagons = trail.passed.iter().zip(trail.passed.iter().skip(1)).map(|(&a, &b)| (a, b));
+            for (a, b) in under_engine.into_iter().chain(under_wagons) {
+                occupied.insert((a, b), train);
+                occupied.insert((b, a), train);
+            }
+        }
         // Phase one: Let's go over all'a dem trains and see what we can do for them in terms
         // of signal reservations.
         // Each train that is en route wants to have two reservations: One for the signal where
         // it's currently located (it should have that one inherently), and another one for the
         // signal that comes _after_ the first one, so that the first one can turn green and
         // allow the train to set forth on its journey.
+        // Trains that were told to give way to break a deadlock have to wait their turn.
         let mut signals_on_go = vec![];
-        for (train, route) in (&entities, &routes).join() {
+        for (train, route, _) in (&entities, &routes, !&giving_way).join() {
             let two_signals: Vec<Entity> = route.hops.iter()
                 .filter(|&&e| junction_signals.contains(e))
                 .take(2)
@@ -137,12 +329,56 @@ impl<'a> System<'a> for Fahrdienstleiter {
                     }
                 } else {
                     reservations
-                        .insert(signal, SignalIsReservedByTrain { train: train })
+                        .insert(signal, SignalIsReservedByTrain { train })
                         .expect("rsvp denied");
                 }
                 rsvp_count += 1;
             }
             if rsvp_count == 2 {
+                // The block behind the first signal may run across a diamond crossing, where
+                // someone on the other line could cut right through us. We need that as well.
+                let first  = route.hops.iter().position(|&hop| hop == two_signals[0]).unwrap();
+                let second = route.hops.iter().position(|&hop| hop == two_signals[1]).unwrap();
+                let crossings_ahead: Vec<Entity> = route.hops.range(first + 1..second)
+                    .filter(|&&hop| crossings.contains(hop))
+                    .cloned()
+                    .collect();
+                let all_ours = crossings_ahead.iter().all(|&crossing| {
+                    [crossing, crossings.get(crossing).unwrap().other].iter().all(|side| {
+                        crossing_reservations.get(*side).is_none_or(|rsvp| rsvp.train == train)
+                    })
+                });
+                if !all_ours {
+                    continue;
+                }
+                // Neither can we go through switches that somebody else is going through. The
+                // signals at either end may well sit on a switch themselves.
+                let area: Vec<Entity> = route.hops.range(first..=second).cloned().collect();
+                if area.iter().any(|&junction| junction_reservations.get(junction).is_some_and(|rsvp| rsvp.train != train)) {
+                    continue;
+                }
+                // A path signal doesn't care if there's someone in its block, as long as they're
+                // not on the rails that we're about to take.
+                if junction_signals.get(two_signals[0]).unwrap().kind == SignalKind::Path {
+                    let path: Vec<Entity> = route.hops.range(first..=second).cloned().collect();
+                    let clear = path.windows(2).all(|rail| {
+                        occupied.get(&(rail[0], rail[1])).is_none_or(|&other| other == train) &&
+                            !crashes.is_blocked(rail[0], rail[1])
+                    });
+                    if !clear {
+                        continue;
+                    }
+                }
+                for junction in area {
+                    junction_reservations
+                        .insert(junction, JunctionIsReservedByTrain { train })
+                        .expect("switch got stuck");
+                }
+                for crossing in crossings_ahead {
+                    crossing_reservations
+                        .insert(crossing, CrossingIsReservedByTrain { train })
+                        .expect("crossing got run over");
+                }
                 // We're clear, allow the first signal to turn green...
                 signals_on_go.push(two_signals[0]);
                 // ... but let's also see if we need to inflict a speed limit on the train.
@@ -150,11 +386,13 @@ impl<'a> System<'a> for Fahrdienstleiter {
                     let distance = positions.get(two_signals[0]).unwrap().distance_to(
                         positions.get(two_signals[1]).unwrap()
                     ).length() - 15.0;
-                    // s = v²/a => v² = s*a => v = sqrt(s*a)
-                    let vmax = (distance * ASSUMED_AMAX).sqrt();
-                    if vmax < ASSUMED_VMAX {
+                    // Every train stops in its own way, so that's what we need to go by.
+                    // s = v²/2a => v² = 2*s*a => v = sqrt(2*s*a)
+                    let (train_vmax, train_braking) = braking_performance(&engines, train);
+                    let vmax = (2.0 * distance * train_braking).sqrt();
+                    if vmax < train_vmax {
                         let _ = speed_limits_upcoming
-                            .insert(train, SpeedLimitFromNextSignal { vmax: vmax });
+                            .insert(train, SpeedLimitFromNextSignal { vmax });
                     }
                 }
             }
@@ -162,9 +400,10 @@ impl<'a> System<'a> for Fahrdienstleiter {
 
         // So now that we have the reservations booked, let's see what those signals need
         // to be telling our trains.
-        for (signal, mut signal_s) in (&entities, &mut junction_signals).join() {
-            // First of all: If I'm blocked, I'll show red.
-            if blockages.contains(signal) {
+        for (signal, signal_s) in (&entities, &mut junction_signals).join() {
+            // First of all: If I'm blocked, I'll show red. Path signals have already made sure
+            // that the train they're letting in won't be running into whoever is in there.
+            if blockages.contains(signal) && signal_s.kind == SignalKind::Block {
                 signal_s.signal_state = SignalState::Halt;
                 continue;
             }
@@ -175,8 +414,9 @@ impl<'a> System<'a> for Fahrdienstleiter {
             // down the line.
             if let Some(rsvp) = reservations.get(signal) {
                 if signals_on_go.contains(&signal) {
+                    let (train_vmax, _) = braking_performance(&engines, rsvp.train);
                     let slow = speed_limits_upcoming.get(rsvp.train)
-                        .and_then(|limit| Some(limit.vmax < VMAX_FOR_SLOW))
+                        .map(|limit| limit.vmax < train_vmax * SHARE_OF_VMAX_FOR_SLOW)
                         .unwrap_or(false);
                     if slow {
                         signal_s.signal_state = SignalState::Slow;
@@ -192,6 +432,27 @@ impl<'a> System<'a> for Fahrdienstleiter {
             // Looks like I've got nothing to do :)
             signal_s.signal_state = SignalState::Dark;
         }
+
+        // Approach signals repeat whatever their junction signal is about to show. If it's
+        // letting someone go who's coming from another direction, that's a halt for us.
+        for approach in (&mut approach_signals).join() {
+            let state = junction_signals.get(approach.junction_signal).map(|signal_s| &signal_s.signal_state);
+            let coming_our_way = reservations.get(approach.junction_signal)
+                .and_then(|rsvp| routes.get(rsvp.train).map(|route| (rsvp.train, route)))
+                .is_some_and(|(train, route)| {
+                    let on_our_rail = route.next_hop() == approach.junction_signal && track_positions.get(train)
+                        .and_then(|track_pos| tracks.get(track_pos.segment))
+                        .is_some_and(|segment| segment.connects(approach.via));
+                    on_our_rail || route.hops.iter().zip(route.hops.iter().skip(1))
+                        .any(|(&here, &next)| here == approach.via && next == approach.junction_signal)
+                });
+            approach.aspect = match state {
+                None | Some(SignalState::Dark) => ApproachAspect::Dark,
+                Some(SignalState::Slow) if coming_our_way => ApproachAspect::ExpectSlow,
+                Some(SignalState::Go) if coming_our_way => ApproachAspect::ExpectGo,
+                Some(_) => ApproachAspect::ExpectHalt,
+            };
+        }
     }
 }
 
@@ -206,9 +467,11 @@ impl<'a> System<'a> for Fahrdienstputzfrau {
     type SystemData = (
         Entities<'a>,
         ReadStorage<'a,  TrainIsInStation>,
-        WriteStorage<'a, TrainIsBlockingSignal>,
+        WriteStorage<'a, TrainIsBlockingSignals>,
         WriteStorage<'a, SignalIsBlockedByTrain>,
         WriteStorage<'a, SignalIsReservedByTrain>,
+        WriteStorage<'a, CrossingIsReservedByTrain>,
+        WriteStorage<'a, JunctionIsReservedByTrain>,
     );
 
     fn run(&mut self, sys_data: Self::SystemData) {
@@ -218,12 +481,18 @@ impl<'a> System<'a> for Fahrdienstputzfrau {
             mut train_blockages,
             mut signal_blockages,
             mut signal_reservations,
+            mut crossing_reservations,
+            mut junction_reservations,
         ) = sys_data;
-        // Clean up signal blockages held by trains that arrived in station.
+        // Clean up signal blockages held by trains that arrived in station. Another train may
+        // have come through a path signal after us, and then it's theirs.
         for (train, _) in (&entities, &trains_in_station).join() {
             if let Some(blockage) = train_blockages.remove(train) {
-                signal_blockages.remove(blockage.signal)
-                    .expect("blockage mismatch");
+                for signal in blockage.signals {
+                    if signal_blockages.get(signal).is_some_and(|blk| blk.train == train) {
+                        signal_blockages.remove(signal);
+                    }
+                }
             }
         }
         // Clean up signal reservations held by trains that arrived in station.
@@ -236,5 +505,21 @@ impl<'a> System<'a> for Fahrdienstputzfrau {
         for signal in outdated_rsvps {
             signal_reservations.remove(signal).expect("derp");
         }
+        // Same goes for diamond crossings.
+        let outdated_crossings: Vec<Entity> = (&entities, &crossing_reservations).join()
+            .filter(|(_, rsvp)| trains_in_station.contains(rsvp.train))
+            .map(|(crossing, _)| crossing)
+            .collect();
+        for crossing in outdated_crossings {
+            crossing_reservations.remove(crossing);
+        }
+        // And for switches.
+        let outdated_junctions: Vec<Entity> = (&entities, &junction_reservations).join()
+            .filter(|(_, rsvp)| trains_in_station.contains(rsvp.train))
+            .map(|(junction, _)| junction)
+            .collect();
+        for junction in outdated_junctions {
+            junction_reservations.remove(junction);
+        }
     }
 }
@@ -0,0 +1,453 @@
+use std::path::Path;
+use specs::prelude::*;
+use rand::seq::IteratorRandom;
+
+use super::map::Map;
+use super::physics::{self, Position, EngineKind};
+use super::routing::{self, Junction, RouteCosts};
+use super::signals;
+use super::cargo;
+use super::track::{self, Tracks, TrackSegment, TrackHit};
+use super::train::{self, Consist, Wagon};
+use super::world::populate;
+use super::approach::put_up_approach_signals;
+use super::history::History;
+use super::savegame::{self, SaveGameError};
+use super::scenario::{self, ScenarioError};
+use super::orders::{self, Order, TrainOrders};
+use super::timetable::Punctuality;
+use super::collision::{self, Crashes};
+use super::deadlock::{self, DeadlockResolution, Deadlocks};
+use super::replay::{Recording, ReplayQueue, UserAction};
+use super::{DeltaTime, SimClock, SimRng, Role, RoleKind, SignalRenderer};
+
+/**
+ * The longest step the systems are ever asked to take, in simulated seconds. Anything
+ * longer gets chopped up into several steps, so trains don't overshoot their brakes.
+ * Updating from the wall clock always goes in steps of exactly this long.
+ */
+pub const MAX_STEP: f64 = 0.05;
+
+/**
+ * The most wall clock time that a single update catches up on.
+ */
+pub const MAX_FRAME_TIME: f64 = 0.25;
+
+/**
+ * The Simulation owns everything that makes our little railway tick: The specs World
+ * with all its entities, the dispatcher that runs the systems and the Map that rails
+ * are drawn on.
+ *
+ * It does not need a window, so it can just as well be run from a test or a tool
+ * that has no display. The piston frontend is only one client that feeds mouse
+ * clicks into it and renders whatever comes out.
+ */
+pub struct Simulation {
+    pub world: World,
+    pub map:   Map,
+    pub(crate) history: History,
+    dispatcher: Dispatcher<'static, 'static>,
+    pub(crate) recording: Option<Recording>,
+    pub(crate) replaying: ReplayQueue,
+}
+
+impl Simulation {
+    /**
+     * Create an empty simulation without any junctions or rails.
+     */
+    pub fn new(width: u32, height: u32) -> Self {
+        let mut world = World::new();
+        world.register::<physics::Position>();
+        world.register::<track::TrackPosition>();
+        world.register::<physics::TrainEngine>();
+        world.register::<physics::SpeedLimit>();
+        world.register::<routing::Junction>();
+        world.register::<routing::DiamondCrossing>();
+        world.register::<signals::JunctionSignal>();
+        world.register::<signals::ApproachSignal>();
+        world.register::<signals::TrainExpectsSignal>();
+        world.register::<signals::TrainIsBlockingSignals>();
+        world.register::<signals::SpeedLimitFromNextSignal>();
+        world.register::<signals::CrossingIsReservedByTrain>();
+        world.register::<signals::JunctionIsReservedByTrain>();
+        world.register::<train::Wagon>();
+        world.register::<train::Consist>();
+        world.register::<train::TrainTrail>();
+        world.register::<train::CarOutline>();
+        world.register::<cargo::CargoStorage>();
+        world.register::<cargo::CargoProducer>();
+        world.register::<cargo::CargoConsumer>();
+        world.register::<cargo::TrainIsDwelling>();
+        world.register::<orders::TrainOrders>();
+        world.register::<routing::TrainIsInStation>();
+        world.register::<routing::TrainWantsToTravelTo>();
+        world.register::<routing::TrainRoute>();
+        world.register::<routing::TrainIsWaitingAtSignal>();
+        world.register::<collision::TrainHasCrashed>();
+        world.register::<deadlock::TrainIsGivingWay>();
+        world.register::<Role>();
+
+        world.add_resource(DeltaTime::new());
+        world.add_resource(SimClock::new());
+        world.add_resource(SimRng::from_seed(rand::random()));
+        world.add_resource(Punctuality::default());
+        world.add_resource(Crashes::default());
+        world.add_resource(Deadlocks::default());
+        world.add_resource(DeadlockResolution::default());
+        world.add_resource(Tracks::new());
+        world.add_resource(RouteCosts::default());
+
+        // Every system waits for the one before it. Most of them touch the same storages
+        // anyway, and this way they always run in the same order, so that the same
+        // scenario turns out the same way every time.
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(cargo::CargoProductionSystem, "CargoProductionSystem", &[])
+            .with(cargo::CargoConsumptionSystem, "CargoConsumptionSystem", &["CargoProductionSystem"])
+            .with(cargo::CargoHandler, "CargoHandler", &["CargoConsumptionSystem"])
+            .with(orders::Conductor, "Conductor", &["CargoHandler"])
+            .with(routing::TrainRouter, "TrainRouter", &["Conductor"])
+            .with(routing::TrainRerouter, "TrainRerouter", &["TrainRouter"])
+            .with(routing::TrainNavigator, "TrainNavigator", &["TrainRerouter"])
+            .with(signals::Fahrdienstleiter, "Fahrdienstleiter", &["TrainNavigator"])
+            .with(signals::Fahrdienstputzfrau, "Fahrdienstputzfrau", &["Fahrdienstleiter"])
+            .with(deadlock::DeadlockDetector, "DeadlockDetector", &["Fahrdienstputzfrau"])
+            .with(physics::TrainDriver, "TrainDriver", &["DeadlockDetector"])
+            .with(physics::TrainEngineSystem, "TrainEngineSystem", &["TrainDriver"])
+            .with(train::Shunter, "Shunter", &["TrainEngineSystem"])
+            .with(collision::CrashDetector, "CrashDetector", &["Shunter"])
+            .with(SignalRenderer, "SignalRenderer", &["CrashDetector"])
+            .build();
+        dispatcher.setup(&mut world.res);
+
+        Self {
+            world,
+            map:        Map::new(width, height),
+            history:    History::new(),
+            dispatcher,
+            recording:  None,
+            replaying:  ReplayQueue::new(),
+        }
+    }
+
+    /**
+     * Create a simulation with our built-in default network.
+     */
+    pub fn with_default_network(width: u32, height: u32) -> Self {
+        let mut sim = Self::new(width, height);
+        populate(&mut sim.world);
+        put_up_approach_signals(&mut sim.world);
+        sim
+    }
+
+    /**
+     * Create a simulation with the network described in a scenario file, and plant
+     * the trains it asks for.
+     */
+    pub fn from_scenario(path: &Path) -> Result<Self, ScenarioError> {
+        let text = scenario::read_file(path)?;
+        let (width, height) = scenario::read_size(&text)?.unwrap_or((640, 480));
+        let mut sim = Self::new(width, height);
+        let trains = scenario::populate_from(&mut sim.world, &text)?;
+        put_up_approach_signals(&mut sim.world);
+        for train in trains {
+            let wagons = train.wagons.unwrap_or(train::DEFAULT_WAGONS);
+            let planted = sim.plant_train_with(train.station, train.destination, wagons, train.engine);
+            if let (Some(planted), false) = (planted, train.orders.is_empty()) {
+                sim.give_timetable(planted, train.orders, train.period);
+            }
+        }
+        Ok(sim)
+    }
+
+    /**
+     * Load a simulation from a savegame file.
+     */
+    pub fn load(path: &Path) -> Result<Self, SaveGameError> {
+        savegame::load(path)
+    }
+
+    /**
+     * Write the whole simulation, including the rails on the map, to a savegame file.
+     */
+    pub fn save(&self, path: &Path) -> Result<(), SaveGameError> {
+        savegame::save(self, path)
+    }
+
+    /**
+     * Find the junction that is closest to the given position, as long as it is
+     * no further away than max_distance.
+     */
+    pub fn junction_at(&self, pos: &Position, max_distance: f64) -> Option<Entity> {
+        let entities = self.world.entities();
+        let positions = self.world.read_storage::<Position>();
+        let junctions = self.world.read_storage::<Junction>();
+        (&entities, &positions, &junctions).join()
+            .map(|(ent, junction_pos, _)| (ent, pos.distance_length_to(junction_pos)))
+            .filter(|&(_, distance)| distance < max_distance)
+            .min_by(|(_, left), (_, right)| left.partial_cmp(right).unwrap())
+            .map(|(ent, _)| ent)
+    }
+
+    /**
+     * Find the point on the rails that is closest to the given position.
+     */
+    pub fn track_at(&self, pos: &Position, max_distance: f64) -> Option<TrackHit> {
+        self.world.read_resource::<Tracks>().hit_test(pos, max_distance)
+    }
+
+    /**
+     * Throw away all track segments and lay them anew from the Junction connections.
+     * Junction.connections is what gets saved, so this is how the rails come back after
+     * loading.
+     */
+    pub fn rebuild_tracks(&mut self) {
+        let entities = self.world.entities();
+        let positions = self.world.read_storage::<Position>();
+        let junctions = self.world.read_storage::<Junction>();
+        let mut tracks = self.world.write_resource::<Tracks>();
+        tracks.clear();
+        for (left, left_pos, junction) in (&entities, &positions, &junctions).join() {
+            for &right in &junction.connections {
+                // Every rail shows up in the connections of both its ends, only lay it once.
+                if left.id() >= right.id() {
+                    continue;
+                }
+                // A savegame that was messed with may connect us to something that isn't
+                // anywhere. There are no rails to lay to nowhere.
+                let right_pos = match positions.get(right) {
+                    Some(pos) => pos,
+                    None => continue,
+                };
+                tracks.add(TrackSegment {
+                    from:  left,
+                    to:    right,
+                    start: left_pos.clone(),
+                    end:   right_pos.clone(),
+                });
+            }
+        }
+    }
+
+    /**
+     * Put a new train into the given station and send it off to any random other
+     * terminal and back, over and over. Leave it to the Router to figure out if the
+     * trip is possible.
+     * Returns None if the station is not a terminal or there's nowhere to go.
+     */
+    pub fn plant_train(&mut self, station: Entity) -> Option<Entity> {
+        let (train, destination) = self.plant_train_anywhere(station)?;
+        let positions = self.world.read_storage::<Position>();
+        let action = UserAction::PlantTrain {
+            station:     positions.get(station)?.clone(),
+            destination: positions.get(destination)?.clone(),
+        };
+        drop(positions);
+        self.record(action);
+        Some(train)
+    }
+
+    /**
+     * Roll the dice for where a train from the given station should go, and plant it.
+     * A replay has to come through here as well, or it wouldn't roll the same dice
+     * afterwards.
+     */
+    pub(crate) fn plant_train_anywhere(&mut self, station: Entity) -> Option<(Entity, Entity)> {
+        let destination = {
+            let entities = self.world.entities();
+            let junctions = self.world.read_storage::<Junction>();
+            let mut rng = self.world.write_resource::<SimRng>();
+            if !junctions.get(station).is_some_and(|j| j.is_terminal) {
+                return None;
+            }
+            (&entities, &junctions).join()
+                .filter(|(e, _j)| *e != station)
+                .filter(|(_e, j)| j.is_terminal)
+                .map(|(e, _j)| e)
+                .choose(&mut rng.rng)?
+        };
+        let train = self.plant_shuttle(station, destination)?;
+        Some((train, destination))
+    }
+
+    /**
+     * Put a new train into the given station that goes back and forth between there
+     * and the destination.
+     */
+    pub fn plant_shuttle(&mut self, station: Entity, destination: Entity) -> Option<Entity> {
+        let train = self.plant_train_to(station, destination)?;
+        self.give_orders(train, vec![
+            Order::to(destination),
+            Order::to(station),
+        ]);
+        Some(train)
+    }
+
+    /**
+     * Hand a train a list of orders that it works through over and over. It finishes
+     * whatever trip it's on first.
+     */
+    pub fn give_orders(&mut self, train: Entity, orders: Vec<Order>) {
+        self.give_timetable(train, orders, None);
+    }
+
+    /**
+     * Same as give_orders, but the train tries to keep to the arrival and departure
+     * times in its orders, and starts a new round every period seconds.
+     */
+    pub fn give_timetable(&mut self, train: Entity, orders: Vec<Order>, period: Option<f64>) {
+        let now = self.world.read_resource::<SimClock>().now;
+        let mut train_orders = TrainOrders::repeating(orders, now);
+        train_orders.period = period;
+        self.world.write_storage::<TrainOrders>()
+            .insert(train, train_orders)
+            .expect("orders got lost in the mail");
+    }
+
+    /**
+     * How well everybody has been keeping to their timetables so far.
+     */
+    pub fn punctuality(&self) -> specs::shred::Fetch<'_, Punctuality> {
+        self.world.read_resource::<Punctuality>()
+    }
+
+    /**
+     * Every time trains got stuck waiting for each other.
+     */
+    pub fn deadlocks(&self) -> specs::shred::Fetch<'_, Deadlocks> {
+        self.world.read_resource::<Deadlocks>()
+    }
+
+    /**
+     * What currently happens once trains are stuck waiting for each other.
+     */
+    pub fn deadlock_resolution(&self) -> DeadlockResolution {
+        *self.world.read_resource::<DeadlockResolution>()
+    }
+
+    /**
+     * Decide what happens once trains are stuck waiting for each other.
+     */
+    pub fn set_deadlock_resolution(&mut self, resolution: DeadlockResolution) {
+        *self.world.write_resource::<DeadlockResolution>() = resolution;
+    }
+
+    /**
+     * Put a new train into the given station that wants to go to the given destination.
+     */
+    pub fn plant_train_to(&mut self, station: Entity, destination: Entity) -> Option<Entity> {
+        self.plant_train_with(station, destination, train::DEFAULT_WAGONS, EngineKind::default())
+    }
+
+    /**
+     * Same as plant_train_to, but with as many wagons as you like behind the kind of
+     * engine you like.
+     */
+    pub fn plant_train_with(&mut self, station: Entity, destination: Entity, wagons: usize, engine: EngineKind) -> Option<Entity> {
+        let station_pos = self.world.read_storage::<Position>().get(station)?.clone();
+        println!(
+            "Planting {:?} train with {} wagons at junction {:?} heading towards {:?}",
+            engine, wagons, station, destination
+        );
+        let train = self.world.create_entity()
+            .with(station_pos)
+            .with(Role(RoleKind::Train))
+            .with(routing::TrainIsInStation { station })
+            .with(routing::TrainWantsToTravelTo { destination })
+            .with(physics::TrainEngine::of_kind(engine))
+            .build();
+        // We only have coal so far, so that's what everybody carries.
+        let wagons = (0..wagons)
+            .map(|_| {
+                self.world.create_entity()
+                    .with(Wagon::for_cargo(train, cargo::CargoKind::Coal))
+                    .with(cargo::CargoStorage::new())
+                    .build()
+            })
+            .collect();
+        self.world.write_storage::<Consist>()
+            .insert(train, Consist {
+                engine_length: train::ENGINE_LENGTH,
+                engine_mass:   train::ENGINE_MASS,
+                wagons,
+            })
+            .expect("train left without its wagons");
+        Some(train)
+    }
+
+    /**
+     * Advance the simulation by dt seconds, in as many steps as it takes for none of
+     * them to be longer than MAX_STEP.
+     */
+    pub fn step(&mut self, dt: f64) {
+        let steps = (dt / MAX_STEP).ceil().max(1.0);
+        for _ in 0..steps as usize {
+            self.play_back_due_actions();
+            self.world.write_resource::<DeltaTime>().fraction = dt / steps;
+            self.run_systems();
+        }
+    }
+
+    /**
+     * Advance the simulation by however much time has passed on the wall clock since
+     * the last update, times the speed of the clock. If the window was stuck for a
+     * while, we don't try to catch up on all of that, lest trains jump across the map.
+     *
+     * The systems always get to run in steps of exactly MAX_STEP. Whatever is left over
+     * waits for the next update, so how fast your computer is doesn't change what happens.
+     */
+    pub fn update(&mut self) {
+        let wall_time = {
+            let mut delta = self.world.write_resource::<DeltaTime>();
+            delta.update();
+            delta.fraction.min(MAX_FRAME_TIME)
+        };
+        let steps = {
+            let mut clock = self.world.write_resource::<SimClock>();
+            if !clock.paused {
+                clock.backlog += wall_time * clock.speed;
+            }
+            let steps = (clock.backlog / MAX_STEP).floor();
+            clock.backlog -= steps * MAX_STEP;
+            steps as usize
+        };
+        for _ in 0..steps {
+            self.step(MAX_STEP);
+        }
+    }
+
+    /**
+     * Start the random number generator over with the given seed. Together with stepping
+     * in fixed steps, the same scenario and seed always play out exactly the same way.
+     */
+    pub fn reseed(&mut self, seed: u64) {
+        *self.world.write_resource::<SimRng>() = SimRng::from_seed(seed);
+    }
+
+    /**
+     * While paused, move on by just one step so you can see what's going on.
+     */
+    pub fn single_step(&mut self) {
+        self.step(MAX_STEP);
+    }
+
+    pub fn toggle_pause(&mut self) -> bool {
+        let mut clock = self.world.write_resource::<SimClock>();
+        clock.toggle_pause();
+        clock.paused
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        self.world.write_resource::<SimClock>().speed = speed;
+    }
+
+    pub fn clock(&self) -> SimClock {
+        self.world.read_resource::<SimClock>().clone()
+    }
+
+    fn run_systems(&mut self) {
+        let dt = self.world.read_resource::<DeltaTime>().fraction;
+        self.world.write_resource::<SimClock>().now += dt;
+        self.dispatcher.dispatch(&self.world.res);
+        self.world.maintain();
+    }
+}
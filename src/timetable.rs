@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use specs::prelude::*;
+use serde::{Serialize, Deserialize};
+
+/**
+ * How late a train can be and still count as being on time, in seconds. Nobody is
+ * going to complain about a couple of seconds.
+ */
+pub const ON_TIME_TOLERANCE: f64 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum StopEvent {
+    Arrival,
+    Departure,
+}
+
+/**
+ * One time that a train was supposed to arrive at or leave a station, and when it
+ * actually did.
+ */
+#[derive(Debug, Clone)]
+pub struct TimetableEntry {
+    pub train:     Entity,
+    pub station:   Entity,
+    pub event:     StopEvent,
+    pub scheduled: f64,
+    pub actual:    f64,
+}
+
+impl TimetableEntry {
+    /**
+     * How many seconds late the train was. Being early makes this negative.
+     */
+    pub fn delay(&self) -> f64 {
+        self.actual - self.scheduled
+    }
+
+    pub fn is_on_time(&self) -> bool {
+        self.delay() <= ON_TIME_TOLERANCE
+    }
+}
+
+/**
+ * How well a train or a station kept to the timetable, summed up.
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DelayStats {
+    pub stops:      usize,
+    pub on_time:    usize,
+    pub mean_delay: f64,
+    pub max_delay:  f64,
+}
+
+impl DelayStats {
+    fn from_entries<'e>(entries: impl Iterator<Item = &'e TimetableEntry>) -> Self {
+        let mut stats = Self::default();
+        let mut total_delay = 0.0;
+        for entry in entries {
+            let delay = entry.delay().max(0.0);
+            stats.stops += 1;
+            if entry.is_on_time() {
+                stats.on_time += 1;
+            }
+            total_delay += delay;
+            stats.max_delay = stats.max_delay.max(delay);
+        }
+        if stats.stops > 0 {
+            stats.mean_delay = total_delay / stats.stops as f64;
+        }
+        stats
+    }
+
+    /**
+     * Which share of the stops were on time, between 0 and 1.
+     */
+    pub fn punctuality(&self) -> f64 {
+        if self.stops == 0 {
+            1.0
+        } else {
+            self.on_time as f64 / self.stops as f64
+        }
+    }
+}
+
+/**
+ * The big book where every timetabled arrival and departure is written down, so that
+ * afterwards we can find out who's to blame.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Punctuality {
+    pub entries: Vec<TimetableEntry>,
+}
+
+impl Punctuality {
+    pub fn record(&mut self, entry: TimetableEntry) {
+        if !entry.is_on_time() {
+            println!(
+                "Train {:?} {} {:?} {:.0}s late",
+                entry.train,
+                match entry.event {
+                    StopEvent::Arrival   => "arrived at",
+                    StopEvent::Departure => "left",
+                },
+                entry.station,
+                entry.delay()
+            );
+        }
+        self.entries.push(entry);
+    }
+
+    pub fn for_train(&self, train: Entity) -> DelayStats {
+        DelayStats::from_entries(self.entries.iter().filter(|entry| entry.train == train))
+    }
+
+    pub fn for_station(&self, station: Entity) -> DelayStats {
+        DelayStats::from_entries(self.entries.iter().filter(|entry| entry.station == station))
+    }
+
+    pub fn by_train(&self) -> HashMap<Entity, DelayStats> {
+        self.entries.iter()
+            .map(|entry| (entry.train, self.for_train(entry.train)))
+            .collect()
+    }
+
+    pub fn by_station(&self) -> HashMap<Entity, DelayStats> {
+        self.entries.iter()
+            .map(|entry| (entry.station, self.for_station(entry.station)))
+            .collect()
+    }
+
+    pub fn overall(&self) -> DelayStats {
+        DelayStats::from_entries(self.entries.iter())
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use specs::prelude::*;
+
+use super::physics::Position;
+
+/**
+ * Size of the cells in our spatial index, in pixels. Rails are usually a lot longer
+ * than this, so a segment ends up in a handful of cells, and a lookup around the
+ * mouse cursor only has to look at one or four of them.
+ */
+const CELL_SIZE: f64 = 32.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SegmentId(pub u32);
+
+/**
+ * A piece of track that connects two Junctions in a straight line.
+ * Junction.connections tells trains where they can go, the segment tells everyone
+ * where the rails physically are.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackSegment {
+    pub from:  Entity,
+    pub to:    Entity,
+    pub start: Position,
+    pub end:   Position,
+}
+
+impl TrackSegment {
+    pub fn length(&self) -> f64 {
+        self.start.distance_length_to(&self.end)
+    }
+
+    pub fn connects(&self, junction: Entity) -> bool {
+        self.from == junction || self.to == junction
+    }
+
+    /**
+     * Find the point on the segment that is closest to pos. Returns that point, and
+     * how far along the segment it is (0.0 at start, 1.0 at the end).
+     */
+    pub fn closest_point(&self, pos: &Position) -> (Position, f64) {
+        let dx = self.end.x - self.start.x;
+        let dy = self.end.y - self.start.y;
+        let len_sq = dx * dx + dy * dy;
+        if len_sq == 0.0 {
+            return (self.start.clone(), 0.0);
+        }
+        let t = (((pos.x - self.start.x) * dx + (pos.y - self.start.y) * dy) / len_sq)
+            .clamp(0.0, 1.0);
+        (Position::new(self.start.x + t * dx, self.start.y + t * dy), t)
+    }
+
+    /**
+     * Find the point where this segment runs across another one. Returns that point,
+     * and how far along this segment it is. Parallel segments never cross.
+     */
+    pub fn intersection(&self, other: &TrackSegment) -> Option<(Position, f64)> {
+        let (rx, ry) = (self.end.x - self.start.x, self.end.y - self.start.y);
+        let (sx, sy) = (other.end.x - other.start.x, other.end.y - other.start.y);
+        let denom = rx * sy - ry * sx;
+        if denom.abs() < 1e-9 {
+            return None;
+        }
+        let (qx, qy) = (other.start.x - self.start.x, other.start.y - self.start.y);
+        let t = (qx * sy - qy * sx) / denom;
+        let u = (qx * ry - qy * rx) / denom;
+        if (0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u) {
+            Some((self.point_at(t), t))
+        } else {
+            None
+        }
+    }
+
+    /**
+     * The point that lies a fraction t along the way from start to end.
+     */
+    pub fn point_at(&self, t: f64) -> Position {
+        Position::new(
+            self.start.x + t * (self.end.x - self.start.x),
+            self.start.y + t * (self.end.y - self.start.y)
+        )
+    }
+}
+
+/**
+ * Result of hit-testing the track: Which segment was hit, where, and how far
+ * away from the point we were looking for.
+ */
+#[derive(Debug, Clone)]
+pub struct TrackHit {
+    pub segment:  SegmentId,
+    pub point:    Position,
+    pub fraction: f64,
+    pub distance: f64,
+}
+
+/**
+ * Where a train is on the rails: On which segment, and how many pixels away from its
+ * start. The train's Position is worked out from this, so it can never leave the track.
+ *
+ * Which way the train is going isn't stored here. It's heading for the next hop on its
+ * route, which has to be one of the ends of the segment.
+ */
+#[derive(Debug, Clone)]
+pub struct TrackPosition {
+    pub segment: SegmentId,
+    pub offset:  f64,
+}
+impl Component for TrackPosition {
+    type Storage = HashMapStorage<Self>;
+}
+
+impl TrackPosition {
+    /**
+     * Standing at junction, about to set off along the rail towards next.
+     */
+    pub fn leaving(junction: Entity, next: Entity, tracks: &Tracks) -> Option<Self> {
+        let id = tracks.segment_between(junction, next)?;
+        let segment = tracks.get(id).unwrap();
+        Some(Self {
+            segment: id,
+            offset:  if segment.from == junction { 0.0 } else { segment.length() },
+        })
+    }
+
+    /**
+     * The spot on the segment that is closest to pos.
+     */
+    pub fn onto(id: SegmentId, pos: &Position, tracks: &Tracks) -> Option<Self> {
+        let segment = tracks.get(id)?;
+        let (_, fraction) = segment.closest_point(pos);
+        Some(Self {
+            segment: id,
+            offset:  fraction * segment.length(),
+        })
+    }
+
+    /**
+     * How far we still have to go along our segment to get to the given junction, if
+     * it's at one of its ends.
+     */
+    pub fn distance_to(&self, junction: Entity, tracks: &Tracks) -> Option<f64> {
+        let segment = tracks.get(self.segment)?;
+        if segment.to == junction {
+            Some(segment.length() - self.offset)
+        } else if segment.from == junction {
+            Some(self.offset)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_at(&self, junction: Entity, tracks: &Tracks) -> bool {
+        self.distance_to(junction, tracks).is_some_and(|distance| distance < 1e-6)
+    }
+
+    /**
+     * Move towards the given junction, but never past it. Returns false if that
+     * junction isn't at the end of our segment.
+     */
+    pub fn advance_towards(&mut self, junction: Entity, distance: f64, tracks: &Tracks) -> bool {
+        let segment = match tracks.get(self.segment) {
+            Some(segment) => segment,
+            None => return false,
+        };
+        if segment.to == junction {
+            self.offset = (self.offset + distance).min(segment.length());
+        } else if segment.from == junction {
+            self.offset = (self.offset - distance).max(0.0);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    pub fn world_position(&self, tracks: &Tracks) -> Option<Position> {
+        let segment = tracks.get(self.segment)?;
+        let length = segment.length();
+        Some(segment.point_at(if length > 0.0 { self.offset / length } else { 0.0 }))
+    }
+}
+
+/**
+ * A place where some segment that isn't on the map yet would run across one that is.
+ * The fraction tells how far along the new segment that is.
+ */
+#[derive(Debug, Clone)]
+pub struct TrackCrossing {
+    pub segment:  SegmentId,
+    pub point:    Position,
+    pub fraction: f64,
+}
+
+/**
+ * All the track segments on the map, plus a grid that tells us which segments are
+ * near any given point, so that hit-testing doesn't have to look at every rail.
+ *
+ * This is the one and only truth about where rails are; the map is rendered from it.
+ */
+#[derive(Default)]
+pub struct Tracks {
+    segments:    BTreeMap<SegmentId, TrackSegment>,
+    grid:        HashMap<(i32, i32), Vec<SegmentId>>,
+    // Which segments start or end at each junction. Trains ask this every time they
+    // reach a hop, so we don't want to go through all segments for that.
+    at_junction: HashMap<Entity, Vec<SegmentId>>,
+    next_id:     u32,
+}
+
+fn cell_of(x: f64, y: f64) -> (i32, i32) {
+    ((x / CELL_SIZE).floor() as i32, (y / CELL_SIZE).floor() as i32)
+}
+
+impl Tracks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    pub fn get(&self, id: SegmentId) -> Option<&TrackSegment> {
+        self.segments.get(&id)
+    }
+
+    pub fn segments(&self) -> impl Iterator<Item = (SegmentId, &TrackSegment)> {
+        self.segments.iter().map(|(&id, seg)| (id, seg))
+    }
+
+    /**
+     * Find the segment that directly connects two junctions, in either direction.
+     */
+    pub fn segment_between(&self, left: Entity, right: Entity) -> Option<SegmentId> {
+        self.segments_at(left).into_iter()
+            .find(|id| self.segments[id].connects(right))
+    }
+
+    /**
+     * All segments that start or end at the given junction.
+     */
+    pub fn segments_at(&self, junction: Entity) -> Vec<SegmentId> {
+        self.at_junction.get(&junction).cloned().unwrap_or_default()
+    }
+
+    pub fn add(&mut self, segment: TrackSegment) -> SegmentId {
+        let id = SegmentId(self.next_id);
+        self.next_id += 1;
+        self.insert(id, segment);
+        id
+    }
+
+    /**
+     * Put a segment back under an id it had before. Used when edits are undone,
+     * so that everyone who remembers the id still finds the right segment.
+     */
+    pub fn insert(&mut self, id: SegmentId, segment: TrackSegment) {
+        for cell in Self::cells_covered_by(&segment) {
+            self.grid.entry(cell).or_default().push(id);
+        }
+        self.at_junction.entry(segment.from).or_default().push(id);
+        if segment.to != segment.from {
+            self.at_junction.entry(segment.to).or_default().push(id);
+        }
+        self.segments.insert(id, segment);
+        self.next_id = self.next_id.max(id.0 + 1);
+    }
+
+    pub fn remove(&mut self, id: SegmentId) -> Option<TrackSegment> {
+        let segment = self.segments.remove(&id)?;
+        for cell in Self::cells_covered_by(&segment) {
+            if let Some(ids) = self.grid.get_mut(&cell) {
+                ids.retain(|&other| other != id);
+                if ids.is_empty() {
+                    self.grid.remove(&cell);
+                }
+            }
+        }
+        for junction in &[segment.from, segment.to] {
+            if let Some(ids) = self.at_junction.get_mut(junction) {
+                ids.retain(|&other| other != id);
+                if ids.is_empty() {
+                    self.at_junction.remove(junction);
+                }
+            }
+        }
+        Some(segment)
+    }
+
+    pub fn clear(&mut self) {
+        self.segments.clear();
+        self.grid.clear();
+        self.at_junction.clear();
+    }
+
+    /**
+     * All segments that pass within radius of pos, according to the grid. These still
+     * need to be checked for their exact distance.
+     */
+    fn candidates_near(&self, pos: &Position, radius: f64) -> HashSet<SegmentId> {
+        // Segments are only registered in the cells that the points we sampled along
+        // them fall into, and every point on a segment is within a quarter cell of
+        // one of those.
+        let radius = radius + CELL_SIZE / 4.0;
+        let (min_x, min_y) = cell_of(pos.x - radius, pos.y - radius);
+        let (max_x, max_y) = cell_of(pos.x + radius, pos.y + radius);
+        let mut candidates = HashSet::new();
+        for cx in min_x..=max_x {
+            for cy in min_y..=max_y {
+                if let Some(ids) = self.grid.get(&(cx, cy)) {
+                    candidates.extend(ids.iter().cloned());
+                }
+            }
+        }
+        candidates
+    }
+
+    /**
+     * Find all segments that pass within max_distance of pos, closest one first.
+     */
+    pub fn hits_near(&self, pos: &Position, max_distance: f64) -> Vec<TrackHit> {
+        let mut hits: Vec<TrackHit> = self.candidates_near(pos, max_distance).into_iter()
+            .map(|id| {
+                let (point, fraction) = self.segments[&id].closest_point(pos);
+                TrackHit {
+                    segment:  id,
+                    distance: point.distance_length_to(pos),
+                    point,
+                    fraction,
+                }
+            })
+            .filter(|hit| hit.distance <= max_distance)
+            .collect();
+        hits.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap().then(a.segment.cmp(&b.segment)));
+        hits
+    }
+
+    /**
+     * Find the point on the track that is closest to pos, if there is one within
+     * max_distance.
+     */
+    pub fn hit_test(&self, pos: &Position, max_distance: f64) -> Option<TrackHit> {
+        self.hits_near(pos, max_distance).into_iter().next()
+    }
+
+    /**
+     * Find all segments that the given one would run across, in the order in which
+     * we'd come across them when going from its start to its end.
+     */
+    pub fn crossings(&self, segment: &TrackSegment) -> Vec<TrackCrossing> {
+        // Any segment that we cross is registered in a cell that is next to one of ours.
+        let mut candidates = HashSet::new();
+        for (cx, cy) in Self::cells_covered_by(segment) {
+            for nx in cx - 1..=cx + 1 {
+                for ny in cy - 1..=cy + 1 {
+                    if let Some(ids) = self.grid.get(&(nx, ny)) {
+                        candidates.extend(ids.iter().cloned());
+                    }
+                }
+            }
+        }
+        let mut crossings: Vec<TrackCrossing> = candidates.into_iter()
+            .filter_map(|id| {
+                segment.intersection(&self.segments[&id]).map(|(point, fraction)| TrackCrossing {
+                    segment:  id,
+                    point,
+                    fraction,
+                })
+            })
+            .collect();
+        crossings.sort_by(|a, b| a.fraction.partial_cmp(&b.fraction).unwrap().then(a.segment.cmp(&b.segment)));
+        crossings
+    }
+
+    fn cells_covered_by(segment: &TrackSegment) -> Vec<(i32, i32)> {
+        // Walk along the segment in steps smaller than a cell, and collect every
+        // cell we pass through.
+        let steps = (segment.length() / (CELL_SIZE / 2.0)).ceil().max(1.0) as usize;
+        let mut cells = vec![];
+        for step in 0..=steps {
+            let point = segment.point_at(step as f64 / steps as f64);
+            let cell = cell_of(point.x, point.y);
+            if !cells.contains(&cell) {
+                cells.push(cell);
+            }
+        }
+        cells
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::VecDeque;
+use specs::prelude::*;
+
+use super::physics::Position;
+use super::cargo::{CargoKind, CargoStorage};
+
+/**
+ * How long things are, in pixels. Cars are coupled with a little gap between them.
+ */
+pub const ENGINE_LENGTH: f64 = 16.0;
+pub const WAGON_LENGTH:  f64 = 12.0;
+pub const COUPLING_GAP:  f64 =  2.0;
+
+/**
+ * How many wagons a train gets if nobody says otherwise.
+ */
+pub const DEFAULT_WAGONS: usize = 3;
+
+/**
+ * How heavy things are. One unit of cargo weighs one unit of mass, so a full wagon
+ * weighs three times as much as an empty one.
+ */
+pub const ENGINE_MASS:    f64 = 80.0;
+pub const WAGON_TARE:     f64 = 10.0;
+pub const WAGON_CAPACITY: f64 = 20.0;
+
+/**
+ * A wagon is an entity of its own, so that it can carry stuff around. It always knows
+ * which train it's coupled to.
+ */
+#[derive(Debug, Clone)]
+pub struct Wagon {
+    pub train:    Entity,
+    pub length:   f64,
+    pub tare:     f64,
+    pub cargo:    CargoKind,
+    pub capacity: f64,
+}
+impl Component for Wagon {
+    type Storage = HashMapStorage<Self>;
+}
+
+impl Wagon {
+    /**
+     * A plain old wagon for the given kind of cargo, like the ones we put behind every
+     * new train.
+     */
+    pub fn for_cargo(train: Entity, cargo: CargoKind) -> Self {
+        Self {
+            train,
+            length:   WAGON_LENGTH,
+            tare:     WAGON_TARE,
+            cargo,
+            capacity: WAGON_CAPACITY,
+        }
+    }
+
+    /**
+     * How much the wagon weighs with whatever it's currently carrying.
+     */
+    pub fn mass(&self, storage: Option<&CargoStorage>) -> f64 {
+        self.tare + storage.map_or(0.0, |storage| storage.quantities.values().sum())
+    }
+}
+
+/**
+ * The engine, and all the wagons that it pulls along behind it, front to back.
+ */
+#[derive(Debug, Clone)]
+pub struct Consist {
+    pub engine_length: f64,
+    pub engine_mass:   f64,
+    pub wagons:        Vec<Entity>,
+}
+impl Component for Consist {
+    type Storage = HashMapStorage<Self>;
+}
+
+impl Consist {
+    /**
+     * How far it is from the nose of the engine to the end of the last wagon.
+     */
+    pub fn length(&self, wagons: &ReadStorage<Wagon>) -> f64 {
+        self.wagons.iter()
+            .filter_map(|&wagon| wagons.get(wagon))
+            .fold(self.engine_length, |length, wagon| length + COUPLING_GAP + wagon.length)
+    }
+
+    /**
+     * How much the whole train weighs, engine, wagons, cargo and all.
+     */
+    pub fn mass(&self, wagons: &ReadStorage<Wagon>, storages: &ReadStorage<CargoStorage>) -> f64 {
+        self.wagons.iter()
+            .filter_map(|&wagon| wagons.get(wagon).map(|w| w.mass(storages.get(wagon))))
+            .fold(self.engine_mass, |mass, wagon| mass + wagon)
+    }
+}
+
+/**
+ * The junctions a train has passed most recently, the latest one first. The rails
+ * between them are where the rest of the train is, so that's what the wagons follow.
+ * We only remember as many as it takes to reach back to the end of the train.
+ */
+#[derive(Debug, Clone)]
+pub struct TrainTrail {
+    pub passed: VecDeque<Entity>,
+}
+impl Component for TrainTrail {
+    type Storage = HashMapStorage<Self>;
+}
+
+impl TrainTrail {
+    pub fn starting_at(junction: Entity) -> Self {
+        Self {
+            passed: VecDeque::from(vec![junction]),
+        }
+    }
+
+    /**
+     * The points that the trail goes through, starting at the nose of the train. Rails
+     * are straight between junctions, so that's all it takes to describe them.
+     */
+    fn points<'p>(&'p self, nose: &'p Position, positions: &'p ReadStorage<Position>) -> impl Iterator<Item = &'p Position> {
+        Some(nose).into_iter().chain(self.passed.iter().filter_map(move |&junction| positions.get(junction)))
+    }
+
+    /**
+     * How far back along the rails the given junction is from the nose of the train, if
+     * we still remember passing it.
+     */
+    pub fn distance_back_to(&self, junction: Entity, nose: &Position, positions: &ReadStorage<Position>) -> Option<f64> {
+        let idx = self.passed.iter().position(|&passed| passed == junction)?;
+        let points: Vec<&Position> = self.points(nose, positions).take(idx + 2).collect();
+        Some(points.windows(2).map(|pair| pair[0].distance_length_to(pair[1])).sum())
+    }
+
+    /**
+     * The point on the rails that is the given distance behind the nose of the train.
+     * If the trail doesn't reach back that far, that's where the train came out of a
+     * station, so we stop at the end of it.
+     */
+    pub fn point_behind(&self, nose: &Position, distance: f64, positions: &ReadStorage<Position>) -> Position {
+        let mut left = distance;
+        let mut here = nose;
+        for there in self.points(nose, positions).skip(1) {
+            let step = here.distance_length_to(there);
+            if left <= step {
+                let t = if step > 0.0 { left / step } else { 0.0 };
+                return Position::new(here.x + t * (there.x - here.x), here.y + t * (there.y - here.y));
+            }
+            left -= step;
+            here = there;
+        }
+        here.clone()
+    }
+
+    /**
+     * Forget about junctions that are so far behind that even the end of the train is
+     * past them, keeping the first one beyond the end so that we know where it is.
+     */
+    pub fn trim(&mut self, nose: &Position, length: f64, positions: &ReadStorage<Position>) {
+        let mut behind = 0.0;
+        let mut keep = 0;
+        let mut here = nose;
+        for there in self.points(nose, positions).skip(1) {
+            keep += 1;
+            behind += here.distance_length_to(there);
+            if behind >= length {
+                break;
+            }
+            here = there;
+        }
+        self.passed.truncate(keep.max(1));
+    }
+}
+
+/**
+ * Where the front and rear end of a car are, so that it can be drawn onto the rails.
+ * Both the engine and the wagons have one.
+ */
+#[derive(Debug, Clone)]
+pub struct CarOutline {
+    pub front: Position,
+    pub rear:  Position,
+}
+impl Component for CarOutline {
+    type Storage = HashMapStorage<Self>;
+}
+
+impl CarOutline {
+    /**
+     * How close this car gets to another one. Zero if they overlap or run across each other.
+     */
+    pub fn distance_to(&self, other: &CarOutline) -> f64 {
+        if self.crosses(other) {
+            return 0.0;
+        }
+        [
+            Self::point_distance(&self.front, other),
+            Self::point_distance(&self.rear, other),
+            Self::point_distance(&other.front, self),
+            Self::point_distance(&other.rear, self),
+        ].iter().cloned().fold(f64::INFINITY, f64::min)
+    }
+
+    fn crosses(&self, other: &CarOutline) -> bool {
+        let side = |from: &Position, to: &Position, pos: &Position| {
+            (to.x - from.x) * (pos.y - from.y) - (to.y - from.y) * (pos.x - from.x)
+        };
+        side(&self.front, &self.rear, &other.front) * side(&self.front, &self.rear, &other.rear) < 0.0 &&
+            side(&other.front, &other.rear, &self.front) * side(&other.front, &other.rear, &self.rear) < 0.0
+    }
+
+    fn point_distance(pos: &Position, car: &CarOutline) -> f64 {
+        let dx = car.rear.x - car.front.x;
+        let dy = car.rear.y - car.front.y;
+        let len_sq = dx * dx + dy * dy;
+        let t = if len_sq > 0.0 {
+            (((pos.x - car.front.x) * dx + (pos.y - car.front.y) * dy) / len_sq).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        pos.distance_length_to(&Position::new(car.front.x + t * dx, car.front.y + t * dy))
+    }
+}
+
+/**
+ * Once the engine has moved, the Shunter pulls all the wagons along behind it.
+ */
+pub struct Shunter;
+
+impl<'a> System<'a> for Shunter {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Consist>,
+        ReadStorage<'a, TrainTrail>,
+        ReadStorage<'a, Wagon>,
+        ReadStorage<'a, Position>,
+        WriteStorage<'a, CarOutline>,
+    );
+
+    fn run(&mut self, sys_data: Self::SystemData) {
+        let (
+            entities,
+            consists,
+            trails,
+            wagons,
+            positions,
+            mut outlines,
+        ) = sys_data;
+        for (train, consist, nose) in (&entities, &consists, &positions).join() {
+            let point_behind = |distance: f64| match trails.get(train) {
+                Some(trail) => trail.point_behind(nose, distance, &positions),
+                None => nose.clone(),
+            };
+            let _ = outlines.insert(train, CarOutline {
+                front: nose.clone(),
+                rear:  point_behind(consist.engine_length),
+            });
+            let mut behind = consist.engine_length;
+            for &wagon in &consist.wagons {
+                let length = match wagons.get(wagon) {
+                    Some(wagon) => wagon.length,
+                    None => continue,
+                };
+                let _ = outlines.insert(wagon, CarOutline {
+                    front: point_behind(behind + COUPLING_GAP),
+                    rear:  point_behind(behind + COUPLING_GAP + length),
+                });
+                behind += COUPLING_GAP + length;
+            }
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use piston_window::*;
+
+use niart::collision::Crashes;
+use niart::map::Map;
+use niart::physics::Position;
+use niart::signals::{ApproachSignal, ApproachAspect, JunctionSignal, SignalKind};
+use niart::track::Tracks;
+use niart::train::{CarOutline, Wagon};
+use specs::prelude::*;
+
+/**
+ * Put the rails on the screen, plus the one the user is currently drawing.
+ */
+pub fn render_map(map: &Map, tracks: &Tracks, c: Context, g: &mut G2d) {
+    for (_, segment) in tracks.segments() {
+        line_from_to(
+            [0., 0., 0., 1.],
+            1.0,
+            segment.start.as_f64_array(),
+            segment.end.as_f64_array(),
+            c.transform,
+            g
+        );
+    }
+    if let Some((from, to)) = map.drawing_line() {
+        line_from_to(
+            [0., 0., 0., 1.],
+            2.0,
+            from.as_f64_array(),
+            to.as_f64_array(),
+            c.transform,
+            g
+        );
+    }
+}
+
+/**
+ * Draw every engine and wagon as a thick line along the rails, from its front to its
+ * rear end. Engines are dark blue, wagons a lighter shade so you can tell them apart.
+ */
+pub fn render_cars(outlines: &ReadStorage<CarOutline>, wagons: &ReadStorage<Wagon>, c: Context, g: &mut G2d) {
+    for (outline, wagon) in (outlines, wagons.maybe()).join() {
+        line_from_to(
+            match wagon {
+                Some(_) => [0.4, 0.6, 1.,  1.],
+                None    => [0.,  0.,  0.6, 1.],
+            },
+            3.0,
+            outline.front.as_f64_array(),
+            outline.rear.as_f64_array(),
+            c.transform,
+            g
+        );
+    }
+}
+
+/**
+ * Mark every wreck that's still lying around with a big red X.
+ */
+pub fn render_crashes(crashes: &Crashes, c: Context, g: &mut G2d) {
+    for (_, crash) in crashes.active() {
+        let (x, y) = crash.at.as_f64_tuple();
+        for &(dx, dy) in &[(8., 8.), (8., -8.)] {
+            line_from_to(
+                [1., 0., 0., 1.],
+                2.0,
+                [x - dx, y - dy],
+                [x + dx, y + dy],
+                c.transform,
+                g
+            );
+        }
+    }
+}
+
+/**
+ * Path signals get a black ring around them, so you can tell them from block signals.
+ */
+pub fn render_path_signals(signals: &ReadStorage<JunctionSignal>, positions: &ReadStorage<Position>, c: Context, g: &mut G2d) {
+    for (signal, pos) in (signals, positions).join() {
+        if signal.kind != SignalKind::Path {
+            continue;
+        }
+        circle_arc(
+            [0., 0., 0., 1.],
+            1.0,
+            0.0,
+            std::f64::consts::TAU,
+            [pos.x - 8., pos.y - 8., 16., 16.],
+            c.transform,
+            g
+        );
+    }
+}
+
+/**
+ * Approach signals are little boxes with two lights, the way the real ones look: Two
+ * yellow ones say expect halt, green and yellow expect slow, and two green ones expect go.
+ */
+pub fn render_approach_signals(approaches: &ReadStorage<ApproachSignal>, positions: &ReadStorage<Position>, c: Context, g: &mut G2d) {
+    const DARK:   [f32; 4] = [0.,  0.,  0., 1.];
+    const YELLOW: [f32; 4] = [0.9, 0.9, 0., 1.];
+    const GREEN:  [f32; 4] = [0.,  1.,  0., 1.];
+    for (approach, pos) in (approaches, positions).join() {
+        let lights = match approach.aspect {
+            ApproachAspect::Dark       => [DARK,   DARK],
+            ApproachAspect::ExpectHalt => [YELLOW, YELLOW],
+            ApproachAspect::ExpectSlow => [GREEN,  YELLOW],
+            ApproachAspect::ExpectGo   => [GREEN,  GREEN],
+        };
+        rectangle(DARK, [pos.x - 4., pos.y - 3., 8., 6.], c.transform, g);
+        for (idx, light) in lights.iter().enumerate() {
+            let x = pos.x - 3. + idx as f64 * 3.;
+            ellipse(*light, [x, pos.y - 1.5, 3., 3.], c.transform, g);
+        }
+    }
+}
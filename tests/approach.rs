@@ -0,0 +1,42 @@
+use std::path::Path;
+use specs::prelude::*;
+use niart::Simulation;
+use niart::approach::SIGHTING_DISTANCE;
+use niart::physics::Position;
+use niart::signals::TrainExpectsSignal;
+use niart::train::Consist;
+
+/**
+ * The driver doesn't know what s2 is going to say until they've passed the approach
+ * signal that was put up for it, and from then on they do.
+ */
+#[test]
+fn drivers_hear_about_signals_at_the_approach_signal() {
+    let mut sim = Simulation::from_scenario(Path::new("tests/scenarios/approach.scn")).unwrap();
+    let s2 = sim.junction_at(&Position::new(440.0, 240.0), 1.0).unwrap();
+    let approach_x = 440.0 - 60.0;
+    let train = (&sim.world.entities(), &sim.world.read_storage::<Consist>()).join()
+        .map(|(train, _)| train)
+        .next()
+        .unwrap();
+
+    let (mut before, mut after) = (0, 0);
+    for _ in 0..1200 {
+        sim.step(0.05);
+        let nose = match sim.world.read_storage::<Position>().get(train) {
+            Some(pos) => pos.x,
+            None => break,
+        };
+        let expects_s2 = sim.world.read_storage::<TrainExpectsSignal>().get(train)
+            .is_some_and(|expect| expect.signal == s2);
+        if nose > 200.0 + 1.0 && nose < approach_x - 1.0 {
+            assert!(!expects_s2, "the driver knew about s2 before passing its approach signal (nose at {:.1})", nose);
+            before += 1;
+        }
+        if nose > approach_x + 1.0 && nose < 440.0 - SIGHTING_DISTANCE {
+            assert!(expects_s2, "the driver passed the approach signal for s2 without noticing (nose at {:.1})", nose);
+            after += 1;
+        }
+    }
+    assert!(before > 0 && after > 0, "the train never got that far");
+}
@@ -0,0 +1,49 @@
+use std::path::Path;
+use specs::prelude::*;
+use niart::Simulation;
+use niart::physics::Position;
+use niart::routing::TrainIsInStation;
+use niart::signals::SignalIsBlockedByTrain;
+use niart::train::{Consist, Wagon};
+
+/**
+ * The line runs straight from west to east, so the tail of the train is exactly as far
+ * behind the nose as the train is long, once it's all out of the station.
+ */
+#[test]
+fn blocks_are_free_once_the_tail_is_out() {
+    let mut sim = Simulation::from_scenario(Path::new("tests/scenarios/long_train.scn")).unwrap();
+    let (train, length) = {
+        let wagons = sim.world.read_storage::<Wagon>();
+        (&sim.world.entities(), &sim.world.read_storage::<Consist>()).join()
+            .map(|(train, consist)| (train, consist.length(&wagons)))
+            .next()
+            .unwrap()
+    };
+    assert!(length > 100.0, "that train is too short to tell");
+    let s1 = sim.junction_at(&Position::new(150.0, 240.0), 1.0).unwrap();
+    let s2_x = 300.0;
+
+    let mut held_on_to_it = false;
+    for _ in 0..2400 {
+        sim.step(0.05);
+        if sim.world.read_storage::<TrainIsInStation>().get(train).is_some() {
+            break;
+        }
+        let nose = sim.world.read_storage::<Position>().get(train).unwrap().x;
+        let tail = nose - length;
+        let blocked = sim.world.read_storage::<SignalIsBlockedByTrain>().get(s1)
+            .is_some_and(|blockage| blockage.train == train);
+        if nose > 150.0 + 1.0 && tail < s2_x - 1.0 {
+            assert!(blocked, "the block behind s1 was freed with the train still in it (nose at {:.1})", nose);
+        }
+        if tail > s2_x + 1.0 {
+            assert!(!blocked, "the block behind s1 is still blocked with the train long gone (nose at {:.1})", nose);
+        }
+        if nose > s2_x + 1.0 && blocked {
+            held_on_to_it = true;
+        }
+    }
+    assert!(sim.world.read_storage::<TrainIsInStation>().get(train).is_some(), "train never made it");
+    assert!(held_on_to_it, "the nose never got past the next signal with the tail still behind it");
+}
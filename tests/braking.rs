@@ -0,0 +1,75 @@
+use std::path::Path;
+use specs::prelude::*;
+use niart::Simulation;
+use niart::physics::{EngineKind, Position, TrainEngine};
+use niart::signals::{JunctionSignal, SignalState, SpeedLimitFromNextSignal};
+use niart::train::Consist;
+
+// From the start of the block at s1 to the next signal, minus what the Fahrdienstleiter
+// keeps as a margin.
+const BLOCK_LENGTH: f64 = 67.0 - 15.0;
+
+/**
+ * Send a train of the given kind from west to east, and see what the signal at the start
+ * of the short block tells it, and how fast it's allowed to go in there.
+ */
+fn signalled(engine: EngineKind) -> (SignalState, f64, TrainEngine) {
+    let mut sim = Simulation::from_scenario(Path::new("tests/scenarios/short_block.scn")).unwrap();
+    let west = sim.junction_at(&Position::new(40.0, 240.0), 1.0).unwrap();
+    let east = sim.junction_at(&Position::new(600.0, 240.0), 1.0).unwrap();
+    let s1 = sim.junction_at(&Position::new(100.0, 240.0), 1.0).unwrap();
+    let train = sim.plant_train_with(west, east, 1, engine).unwrap();
+    for _ in 0..5 {
+        sim.step(0.05);
+    }
+    let state = sim.world.read_storage::<JunctionSignal>().get(s1).unwrap().signal_state.clone();
+    let limit = sim.world.read_storage::<SpeedLimitFromNextSignal>().get(train).unwrap().vmax;
+    let engine = sim.world.read_storage::<TrainEngine>().get(train).unwrap().clone();
+    (state, limit, engine)
+}
+
+#[test]
+fn every_train_gets_a_speed_limit_it_can_stop_from() {
+    for &kind in &[EngineKind::Freight, EngineKind::HeavyFreight, EngineKind::Passenger] {
+        let (_, limit, engine) = signalled(kind);
+        let expected = (2.0 * BLOCK_LENGTH * engine.braking).sqrt();
+        assert!((limit - expected).abs() < 1e-6, "{:?}: limit {} instead of {}", kind, limit, expected);
+        // Braking from there down to zero takes no more than the block has to offer.
+        assert!(limit * limit / (2.0 * engine.braking) <= BLOCK_LENGTH + 1e-6);
+    }
+}
+
+#[test]
+fn aspects_depend_on_what_the_train_can_do() {
+    let (freight, freight_limit, _) = signalled(EngineKind::Freight);
+    let (heavy, heavy_limit, _) = signalled(EngineKind::HeavyFreight);
+    let (passenger, passenger_limit, _) = signalled(EngineKind::Passenger);
+    assert_eq!(freight, SignalState::Go);
+    // Heavy trains brake so badly that they have to take it slow...
+    assert_eq!(heavy, SignalState::Slow);
+    assert!(heavy_limit < freight_limit);
+    // ... and passenger trains may go faster than freight trains, but not nearly as fast
+    // as they could.
+    assert_eq!(passenger, SignalState::Slow);
+    assert!(passenger_limit > freight_limit);
+}
+
+#[test]
+fn scenarios_pick_the_engine() {
+    let sim = Simulation::from_scenario(Path::new("tests/scenarios/engines.scn")).unwrap();
+    let engines = sim.world.read_storage::<TrainEngine>();
+    let consists = sim.world.read_storage::<Consist>();
+    let mut trains: Vec<(f64, f64, usize)> = (&engines, &consists).join()
+        .map(|(engine, consist)| (engine.vmax, engine.braking, consist.wagons.len()))
+        .collect();
+    trains.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let expect = |kind, wagons| {
+        let engine = TrainEngine::of_kind(kind);
+        (engine.vmax, engine.braking, wagons)
+    };
+    assert_eq!(trains, vec![
+        expect(EngineKind::HeavyFreight, 3),
+        expect(EngineKind::Freight, 4),
+        expect(EngineKind::Passenger, 2),
+    ]);
+}
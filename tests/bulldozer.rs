@@ -0,0 +1,46 @@
+use std::path::Path;
+use specs::prelude::*;
+use niart::Simulation;
+use niart::orders::TrainOrders;
+use niart::physics::{Position, TrainEngine};
+use niart::routing::{TrainRoute, TrainWantsToTravelTo};
+use niart::signals::JunctionIsReservedByTrain;
+use niart::train::Consist;
+
+fn run(sim: &mut Simulation, seconds: f64) {
+    for _ in 0..(seconds / 0.05) as usize {
+        sim.step(0.05);
+    }
+}
+
+/**
+ * Digging up the only line to where a train is going leaves it with nowhere to go. It
+ * should stop and let go of everything it wanted, not least the switch it had reserved.
+ */
+#[test]
+fn stranded_train_lets_go_of_its_switches() {
+    let mut sim = Simulation::from_scenario(Path::new("tests/scenarios/bulldozer.scn")).unwrap();
+    let train = (&sim.world.entities(), &sim.world.read_storage::<Consist>()).join()
+        .map(|(train, _consist)| train)
+        .next()
+        .unwrap();
+    let switch = sim.junction_at(&Position::new(320.0, 240.0), 1.0).unwrap();
+    run(&mut sim, 2.0);
+    assert_eq!(sim.world.read_storage::<JunctionIsReservedByTrain>().get(switch).map(|rsvp| rsvp.train), Some(train));
+
+    // The rail from the switch to the second signal.
+    assert!(sim.world.read_storage::<TrainOrders>().contains(train));
+    assert!(sim.bulldoze(&Position::new(380.0, 240.0)));
+    assert!(!sim.world.read_storage::<TrainRoute>().contains(train));
+    assert!(!sim.world.read_storage::<TrainWantsToTravelTo>().contains(train));
+    assert!(!sim.world.read_storage::<TrainOrders>().contains(train));
+    assert_eq!(sim.world.read_storage::<JunctionIsReservedByTrain>().count(), 0);
+
+    // And nobody comes along to pick any of it back up.
+    let stopped_at = sim.world.read_storage::<Position>().get(train).unwrap().clone();
+    run(&mut sim, 5.0);
+    assert_eq!(sim.world.read_storage::<Position>().get(train), Some(&stopped_at));
+    assert_eq!(sim.world.read_storage::<TrainEngine>().get(train).unwrap().speed, 0.0);
+    assert!(!sim.world.read_storage::<TrainRoute>().contains(train));
+    assert_eq!(sim.world.read_storage::<JunctionIsReservedByTrain>().count(), 0);
+}
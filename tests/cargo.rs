@@ -0,0 +1,69 @@
+use specs::prelude::*;
+use niart::{scenario, Simulation};
+use niart::cargo::{CargoKind, CargoStorage, TrainIsDwelling, DWELL_TIME_PER_UNIT};
+use niart::physics::{EngineKind, Position};
+use niart::routing::TrainIsInStation;
+use niart::train::{Consist, WAGON_CAPACITY};
+
+const LINE: &str = "
+    industry mine  coal_mine    40 240
+    industry plant power_plant 600 240
+    produces mine  coal 0.1
+    consumes plant coal 0.05
+    junction ps 610 250
+    signal ps
+    connect plant ps
+    connect mine plant
+";
+
+fn coal(sim: &Simulation, ent: Entity) -> f64 {
+    sim.world.read_storage::<CargoStorage>().get(ent)
+        .and_then(|storage| storage.quantities.get(&CargoKind::Coal).cloned())
+        .unwrap_or(0.0)
+}
+
+/**
+ * A mine with less coal than the train has room for. The train takes what there is,
+ * no wagon gets more than it can hold, and all of it comes out again at the plant.
+ */
+#[test]
+fn cargo_goes_where_there_is_room_for_it() {
+    let mut sim = Simulation::new(640, 480);
+    scenario::populate_from(&mut sim.world, LINE).unwrap();
+    let mine = sim.junction_at(&Position::new(40.0, 240.0), 1.0).unwrap();
+    let plant = sim.junction_at(&Position::new(600.0, 240.0), 1.0).unwrap();
+    sim.world.write_storage::<CargoStorage>().get_mut(mine).unwrap().quantities.insert(CargoKind::Coal, 30.0);
+    let train = sim.plant_train_with(mine, plant, 3, EngineKind::Freight).unwrap();
+    let wagons = sim.world.read_storage::<Consist>().get(train).unwrap().wagons.clone();
+    let in_wagons = |sim: &Simulation| wagons.iter().map(|&wagon| coal(sim, wagon)).collect::<Vec<_>>();
+
+    sim.step(0.05);
+    // The mine digs up a little more in the meantime.
+    let loaded = in_wagons(&sim);
+    assert_eq!(loaded[0], WAGON_CAPACITY);
+    assert!((loaded[1] - 10.0).abs() < 0.01, "second wagon got {}", loaded[1]);
+    assert_eq!(loaded[2], 0.0);
+    assert!(coal(&sim, mine).abs() < 0.01, "left {} behind", coal(&sim, mine));
+    let dwell = sim.world.read_storage::<TrainIsDwelling>().get(train).unwrap().remaining;
+    assert!((dwell - 30.0 * DWELL_TIME_PER_UNIT).abs() < 0.1, "dwelling for {}s", dwell);
+
+    let mut before_unloading = (0.0, 0.0);
+    for _ in 0..2400 {
+        let total: f64 = in_wagons(&sim).iter().sum();
+        if total == 0.0 {
+            break;
+        }
+        before_unloading = (total, coal(&sim, plant));
+        sim.step(0.05);
+        for amount in in_wagons(&sim) {
+            assert!(amount <= WAGON_CAPACITY + 1e-9, "a wagon is carrying {}", amount);
+        }
+        assert!(coal(&sim, mine) > -0.01, "the mine gave away coal it didn't have");
+    }
+    assert_eq!(sim.world.read_storage::<TrainIsInStation>().get(train).map(|in_station| in_station.station), Some(plant));
+    assert_eq!(in_wagons(&sim), vec![0.0, 0.0, 0.0]);
+    let (delivered, stock) = before_unloading;
+    // It topped up with what the mine dug up while it waited.
+    assert!(delivered > 30.0 && delivered < 31.0, "delivered {}", delivered);
+    assert!((coal(&sim, plant) - (stock + delivered)).abs() < 0.01, "the plant got {} instead", coal(&sim, plant) - stock);
+}
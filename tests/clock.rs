@@ -0,0 +1,91 @@
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use specs::prelude::*;
+use niart::Simulation;
+use niart::physics::{Position, TrainEngine};
+use niart::simulation::MAX_STEP;
+
+fn start() -> Simulation {
+    Simulation::from_scenario(Path::new("scenarios/coal.scn")).unwrap()
+}
+
+type State = (f64, Vec<(f64, f64)>, Vec<f64>);
+
+/**
+ * What time it is, where everything is, and how fast the trains are going.
+ */
+fn state(sim: &Simulation) -> State {
+    let positions = (&sim.world.read_storage::<Position>()).join()
+        .map(|pos| pos.as_f64_tuple())
+        .collect();
+    let speeds = (&sim.world.read_storage::<TrainEngine>()).join()
+        .map(|engine| engine.speed)
+        .collect();
+    (sim.clock().now, positions, speeds)
+}
+
+fn steps_of(sim: &Simulation) -> usize {
+    let steps = sim.clock().now / MAX_STEP;
+    assert!((steps - steps.round()).abs() < 1e-6, "the clock is at {}, in between two steps", sim.clock().now);
+    steps.round() as usize
+}
+
+#[test]
+fn big_steps_are_cut_into_small_ones() {
+    let mut big = start();
+    let mut small = start();
+    for _ in 0..30 {
+        big.step(1.0);
+    }
+    for _ in 0..600 {
+        small.step(MAX_STEP);
+    }
+    assert_eq!(state(&big), state(&small));
+}
+
+/**
+ * However fast the clock runs, the systems get the same steps. It just gets through more
+ * of them for every frame.
+ */
+#[test]
+fn speeding_up_doesnt_change_what_happens() {
+    let mut fast = start();
+    fast.set_speed(8.0);
+    while fast.clock().now < 10.0 {
+        thread::sleep(Duration::from_millis(5));
+        fast.update();
+    }
+    let mut steady = start();
+    for _ in 0..steps_of(&fast) {
+        steady.step(MAX_STEP);
+    }
+    assert_eq!(state(&fast), state(&steady));
+}
+
+#[test]
+fn paused_means_paused() {
+    let mut paused = start();
+    for _ in 0..100 {
+        paused.step(MAX_STEP);
+    }
+    assert!(paused.toggle_pause());
+    let before = state(&paused);
+    for _ in 0..5 {
+        thread::sleep(Duration::from_millis(20));
+        paused.update();
+    }
+    assert_eq!(state(&paused), before);
+
+    // Stepping through it by hand is the same as letting it run.
+    for _ in 0..20 {
+        paused.single_step();
+    }
+    assert!(paused.clock().paused);
+    let mut running = start();
+    for _ in 0..120 {
+        running.step(MAX_STEP);
+    }
+    assert_eq!(steps_of(&paused), 120);
+    assert_eq!(state(&paused), state(&running));
+}
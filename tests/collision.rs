@@ -0,0 +1,67 @@
+use std::path::Path;
+use specs::prelude::*;
+use niart::Simulation;
+use niart::collision::{Crashes, TrainHasCrashed};
+use niart::physics::{Position, TrainEngine};
+use niart::routing::TrainRoute;
+use niart::train::Consist;
+
+fn run(sim: &mut Simulation, seconds: f64) {
+    for _ in 0..(seconds / 0.05) as usize {
+        sim.step(0.05);
+    }
+}
+
+fn trains(sim: &Simulation) -> Vec<(Entity, Position)> {
+    let entities = sim.world.entities();
+    let consists = sim.world.read_storage::<Consist>();
+    let positions = sim.world.read_storage::<Position>();
+    (&entities, &consists, &positions).join()
+        .map(|(train, _consist, pos)| (train, pos.clone()))
+        .collect()
+}
+
+/**
+ * Two trains on the same track going opposite ways. Once they meet, neither of them goes
+ * anywhere anymore, and nobody else gets past them until the wreck is hauled away.
+ */
+#[test]
+fn wrecks_stay_put_until_they_are_cleared() {
+    let mut sim = Simulation::from_scenario(Path::new("tests/scenarios/head_on.scn")).unwrap();
+    let west = sim.junction_at(&Position::new(40.0, 240.0), 1.0).unwrap();
+    let east = sim.junction_at(&Position::new(600.0, 240.0), 1.0).unwrap();
+    let a = sim.junction_at(&Position::new(200.0, 240.0), 1.0).unwrap();
+    let b = sim.junction_at(&Position::new(440.0, 240.0), 1.0).unwrap();
+    run(&mut sim, 15.0);
+    assert_eq!(sim.crashes().log.len(), 1);
+
+    let wrecks = trains(&sim);
+    assert_eq!(wrecks.len(), 2);
+    {
+        let crashed = sim.world.read_storage::<TrainHasCrashed>();
+        let engines = sim.world.read_storage::<TrainEngine>();
+        let routes = sim.world.read_storage::<TrainRoute>();
+        for &(train, _) in &wrecks {
+            assert!(crashed.contains(train));
+            assert_eq!(engines.get(train).unwrap().speed, 0.0);
+            assert!(!routes.contains(train), "a wreck still has somewhere to be");
+        }
+    }
+    assert!(sim.world.read_resource::<Crashes>().is_blocked(a, b));
+
+    // They don't budge, and there's no way round them.
+    run(&mut sim, 10.0);
+    assert_eq!(trains(&sim), wrecks);
+    let stuck = sim.plant_train_to(west, east).unwrap();
+    run(&mut sim, 1.0);
+    assert!(!sim.world.entities().is_alive(stuck), "found a way through the wreck");
+
+    let crash = sim.crash_at(&sim.crashes().log[0].at.clone(), 1.0).unwrap();
+    assert!(sim.clear_crash(crash));
+    assert!(!sim.clear_crash(crash));
+    assert!(!sim.world.read_resource::<Crashes>().is_blocked(a, b));
+    let through = sim.plant_train_to(west, east).unwrap();
+    run(&mut sim, 1.0);
+    assert!(sim.world.read_storage::<TrainRoute>().contains(through));
+    assert_eq!(trains(&sim).len(), 1);
+}
@@ -0,0 +1,90 @@
+use niart::{scenario, Simulation};
+use niart::cargo::{CargoKind, CargoStorage};
+use niart::physics::{EngineKind, Position, TrainEngine};
+use niart::train::{Consist, WAGON_CAPACITY};
+
+const LINE: &str = "
+    industry west power_plant  40 240
+    industry east power_plant 600 240
+    junction es 610 250
+    signal es
+    connect east es
+    connect west east
+";
+
+/**
+ * Send a freight train with the given number of wagons on its way, filled up to the
+ * given share of their capacity, and see how fast it's going after every step.
+ */
+fn speeds(wagons: usize, load: f64, steps: usize) -> Vec<f64> {
+    let mut sim = Simulation::new(640, 480);
+    scenario::populate_from(&mut sim.world, LINE).unwrap();
+    let west = sim.junction_at(&Position::new(40.0, 240.0), 1.0).unwrap();
+    let east = sim.junction_at(&Position::new(600.0, 240.0), 1.0).unwrap();
+    let train = sim.plant_train_with(west, east, wagons, EngineKind::Freight).unwrap();
+    {
+        let consists = sim.world.read_storage::<Consist>();
+        let mut storages = sim.world.write_storage::<CargoStorage>();
+        for &wagon in &consists.get(train).unwrap().wagons {
+            storages.get_mut(wagon).unwrap().quantities.insert(CargoKind::Coal, load * WAGON_CAPACITY);
+        }
+    }
+    (0..steps)
+        .map(|_| {
+            sim.step(0.05);
+            sim.world.read_storage::<TrainEngine>().get(train).unwrap().speed
+        })
+        .collect()
+}
+
+/**
+ * How fast the train is going a few seconds after setting off.
+ */
+fn speed_after_setting_off(wagons: usize, load: f64) -> f64 {
+    let speed = *speeds(wagons, load, 60).last().unwrap();
+    assert!(speed > 0.0, "the train didn't get going at all");
+    speed
+}
+
+#[test]
+fn longer_trains_are_slower_to_get_going() {
+    let engine_only = speed_after_setting_off(0, 0.0);
+    let short = speed_after_setting_off(1, 0.0);
+    let long = speed_after_setting_off(8, 0.0);
+    assert!(engine_only > short, "{} vs {}", engine_only, short);
+    assert!(short > long, "{} vs {}", short, long);
+}
+
+#[test]
+fn full_wagons_are_heavier_than_empty_ones() {
+    let empty = speed_after_setting_off(4, 0.0);
+    let half = speed_after_setting_off(4, 0.5);
+    let full = speed_after_setting_off(4, 1.0);
+    assert!(empty > half, "{} vs {}", empty, half);
+    assert!(half > full, "{} vs {}", half, full);
+}
+
+/**
+ * Pulling flat out until the very last moment would take the train past its top speed,
+ * and then it'd have to brake, and then pull again. It's supposed to just get there and
+ * stay there.
+ */
+#[test]
+fn trains_settle_at_their_top_speed() {
+    let vmax = TrainEngine::of_kind(EngineKind::Freight).vmax;
+    let steps_until_vmax = |speeds: &[f64]| speeds.iter().position(|&speed| (speed - vmax).abs() < 1e-9);
+    let empty = speeds(4, 0.0, 600);
+    let full = speeds(4, 1.0, 600);
+    let (empty_at, full_at) = (steps_until_vmax(&empty).unwrap(), steps_until_vmax(&full).unwrap());
+    assert!(empty_at < full_at, "the full train got up to speed first ({} vs {} steps)", full_at, empty_at);
+
+    for speeds in [&empty, &full] {
+        let reached = steps_until_vmax(speeds).unwrap();
+        assert!(speeds[..=reached].windows(2).all(|pair| pair[1] >= pair[0]), "slowed down before getting up to speed");
+        // Until it pulls into the station, that is.
+        let cruising = speeds[reached..].iter().take_while(|&&speed| speed > vmax / 2.0);
+        for &speed in cruising {
+            assert!((speed - vmax).abs() < 1e-9, "going {} instead of {}", speed, vmax);
+        }
+    }
+}
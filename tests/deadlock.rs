@@ -0,0 +1,90 @@
+use std::collections::HashSet;
+use std::path::Path;
+use specs::prelude::*;
+use niart::Simulation;
+use niart::deadlock::DeadlockResolution;
+use niart::physics::Position;
+use niart::routing::{TrainIsInStation, TrainRoute};
+
+const DEADLOCK: &str = "tests/scenarios/deadlock.scn";
+const BYPASS: &str = "tests/scenarios/deadlock_bypass.scn";
+
+/**
+ * Run the simulation for a while, and tell where the trains stopped on the way.
+ */
+fn run(sim: &mut Simulation, seconds: f64) -> HashSet<Entity> {
+    let mut visited = HashSet::new();
+    for _ in 0..(seconds / 0.05) as usize {
+        sim.step(0.05);
+        visited.extend((&sim.world.read_storage::<TrainIsInStation>()).join().map(|in_station| in_station.station));
+    }
+    visited
+}
+
+fn station(sim: &Simulation, x: f64, y: f64) -> Entity {
+    sim.junction_at(&Position::new(x, y), 1.0).unwrap()
+}
+
+/**
+ * Both trains get where they were headed, so the deadlock really is over.
+ */
+fn assert_both_get_through(sim: &mut Simulation) {
+    let east = station(sim, 600.0, 200.0);
+    let west2 = station(sim, 40.0, 280.0);
+    let visited = run(sim, 60.0);
+    assert!(visited.contains(&east), "the train from the west never made it east");
+    assert!(visited.contains(&west2), "the train from the east never made it west");
+    assert!(sim.deadlocks().log[0].over_at.is_some());
+}
+
+#[test]
+fn deadlocks_are_only_reported_if_you_say_so() {
+    let mut sim = Simulation::from_scenario(Path::new(DEADLOCK)).unwrap();
+    run(&mut sim, 15.0);
+    assert_eq!(sim.deadlocks().current().count(), 1);
+    run(&mut sim, 30.0);
+    assert_eq!(sim.deadlocks().log.len(), 1, "the same deadlock got reported twice");
+    assert_eq!(sim.deadlocks().log[0].resolved_by, None);
+    assert!(sim.deadlocks().log[0].over_at.is_none());
+
+    // Changing your mind takes care of the one we're already stuck in.
+    sim.set_deadlock_resolution(DeadlockResolution::RollBack);
+    run(&mut sim, 0.1);
+    assert_eq!(sim.deadlocks().log[0].resolved_by, Some(DeadlockResolution::RollBack));
+    assert_both_get_through(&mut sim);
+}
+
+#[test]
+fn rolling_back_breaks_the_deadlock() {
+    let mut sim = Simulation::from_scenario(Path::new(DEADLOCK)).unwrap();
+    sim.set_deadlock_resolution(DeadlockResolution::RollBack);
+    run(&mut sim, 15.0);
+    assert!(!sim.deadlocks().log.is_empty(), "the trains never ran into each other");
+    assert_eq!(sim.deadlocks().log[0].resolved_by, Some(DeadlockResolution::RollBack));
+    assert_both_get_through(&mut sim);
+}
+
+#[test]
+fn rerouting_goes_round_the_deadlock() {
+    let mut sim = Simulation::from_scenario(Path::new(BYPASS)).unwrap();
+    let z = sim.junction_at(&Position::new(320.0, 140.0), 1.0).unwrap();
+    sim.set_deadlock_resolution(DeadlockResolution::Reroute);
+    run(&mut sim, 15.0);
+    assert!(!sim.deadlocks().log.is_empty(), "the trains never ran into each other");
+    assert_eq!(sim.deadlocks().log[0].resolved_by, Some(DeadlockResolution::Reroute));
+    assert!(
+        (&sim.world.read_storage::<TrainRoute>()).join().any(|route| route.hops.contains(&z)),
+        "nobody took the long way round"
+    );
+    assert_both_get_through(&mut sim);
+}
+
+#[test]
+fn rerouting_falls_back_to_rolling_back() {
+    let mut sim = Simulation::from_scenario(Path::new(DEADLOCK)).unwrap();
+    sim.set_deadlock_resolution(DeadlockResolution::Reroute);
+    run(&mut sim, 15.0);
+    assert!(!sim.deadlocks().log.is_empty(), "the trains never ran into each other");
+    assert_eq!(sim.deadlocks().log[0].resolved_by, Some(DeadlockResolution::RollBack));
+    assert_both_get_through(&mut sim);
+}
@@ -0,0 +1,154 @@
+use std::path::Path;
+use specs::prelude::*;
+use niart::{savegame, Simulation};
+use niart::orders::{Order, TrainOrders, WaitFor};
+use niart::physics::Position;
+use niart::replay::{StartingPoint, UserAction};
+use niart::signals::SignalKind;
+use niart::timetable::StopEvent;
+use niart::train::Consist;
+
+const SCENARIO: &str = "scenarios/coal.scn";
+const STEP: f64 = 0.05;
+
+// Every terminal that's connected to the rest, so that trains planted there can go
+// somewhere. Trains leave the coal mine first, so they don't get planted on each other.
+const TERMINALS: [(f64, f64); 3] = [(40.0, 45.0), (600.0, 460.0), (600.0, 130.0)];
+
+fn terminal(sim: &Simulation, which: usize) -> Entity {
+    let (x, y) = TERMINALS[which % TERMINALS.len()];
+    sim.junction_at(&Position::new(x, y), 1.0).unwrap()
+}
+
+fn start(seed: u64) -> Simulation {
+    let mut sim = Simulation::from_scenario(Path::new(SCENARIO)).unwrap();
+    sim.reseed(seed);
+    sim
+}
+
+fn run(sim: &mut Simulation, seconds: f64) {
+    for _ in 0..(seconds / STEP) as usize {
+        sim.step(STEP);
+    }
+}
+
+/**
+ * Let the dice pick where a new train from the coal mine goes, and have it keep to a
+ * timetable so there's something to be punctual about.
+ */
+fn plant_timetabled(sim: &mut Simulation, which: usize) -> Position {
+    let station = terminal(sim, which);
+    let train = sim.plant_train(station).unwrap();
+    let destination = sim.world.read_storage::<TrainOrders>().get(train).unwrap().orders[0].destination;
+    sim.give_timetable(train, vec![
+        Order { destination, wait_for: WaitFor::Nothing, arrival: Some(40.0) },
+        Order { destination: station, wait_for: WaitFor::DepartureTime(10.0), arrival: Some(90.0) },
+    ], Some(120.0));
+    let position = sim.world.read_storage::<Position>().get(destination).unwrap().clone();
+    position
+}
+
+type Snapshot = (Vec<Position>, Vec<(StopEvent, f64, f64)>);
+
+/**
+ * Where every train is, and everything the timetable keeper wrote down.
+ */
+fn snapshot(sim: &Simulation) -> Snapshot {
+    let consists = sim.world.read_storage::<Consist>();
+    let positions = sim.world.read_storage::<Position>();
+    let trains = (&consists, &positions).join()
+        .map(|(_consist, pos)| pos.clone())
+        .collect();
+    let timetable = sim.punctuality().entries.iter()
+        .map(|entry| (entry.event, entry.scheduled, entry.actual))
+        .collect();
+    (trains, timetable)
+}
+
+fn play(seed: u64) -> (Vec<Position>, Snapshot) {
+    let mut sim = start(seed);
+    // The dice may well send everybody else off to where there's no track, but the train
+    // from the scenario always has somewhere to be on time for.
+    let coal_train = (&sim.world.entities(), &sim.world.read_storage::<Consist>()).join()
+        .map(|(train, _consist)| train)
+        .next()
+        .unwrap();
+    let (mine, top) = (terminal(&sim, 0), terminal(&sim, 2));
+    sim.give_timetable(coal_train, vec![
+        Order { destination: top, wait_for: WaitFor::Nothing, arrival: Some(40.0) },
+        Order { destination: mine, wait_for: WaitFor::DepartureTime(100.0), arrival: Some(80.0) },
+    ], Some(120.0));
+    let mut destinations = vec![];
+    for which in 0..3 {
+        destinations.push(plant_timetabled(&mut sim, which));
+        run(&mut sim, 20.0);
+    }
+    run(&mut sim, 100.0);
+    (destinations, snapshot(&sim))
+}
+
+#[test]
+fn same_seed_plays_out_the_same() {
+    let (destinations, (trains, timetable)) = play(42);
+    assert!(!timetable.is_empty(), "nobody kept to the timetable, so there's nothing to compare");
+    assert_eq!((destinations, (trains, timetable)), play(42));
+}
+
+#[test]
+fn loaded_game_rolls_the_same_dice() {
+    let mut unsaved = start(7);
+    let mut loaded = start(7);
+    plant_timetabled(&mut unsaved, 1);
+    plant_timetabled(&mut loaded, 1);
+    run(&mut unsaved, 20.0);
+    run(&mut loaded, 20.0);
+    let mut loaded = savegame::load_from_str(&savegame::save_to_string(&loaded).unwrap()).unwrap();
+    let unsaved_destinations: Vec<Position> = (0..10).map(|_| plant_timetabled(&mut unsaved, 2)).collect();
+    let loaded_destinations: Vec<Position> = (0..10).map(|_| plant_timetabled(&mut loaded, 2)).collect();
+    assert_eq!(unsaved_destinations, loaded_destinations);
+}
+
+#[test]
+fn replaying_a_recording_plays_out_the_same() {
+    let mut live = start(3);
+    live.start_recording(StartingPoint::Scenario(SCENARIO.into()));
+    for which in 0..3 {
+        let station = terminal(&live, which);
+        live.plant_train(station).unwrap();
+        run(&mut live, 15.0);
+    }
+    assert!(live.perform(UserAction::PlaceSignal {
+        junction: Position::new(330.0, 240.0),
+        kind:     SignalKind::Block,
+    }));
+    run(&mut live, 60.0);
+    let recording = live.recording().unwrap().clone();
+
+    let mut replayed = Simulation::from_recording(&recording).unwrap();
+    run(&mut replayed, 3.0 * 15.0 + 60.0);
+    assert!(!replayed.is_replaying());
+    assert_eq!(snapshot(&live), snapshot(&replayed));
+}
+
+/**
+ * A train planted by hand rolls the dice for where it goes. The replay has to roll them
+ * too, or every roll after that comes up differently.
+ */
+#[test]
+fn replay_rolls_the_dice_for_hand_planted_trains() {
+    let mut live = start(11);
+    live.start_recording(StartingPoint::Scenario(SCENARIO.into()));
+    for which in 0..2 {
+        let station = terminal(&live, which);
+        live.plant_train(station).unwrap();
+        run(&mut live, 15.0);
+    }
+    let recording = live.recording().unwrap().clone();
+
+    let mut replayed = Simulation::from_recording(&recording).unwrap();
+    run(&mut replayed, 2.0 * 15.0);
+    assert!(!replayed.is_replaying());
+    let live_destinations: Vec<Position> = (0..10).map(|_| plant_timetabled(&mut live, 2)).collect();
+    let replayed_destinations: Vec<Position> = (0..10).map(|_| plant_timetabled(&mut replayed, 2)).collect();
+    assert_eq!(live_destinations, replayed_destinations);
+}
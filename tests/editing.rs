@@ -0,0 +1,137 @@
+use std::path::Path;
+use specs::prelude::*;
+use niart::Simulation;
+use niart::editing::CrossingKind;
+use niart::physics::Position;
+use niart::routing::{DiamondCrossing, Junction, TrainIsInStation, TrainRoute};
+use niart::track::{TrackPosition, Tracks};
+use niart::train::Consist;
+
+fn scenario() -> Simulation {
+    Simulation::from_scenario(Path::new("tests/scenarios/bulldozer.scn")).unwrap()
+}
+
+fn the_train(sim: &Simulation) -> Entity {
+    (&sim.world.entities(), &sim.world.read_storage::<Consist>()).join()
+        .map(|(train, _consist)| train)
+        .next()
+        .unwrap()
+}
+
+fn run_until_arrived(sim: &mut Simulation, train: Entity, station: Entity) -> bool {
+    for _ in 0..2400 {
+        let arrived = sim.world.read_storage::<TrainIsInStation>().get(train)
+            .is_some_and(|in_station| in_station.station == station);
+        if arrived {
+            return true;
+        }
+        sim.step(0.05);
+    }
+    false
+}
+
+#[test]
+fn splitting_the_rail_under_a_train() {
+    let mut sim = scenario();
+    let train = the_train(&sim);
+    let east = sim.junction_at(&Position::new(600.0, 240.0), 1.0).unwrap();
+    let (s1, p) = (
+        sim.junction_at(&Position::new(200.0, 240.0), 1.0).unwrap(),
+        sim.junction_at(&Position::new(320.0, 240.0), 1.0).unwrap(),
+    );
+    for _ in 0..40 {
+        sim.step(0.05);
+    }
+    let train_x = sim.world.read_storage::<Position>().get(train).unwrap().x;
+    assert!(train_x < 150.0, "the train is past where we wanted to cut in front of it");
+
+    // One right in front of the train, and one further down its way.
+    let under_train = sim.world.read_storage::<TrackPosition>().get(train).unwrap().segment;
+    let ahead = sim.split_rail(under_train, Position::new(150.0, 240.0)).unwrap();
+    let further = sim.track_at(&Position::new(260.0, 240.0), 1.0).unwrap().segment;
+    let further = sim.split_rail(further, Position::new(260.0, 240.0)).unwrap();
+    assert!(sim.split_rail(under_train, Position::new(100.0, 240.0)).is_none(), "that rail was split already");
+
+    {
+        let junctions = sim.world.read_storage::<Junction>();
+        let tracks = sim.world.read_resource::<Tracks>();
+        assert_eq!(junctions.get(further).unwrap().connections, vec![s1, p]);
+        assert!(!junctions.get(s1).unwrap().connections.contains(&p));
+        assert!(tracks.segment_between(s1, p).is_none());
+        assert!(tracks.segment_between(s1, further).is_some());
+        assert!(tracks.segment_between(further, p).is_some());
+
+        // The train is still on the rail to where it's going next, and passes both.
+        let route = sim.world.read_storage::<TrainRoute>().get(train).unwrap().hops.clone();
+        assert_eq!(route.iter().take(4).cloned().collect::<Vec<_>>(), vec![ahead, s1, further, p]);
+        let track_pos = sim.world.read_storage::<TrackPosition>().get(train).unwrap().clone();
+        assert!(track_pos.distance_to(ahead, &tracks).is_some());
+    }
+    assert!(run_until_arrived(&mut sim, train, east), "the train got lost among the new waypoints");
+}
+
+fn connections(sim: &Simulation, junction: Entity) -> Vec<Entity> {
+    sim.world.read_storage::<Junction>().get(junction).unwrap().connections.clone()
+}
+
+#[test]
+fn switch_across_the_line() {
+    let mut sim = scenario();
+    let train = the_train(&sim);
+    let east = sim.junction_at(&Position::new(600.0, 240.0), 1.0).unwrap();
+    let (top, bottom) = sim.add_rail(Position::new(260.0, 100.0), Position::new(260.0, 400.0), CrossingKind::Switch);
+    let switch = sim.junction_at(&Position::new(260.0, 240.0), 1.0).unwrap();
+    let mut joined = connections(&sim, switch);
+    joined.sort();
+    let mut expected = vec![
+        top,
+        bottom,
+        sim.junction_at(&Position::new(200.0, 240.0), 1.0).unwrap(),
+        sim.junction_at(&Position::new(320.0, 240.0), 1.0).unwrap(),
+    ];
+    expected.sort();
+    assert_eq!(joined, expected);
+    assert!(sim.world.read_storage::<DiamondCrossing>().is_empty());
+    assert!(run_until_arrived(&mut sim, train, east), "the train didn't make it across the switch");
+}
+
+/**
+ * Both halves of a diamond crossing are at the same spot. Whatever gets attached there
+ * has to end up on the line it was drawn along.
+ */
+#[test]
+fn diamond_across_the_line() {
+    let mut sim = scenario();
+    let train = the_train(&sim);
+    let east = sim.junction_at(&Position::new(600.0, 240.0), 1.0).unwrap();
+    let (top, bottom) = sim.add_rail(Position::new(380.0, 100.0), Position::new(380.0, 400.0), CrossingKind::Diamond);
+    let (p, s2) = (
+        sim.junction_at(&Position::new(320.0, 240.0), 1.0).unwrap(),
+        sim.junction_at(&Position::new(440.0, 240.0), 1.0).unwrap(),
+    );
+    let halves = (&sim.world.entities(), &sim.world.read_storage::<DiamondCrossing>()).join()
+        .map(|(half, _crossing)| half)
+        .collect::<Vec<_>>();
+    assert_eq!(halves.len(), 2);
+    let (main, cross) = if connections(&sim, halves[0]).contains(&p) {
+        (halves[0], halves[1])
+    } else {
+        (halves[1], halves[0])
+    };
+    assert_eq!(connections(&sim, main), vec![p, s2]);
+    assert_eq!(connections(&sim, cross), vec![top, bottom]);
+
+    // Carrying on from the crossing, along either of the lines.
+    let (_, up) = sim.add_rail(Position::new(380.0, 240.0), Position::new(360.0, 20.0), CrossingKind::Diamond);
+    let (_, right) = sim.add_rail(Position::new(380.0, 240.0), Position::new(560.0, 200.0), CrossingKind::Diamond);
+    assert_eq!(connections(&sim, cross), vec![top, bottom, up]);
+    assert_eq!(connections(&sim, main), vec![p, s2, right]);
+
+    // Running right across the crossing joins one of the lines, not both.
+    let (from, to) = sim.add_rail(Position::new(300.0, 300.0), Position::new(460.0, 180.0), CrossingKind::Diamond);
+    assert_eq!(connections(&sim, from), connections(&sim, to));
+    assert!(connections(&sim, from) == vec![main] || connections(&sim, from) == vec![cross]);
+    assert_eq!(sim.world.read_storage::<DiamondCrossing>().count(), 2);
+
+    assert!(run_until_arrived(&mut sim, train, east), "the train didn't make it across the diamond");
+}
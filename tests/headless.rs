@@ -0,0 +1,28 @@
+use niart::Simulation;
+use niart::physics::Position;
+use niart::routing::TrainIsInStation;
+
+/**
+ * No window, no piston, just the library: Build the default network, send a train from
+ * the coal mine to the top power plant and see it get there.
+ */
+#[test]
+fn train_gets_where_it_is_going() {
+    let mut sim = Simulation::with_default_network(640, 480);
+    let coal_mine = sim.junction_at(&Position::new(40.0, 45.0), 1.0).unwrap();
+    let top_power_plant = sim.junction_at(&Position::new(600.0, 130.0), 1.0).unwrap();
+    let train = sim.plant_train_to(coal_mine, top_power_plant).unwrap();
+
+    let arrived = |sim: &Simulation| sim.world.read_storage::<TrainIsInStation>()
+        .get(train)
+        .is_some_and(|in_station| in_station.station == top_power_plant);
+    // Two simulated minutes is plenty for a freight train to cross the map.
+    for _ in 0..2400 {
+        if arrived(&sim) {
+            break;
+        }
+        sim.step(0.05);
+    }
+    assert!(arrived(&sim), "train never made it to the power plant");
+    assert!(sim.world.is_alive(train));
+}
@@ -0,0 +1,160 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use specs::prelude::*;
+use niart::Simulation;
+use niart::editing::CrossingKind;
+use niart::physics::Position;
+use niart::routing::{DiamondCrossing, Junction};
+use niart::signals::{ApproachSignal, JunctionSignal, SignalKind};
+use niart::track::Tracks;
+
+type Spot = (i64, i64);
+type Rail = (Spot, Spot);
+
+/**
+ * Junctions come back from the dead under a new name, so we go by where things are.
+ */
+fn spot(pos: &Position) -> Spot {
+    ((pos.x * 100.0).round() as i64, (pos.y * 100.0).round() as i64)
+}
+
+fn rail(a: Spot, b: Spot) -> Rail {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+#[derive(Debug, PartialEq)]
+struct Layout {
+    rails:      BTreeSet<Rail>,
+    signals:    BTreeSet<(Spot, String)>,
+    approaches: BTreeSet<(Spot, Spot, bool)>,
+    diamonds:   usize,
+}
+
+/**
+ * What the map looks like, after making sure that the junctions and the rails tell the
+ * same story about it.
+ */
+fn layout(sim: &Simulation) -> Layout {
+    let entities = sim.world.entities();
+    let positions = sim.world.read_storage::<Position>();
+    let junctions = sim.world.read_storage::<Junction>();
+    let signals = sim.world.read_storage::<JunctionSignal>();
+    let crossings = sim.world.read_storage::<DiamondCrossing>();
+    let approaches = sim.world.read_storage::<ApproachSignal>();
+    let tracks = sim.world.read_resource::<Tracks>();
+
+    let mut connected = BTreeSet::new();
+    for (junction, pos, junction_s) in (&entities, &positions, &junctions).join() {
+        assert_eq!(junction_s.connections.len(), tracks.segments_at(junction).len(),
+                   "junction at {:?} doesn't have a rail for each of its connections", pos);
+        for &other in &junction_s.connections {
+            assert!(junctions.get(other).unwrap().connections.contains(&junction), "one-way connection at {:?}", pos);
+            assert!(tracks.segment_between(junction, other).is_some(), "connection without a rail at {:?}", pos);
+            connected.insert(rail(spot(pos), spot(positions.get(other).unwrap())));
+        }
+    }
+    let mut laid = BTreeSet::new();
+    for (_id, segment) in tracks.segments() {
+        assert_eq!(positions.get(segment.from), Some(&segment.start), "rail starts somewhere else than its junction");
+        assert_eq!(positions.get(segment.to), Some(&segment.end), "rail ends somewhere else than its junction");
+        laid.insert(rail(spot(&segment.start), spot(&segment.end)));
+    }
+    assert_eq!(connected, laid);
+    assert_eq!(laid.len(), tracks.len(), "two rails between the same junctions");
+
+    let signals = (&positions, &signals).join()
+        .map(|(pos, signal)| (spot(pos), format!("{:?}", signal.kind)))
+        .collect();
+    // Where each approach signal is, which signal it's for, and whether somebody put it there.
+    let approaches = (&positions, &approaches).join()
+        .map(|(pos, approach)| (spot(pos), spot(positions.get(approach.junction_signal).unwrap()), approach.by_hand))
+        .collect();
+    for (crossing, pos, diamond) in (&entities, &positions, &crossings).join() {
+        assert_eq!(crossings.get(diamond.other).map(|other| other.other), Some(crossing), "half a crossing at {:?}", pos);
+        assert_eq!(positions.get(diamond.other), Some(pos), "crossing lines that don't cross at {:?}", pos);
+    }
+    Layout { rails: laid, signals, approaches, diamonds: crossings.count() }
+}
+
+/**
+ * Make an edit, then see that taking it back leaves everything as it was before, and
+ * that doing it again gets us back to what it was right after.
+ */
+fn assert_undoes_and_redoes(sim: &mut Simulation, edit: impl FnOnce(&mut Simulation)) {
+    let before = layout(sim);
+    edit(sim);
+    let after = layout(sim);
+    assert_ne!(before, after, "the edit didn't change anything");
+    assert!(sim.undo());
+    assert_eq!(layout(sim), before);
+    assert!(sim.redo());
+    assert_eq!(layout(sim), after);
+    assert!(sim.undo());
+    assert_eq!(layout(sim), before);
+}
+
+fn scenario() -> Simulation {
+    Simulation::from_scenario(Path::new("tests/scenarios/bulldozer.scn")).unwrap()
+}
+
+#[test]
+fn adding_a_rail_across_the_line() {
+    let mut sim = scenario();
+    assert_undoes_and_redoes(&mut sim, |sim| {
+        sim.add_rail(Position::new(260.0, 100.0), Position::new(260.0, 400.0), CrossingKind::Switch);
+    });
+    assert_undoes_and_redoes(&mut sim, |sim| {
+        sim.add_rail(Position::new(380.0, 100.0), Position::new(380.0, 400.0), CrossingKind::Diamond);
+    });
+}
+
+#[test]
+fn bulldozing_a_signalled_rail() {
+    let mut sim = scenario();
+    // A siding off the switch, with a signal at its end. Once the rail goes, so does
+    // the waypoint at the end along with its signal, and undo has to bring back all of it.
+    sim.add_rail(Position::new(320.0, 240.0), Position::new(320.0, 400.0), CrossingKind::Switch);
+    let end = sim.junction_at(&Position::new(320.0, 400.0), 1.0).unwrap();
+    sim.place_signal(end, SignalKind::Path);
+    assert_undoes_and_redoes(&mut sim, |sim| {
+        assert!(sim.bulldoze(&Position::new(320.0, 320.0)));
+    });
+    // The signals on the main line stay where they are, it's the rail that goes.
+    assert_undoes_and_redoes(&mut sim, |sim| {
+        assert!(sim.bulldoze(&Position::new(260.0, 240.0)));
+    });
+    // Several edits in a row come back one after the other.
+    let before = layout(&sim);
+    sim.add_rail(Position::new(100.0, 100.0), Position::new(500.0, 100.0), CrossingKind::Switch);
+    let end = sim.junction_at(&Position::new(500.0, 100.0), 1.0).unwrap();
+    sim.place_signal(end, SignalKind::Block);
+    assert!(sim.bulldoze(&Position::new(300.0, 100.0)));
+    for _ in 0..3 {
+        assert!(sim.undo());
+        layout(&sim);
+    }
+    assert_eq!(layout(&sim), before);
+    for _ in 0..3 {
+        assert!(sim.redo());
+        layout(&sim);
+    }
+    assert!(sim.junction_at(&Position::new(500.0, 100.0), 1.0).is_none());
+    assert!(!sim.redo());
+}
+
+#[test]
+fn putting_up_approach_signals_by_hand() {
+    let mut sim = scenario();
+    assert_undoes_and_redoes(&mut sim, |sim| {
+        assert!(sim.place_approach_signal(&Position::new(380.0, 240.0)));
+    });
+    // There's only room for one on each line, so a second one takes the place of the first,
+    // and taking that back brings the first one back.
+    assert!(sim.place_approach_signal(&Position::new(380.0, 240.0)));
+    let first = layout(&sim);
+    assert_undoes_and_redoes(&mut sim, |sim| {
+        assert!(sim.place_approach_signal(&Position::new(350.0, 240.0)));
+    });
+    assert_eq!(layout(&sim), first);
+    assert_eq!(first.approaches.iter().filter(|&&(_, _, by_hand)| by_hand).count(), 1);
+}
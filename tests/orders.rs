@@ -0,0 +1,89 @@
+use specs::prelude::*;
+use niart::{scenario, Simulation};
+use niart::cargo::{CargoKind, CargoStorage};
+use niart::orders::{Order, TrainOrders, WaitFor};
+use niart::physics::{EngineKind, Position};
+use niart::routing::TrainIsInStation;
+use niart::train::{Consist, WAGON_CAPACITY};
+
+const LINE: &str = "
+    industry mine  coal_mine    40 240
+    industry plant power_plant 600 240
+    produces mine  coal 3
+    consumes plant coal 0.05
+    junction ms  30 250
+    junction ps 610 250
+    signal ms
+    signal ps
+    connect mine ms
+    connect plant ps
+    connect mine plant
+";
+
+/**
+ * A stop that a train made: Where, when it got there and when it left, and how much it
+ * was carrying when it did.
+ */
+#[derive(Debug)]
+struct Stop {
+    station: Entity,
+    arrived: f64,
+    left:    f64,
+    load:    f64,
+}
+
+/**
+ * Back and forth between the mine and the plant: Fill up at the mine, whatever it takes,
+ * and have a break at the plant. Again and again.
+ */
+#[test]
+fn orders_go_round_and_round() {
+    let mut sim = Simulation::new(640, 480);
+    scenario::populate_from(&mut sim.world, LINE).unwrap();
+    let mine = sim.junction_at(&Position::new(40.0, 240.0), 1.0).unwrap();
+    let plant = sim.junction_at(&Position::new(600.0, 240.0), 1.0).unwrap();
+    let train = sim.plant_train_with(plant, mine, 3, EngineKind::Freight).unwrap();
+    sim.give_orders(train, vec![
+        Order { destination: mine, wait_for: WaitFor::FullLoad, arrival: None },
+        Order { destination: plant, wait_for: WaitFor::Timeout(8.0), arrival: None },
+    ]);
+    let wagons = sim.world.read_storage::<Consist>().get(train).unwrap().wagons.clone();
+    let load = |sim: &Simulation| -> f64 {
+        let storages = sim.world.read_storage::<CargoStorage>();
+        wagons.iter()
+            .filter_map(|&wagon| storages.get(wagon).and_then(|storage| storage.quantities.get(&CargoKind::Coal).cloned()))
+            .sum()
+    };
+
+    let mut stops: Vec<Stop> = vec![];
+    let mut here: Option<(Entity, f64)> = Some((plant, 0.0));
+    for _ in 0..(300.0 / 0.05) as usize {
+        let load_before = load(&sim);
+        sim.step(0.05);
+        let now = sim.clock().now;
+        let station = sim.world.read_storage::<TrainIsInStation>().get(train).map(|in_station| in_station.station);
+        match (here, station) {
+            (None, Some(station)) => here = Some((station, now)),
+            (Some((station, arrived)), None) => {
+                stops.push(Stop { station, arrived, left: now, load: load_before });
+                here = None;
+            },
+            _ => {},
+        }
+    }
+
+    // The first one is where it was planted, that's not one of its orders.
+    let stops = &stops[1..];
+    assert!(stops.len() >= 4, "only made it to {:?}", stops);
+    for (idx, stop) in stops.iter().enumerate() {
+        if idx % 2 == 0 {
+            assert_eq!(stop.station, mine);
+            assert!(stop.load >= 3.0 * WAGON_CAPACITY - 1e-6, "left the mine with only {} on board", stop.load);
+        } else {
+            assert_eq!(stop.station, plant);
+            assert!(stop.left - stop.arrived >= 8.0 - 1e-6, "only had a break of {}s", stop.left - stop.arrived);
+            assert!(stop.load < 1e-6, "didn't unload {} at the plant", stop.load);
+        }
+    }
+    assert!(sim.world.read_storage::<TrainOrders>().contains(train), "repeating orders aren't supposed to run out");
+}
@@ -0,0 +1,154 @@
+use std::path::Path;
+use specs::prelude::*;
+use niart::{scenario, Simulation};
+use niart::physics::Position;
+use niart::routing::{Junction, RailNetwork, RouteCosts, TrainIsInStation, TrainIsWaitingAtSignal, TrainRoute};
+use niart::signals::{JunctionIsReservedByTrain, JunctionSignal, SignalIsBlockedByTrain};
+use niart::track::{TrackPosition, Tracks};
+use niart::train::Consist;
+
+fn build(text: &str) -> Simulation {
+    let mut sim = Simulation::new(640, 480);
+    scenario::populate_from(&mut sim.world, text).unwrap();
+    sim
+}
+
+/**
+ * Ask the router the way from one industry to another, and tell where it goes by the
+ * positions of the hops, since that's what we wrote the network down with.
+ */
+fn route(sim: &Simulation, from: (f64, f64), to: (f64, f64)) -> Vec<(f64, f64)> {
+    let from = sim.junction_at(&Position::new(from.0, from.1), 1.0).unwrap();
+    let to = sim.junction_at(&Position::new(to.0, to.1), 1.0).unwrap();
+    let junctions = sim.world.read_storage::<Junction>();
+    let positions = sim.world.read_storage::<Position>();
+    let signals = sim.world.read_storage::<JunctionSignal>();
+    let costs = sim.world.read_resource::<RouteCosts>().clone();
+    let network = RailNetwork::new(&junctions, &positions, &signals, costs);
+    network.find_path(None, from, to).into_iter()
+        .map(|hop| positions.get(hop).unwrap().as_f64_tuple())
+        .collect()
+}
+
+const STATIONS: &str = "
+    industry west power_plant  40 240
+    industry east power_plant 600 240
+    junction es 610 250
+    signal es
+    connect east es
+";
+
+#[test]
+fn takes_the_shorter_way() {
+    // The long way round is laid first, so the router finds it first.
+    let sim = build(&format!("{}
+        junction far1 200 440
+        junction far2 440 440
+        connect west far1 far2 east
+        junction near1 200 260
+        junction near2 440 260
+        connect west near1 near2 east
+    ", STATIONS));
+    assert_eq!(route(&sim, (40.0, 240.0), (600.0, 240.0)), vec![
+        (40.0, 240.0), (200.0, 260.0), (440.0, 260.0), (600.0, 240.0), (610.0, 250.0),
+    ]);
+}
+
+#[test]
+fn finds_its_way_down_a_long_line() {
+    // Way more hops than the router used to give up after.
+    let hops = 60;
+    let mut text = String::from(STATIONS);
+    let mut line = String::from("connect west");
+    for hop in 0..hops {
+        text.push_str(&format!("junction j{} {} 240\n", hop, 60 + hop * 9));
+        line.push_str(&format!(" j{}", hop));
+    }
+    text.push_str(&line);
+    text.push_str(" east\n");
+    let sim = build(&text);
+    let path = route(&sim, (40.0, 240.0), (600.0, 240.0));
+    assert_eq!(path.len(), hops + 3);
+    assert_eq!(path[1], (60.0, 240.0));
+    assert_eq!(path[hops], (60.0 + (hops - 1) as f64 * 9.0, 240.0));
+}
+
+/**
+ * There's a signal right outside the station, which trains setting off from it should
+ * stay away from. Two ways lead there from the station that meet just before. The short
+ * one gets there while we're still close enough to the station to mind, the long one
+ * doesn't, and that's worth more than the few pixels it's longer. That they both reach
+ * the junction before the signal from the same one mustn't make the router forget that.
+ */
+#[test]
+fn steers_clear_of_signals_near_the_start() {
+    let sim = build(&format!("{}
+        junction x   70 240
+        junction y1  55 260
+        junction y2  75 265
+        junction y3  90 255
+        junction p   95 240
+        junction j  110 235
+        junction s  125 230
+        signal s
+        connect west x p
+        connect west y1 y2 y3 p
+        connect p j s east
+    ", STATIONS));
+    let path = route(&sim, (40.0, 240.0), (600.0, 240.0));
+    assert_eq!(&path[..5], &[(40.0, 240.0), (55.0, 260.0), (75.0, 265.0), (90.0, 255.0), (95.0, 240.0)]);
+}
+
+/**
+ * Something has broken down in the block behind the signal on the main line, and it's
+ * not going anywhere. After waiting for a while, the train backs up and goes round.
+ */
+#[test]
+fn backs_up_and_goes_round_when_tired_of_waiting() {
+    let mut sim = Simulation::from_scenario(Path::new("tests/scenarios/detour.scn")).unwrap();
+    let train = (&sim.world.entities(), &sim.world.read_storage::<Consist>()).join()
+        .map(|(train, _consist)| train)
+        .next()
+        .unwrap();
+    let (east, s1, p) = (
+        sim.junction_at(&Position::new(600.0, 240.0), 1.0).unwrap(),
+        sim.junction_at(&Position::new(300.0, 240.0), 1.0).unwrap(),
+        sim.junction_at(&Position::new(300.0, 440.0), 1.0).unwrap(),
+    );
+    sim.step(0.05);
+    assert!(sim.world.read_storage::<TrainRoute>().get(train).unwrap().hops.contains(&s1));
+    let broken_down = sim.world.create_entity().build();
+    sim.world.write_storage::<SignalIsBlockedByTrain>()
+        .insert(s1, SignalIsBlockedByTrain { train: broken_down })
+        .unwrap();
+
+    let route = |sim: &Simulation| sim.world.read_storage::<TrainRoute>().get(train).unwrap().hops.clone();
+    let waiting = |sim: &Simulation| sim.world.read_storage::<TrainIsWaitingAtSignal>().get(train).is_some();
+    let mut waited = 0.0;
+    while route(&sim).contains(&s1) {
+        assert!(waited < 20.0, "the train has been waiting for ages and still wants to go through");
+        sim.step(0.05);
+        if waiting(&sim) {
+            waited += 0.05;
+        }
+    }
+    // It did try to be patient.
+    assert!(waited >= 10.0 - 1e-6, "the train only waited for {}s", waited);
+    assert!(route(&sim).contains(&p));
+    // It's still on the rail to the signal, the first thing it does is back up.
+    let hops = route(&sim);
+    let track_pos = sim.world.read_storage::<TrackPosition>().get(train).unwrap().clone();
+    assert!(track_pos.distance_to(hops[0], &sim.world.read_resource::<Tracks>()).is_some());
+    // Switches that aren't on the way anymore are free for others.
+    for (junction, rsvp) in (&sim.world.entities(), &sim.world.read_storage::<JunctionIsReservedByTrain>()).join() {
+        assert!(rsvp.train != train || hops.contains(&junction), "still holding on to {:?}", junction);
+    }
+
+    for _ in 0..2400 {
+        if sim.world.read_storage::<TrainIsInStation>().get(train).is_some_and(|in_station| in_station.station == east) {
+            return;
+        }
+        sim.step(0.05);
+    }
+    panic!("the train never made it round");
+}
@@ -0,0 +1,113 @@
+use std::path::Path;
+use specs::prelude::*;
+use niart::{savegame, Simulation};
+use niart::collision::Crashes;
+use niart::orders::TrainOrders;
+use niart::physics::Position;
+use niart::routing::TrainRoute;
+use niart::signals::{SignalIsReservedByTrain, SignalIsBlockedByTrain};
+use niart::track::Tracks;
+use niart::train::Consist;
+
+fn run(sim: &mut Simulation, seconds: f64) {
+    for _ in 0..(seconds / 0.05) as usize {
+        sim.step(0.05);
+    }
+}
+
+fn round_trip(sim: &Simulation) -> Simulation {
+    savegame::load_from_str(&savegame::save_to_string(sim).unwrap()).unwrap()
+}
+
+/**
+ * Whatever we load has to save to the very same thing, and carry on the same way as
+ * the game that was never saved.
+ */
+fn assert_same_game(original: &mut Simulation, loaded: &mut Simulation) {
+    let saved = savegame::save_to_string(original).unwrap();
+    assert_eq!(saved, savegame::save_to_string(loaded).unwrap());
+    run(original, 10.0);
+    run(loaded, 10.0);
+    assert_eq!(savegame::save_to_string(original).unwrap(), savegame::save_to_string(loaded).unwrap());
+}
+
+#[test]
+fn deadlocked_trains_survive_saving() {
+    let mut sim = Simulation::from_scenario(Path::new("tests/scenarios/deadlock.scn")).unwrap();
+    run(&mut sim, 15.0);
+    assert_eq!(sim.deadlocks().current().count(), 1);
+
+    let mut loaded = round_trip(&sim);
+    assert_eq!(loaded.deadlocks().log.len(), 1);
+    assert_eq!(loaded.deadlocks().log[0].trains.len(), 2);
+    assert_eq!(loaded.world.read_storage::<Consist>().count(), 2);
+    assert_eq!(loaded.world.read_storage::<TrainOrders>().count(), 1);
+    assert_eq!(loaded.world.read_storage::<TrainOrders>().join().next().unwrap().orders.len(), 2);
+    // Each of them holds the signal in front of them, and blocks the one behind.
+    assert_eq!(loaded.world.read_storage::<SignalIsReservedByTrain>().count(),
+               sim.world.read_storage::<SignalIsReservedByTrain>().count());
+    assert_eq!(loaded.world.read_storage::<SignalIsBlockedByTrain>().count(), 2);
+    assert_same_game(&mut sim, &mut loaded);
+}
+
+#[test]
+fn wrecks_survive_saving() {
+    let mut sim = Simulation::from_scenario(Path::new("tests/scenarios/head_on.scn")).unwrap();
+    run(&mut sim, 15.0);
+    assert_eq!(sim.world.read_resource::<Crashes>().log.len(), 1);
+
+    let mut loaded = round_trip(&sim);
+    let crashes = loaded.world.read_resource::<Crashes>().log.clone();
+    assert_eq!(crashes.len(), 1);
+    assert_eq!(crashes[0].trains.len(), 2);
+    assert!(!crashes[0].blocked.is_empty());
+    assert_same_game(&mut sim, &mut loaded);
+}
+
+#[test]
+fn clock_survives_saving() {
+    let mut sim = Simulation::with_default_network(640, 480);
+    run(&mut sim, 1.0);
+    sim.set_speed(4.0);
+    sim.toggle_pause();
+    let loaded = round_trip(&sim);
+    let clock = loaded.clock();
+    assert_eq!(clock.now, sim.clock().now);
+    assert_eq!(clock.speed, 4.0);
+    assert!(clock.paused);
+}
+
+#[test]
+fn references_to_deleted_junctions_are_dropped() {
+    let mut sim = Simulation::from_scenario(Path::new("tests/scenarios/deadlock.scn")).unwrap();
+    run(&mut sim, 15.0);
+    let west = sim.junction_at(&Position::new(40.0, 200.0), 1.0).unwrap();
+    let b2 = sim.junction_at(&Position::new(150.0, 280.0), 1.0).unwrap();
+    // Say the train from west was already on its way back there.
+    for orders in (&mut sim.world.write_storage::<TrainOrders>()).join() {
+        orders.current = 1;
+    }
+    // Nobody would ever leave things like this, but a savegame may have to cope anyway.
+    sim.world.delete_entity(west).unwrap();
+    sim.world.delete_entity(b2).unwrap();
+    sim.world.maintain();
+
+    let loaded = round_trip(&sim);
+    let orders = loaded.world.read_storage::<TrainOrders>();
+    let orders = orders.join().next().unwrap();
+    assert_eq!(orders.orders.len(), 1);
+    // Its stop is gone, so it starts over with the one that's left.
+    assert_eq!(orders.current, 0);
+    // The train from east2 was headed through b2, and doesn't know where to go anymore.
+    assert_eq!(loaded.world.read_storage::<TrainRoute>().count(), 1);
+}
+
+#[test]
+fn rails_to_nowhere_are_left_out() {
+    let sim = Simulation::with_default_network(640, 480);
+    let junction = sim.junction_at(&Position::new(140.0, 160.0), 1.0).unwrap();
+    // As if someone had been editing the savegame by hand.
+    sim.world.write_storage::<Position>().remove(junction);
+    let loaded = round_trip(&sim);
+    assert!(loaded.world.read_resource::<Tracks>().len() < sim.world.read_resource::<Tracks>().len());
+}
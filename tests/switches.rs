@@ -0,0 +1,49 @@
+use std::path::Path;
+use specs::prelude::*;
+use niart::Simulation;
+use niart::physics::Position;
+use niart::routing::TrainIsInStation;
+use niart::signals::JunctionSignal;
+use niart::train::{Consist, Wagon};
+
+/**
+ * The long train gets the green light into the merge first. The other line doesn't get
+ * one for as long as the long train is on its way through, tail and all, even though the
+ * two paths only touch at the signal where they come together.
+ */
+#[test]
+fn merging_lines_take_turns() {
+    let mut sim = Simulation::from_scenario(Path::new("tests/scenarios/merge.scn")).unwrap();
+    let p1 = sim.junction_at(&Position::new(150.0, 200.0), 1.0).unwrap();
+    let p2 = sim.junction_at(&Position::new(150.0, 280.0), 1.0).unwrap();
+    let m_x = 300.0;
+    let east = sim.junction_at(&Position::new(600.0, 240.0), 1.0).unwrap();
+    let (long, length) = {
+        let wagons = sim.world.read_storage::<Wagon>();
+        (&sim.world.entities(), &sim.world.read_storage::<Consist>()).join()
+            .map(|(train, consist)| (train, consist.length(&wagons)))
+            .max_by(|(_, left), (_, right)| left.partial_cmp(right).unwrap())
+            .unwrap()
+    };
+    let is_green = |sim: &Simulation, signal| !sim.world.read_storage::<JunctionSignal>().get(signal).unwrap().is_halt();
+
+    let mut went = (false, false);
+    for _ in 0..2400 {
+        sim.step(0.05);
+        let (first, second) = (is_green(&sim, p1), is_green(&sim, p2));
+        went = (went.0 || first, went.1 || second);
+        // Past the merge everything is in a straight line, so that's where the tail is.
+        let nose = sim.world.read_storage::<Position>().get(long).unwrap().x;
+        let in_the_merge = went.0 && (nose < m_x || nose - m_x < length);
+        assert!(
+            !(second && (first || in_the_merge)),
+            "the other line got a green light with the long train still in the merge (nose at {:.1})", nose
+        );
+        assert!(sim.crashes().log.is_empty(), "they ran into each other at the merge");
+    }
+    assert_eq!(went, (true, true));
+    let arrived = (&sim.world.read_storage::<TrainIsInStation>()).join()
+        .filter(|in_station| in_station.station == east)
+        .count();
+    assert_eq!(arrived, 2, "somebody didn't make it through the merge");
+}
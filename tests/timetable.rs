@@ -0,0 +1,92 @@
+use niart::{scenario, Simulation};
+use niart::orders::{Order, WaitFor};
+use niart::physics::{EngineKind, Position};
+use niart::timetable::{StopEvent, ON_TIME_TOLERANCE};
+
+// Two mines that each have their own line to the same power plant.
+const LINES: &str = "
+    industry north power_plant  40 100
+    industry south power_plant  40 380
+    industry plant power_plant 600 240
+    junction ns  30  90
+    junction ss  30 390
+    junction ps 610 250
+    signal ns
+    signal ss
+    signal ps
+    connect north ns
+    connect south ss
+    connect plant ps
+    junction n 500 100
+    junction s 500 380
+    connect north n plant
+    connect south s plant
+";
+
+fn run(sim: &mut Simulation, seconds: f64) {
+    for _ in 0..(seconds / 0.05) as usize {
+        sim.step(0.05);
+    }
+}
+
+/**
+ * One train that can't possibly make its first arrival on time but keeps to the rest,
+ * and one that is on time everywhere. Both of them stop at the plant, one after the
+ * other, since the lines come together right in front of it.
+ */
+#[test]
+fn delays_are_booked_per_train_and_per_station() {
+    let mut sim = Simulation::new(640, 480);
+    scenario::populate_from(&mut sim.world, LINES).unwrap();
+    let north = sim.junction_at(&Position::new(40.0, 100.0), 1.0).unwrap();
+    let south = sim.junction_at(&Position::new(40.0, 380.0), 1.0).unwrap();
+    let plant = sim.junction_at(&Position::new(600.0, 240.0), 1.0).unwrap();
+
+    let late = sim.plant_train_with(north, plant, 1, EngineKind::Freight).unwrap();
+    sim.give_timetable(late, vec![
+        Order { destination: plant, wait_for: WaitFor::DepartureTime(60.0), arrival: Some(5.0) },
+        Order { destination: north, wait_for: WaitFor::DepartureTime(290.0), arrival: Some(200.0) },
+    ], Some(300.0));
+    run(&mut sim, 70.0);
+    let punctual = sim.plant_train_with(south, plant, 1, EngineKind::Freight).unwrap();
+    sim.give_timetable(punctual, vec![
+        Order { destination: plant, wait_for: WaitFor::Nothing, arrival: Some(100.0) },
+        Order { destination: south, wait_for: WaitFor::DepartureTime(290.0), arrival: Some(200.0) },
+    ], Some(300.0));
+
+    run(&mut sim, 130.0);
+
+    let punctuality = sim.punctuality();
+    let entries_of = |train| punctuality.entries.iter().filter(|entry| entry.train == train).collect::<Vec<_>>();
+    let late_entries = entries_of(late);
+    assert_eq!(late_entries.iter().map(|entry| (entry.station, entry.event)).collect::<Vec<_>>(), vec![
+        (plant, StopEvent::Arrival),
+        (plant, StopEvent::Departure),
+        (north, StopEvent::Arrival),
+    ]);
+    assert!(late_entries[0].delay() > ON_TIME_TOLERANCE);
+    // It's held until it's time to leave, and not any longer.
+    assert!((late_entries[1].actual - 60.0).abs() < 0.1, "left at {}", late_entries[1].actual);
+    assert_eq!(entries_of(punctual).len(), 2);
+
+    let late_stats = punctuality.for_train(late);
+    assert_eq!((late_stats.stops, late_stats.on_time), (3, 2));
+    assert!((late_stats.max_delay - late_entries[0].delay()).abs() < 1e-9);
+    // Being early doesn't make up for being late elsewhere.
+    let total_delay: f64 = late_entries.iter().map(|entry| entry.delay().max(0.0)).sum();
+    assert!(late_entries[2].delay() < 0.0);
+    assert!((late_stats.mean_delay - total_delay / 3.0).abs() < 1e-9);
+    let punctual_stats = punctuality.for_train(punctual);
+    assert_eq!((punctual_stats.stops, punctual_stats.on_time), (2, 2));
+    assert_eq!(punctual_stats.max_delay, 0.0);
+    assert_eq!(punctual_stats.punctuality(), 1.0);
+
+    let plant_stats = punctuality.for_station(plant);
+    assert_eq!((plant_stats.stops, plant_stats.on_time), (3, 2));
+    assert_eq!(punctuality.for_station(north).stops, 1);
+    assert_eq!(punctuality.for_station(south).stops, 1);
+    assert_eq!(punctuality.by_train().len(), 2);
+    assert_eq!(punctuality.by_station().len(), 3);
+    assert_eq!(punctuality.by_station()[&plant], plant_stats);
+    assert_eq!(punctuality.overall().stops, 5);
+}
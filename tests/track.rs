@@ -0,0 +1,48 @@
+use niart::Simulation;
+use niart::physics::Position;
+use niart::routing::TrainIsInStation;
+use niart::track::{TrackPosition, Tracks};
+
+/**
+ * Follow a train all the way across the default network, and make sure that it's on the
+ * rails every step of the way, and only ever changes rails at the junction where its old
+ * one ends and the new one starts.
+ */
+#[test]
+fn trains_stay_on_the_rails() {
+    let mut sim = Simulation::with_default_network(640, 480);
+    let coal_mine = sim.junction_at(&Position::new(40.0, 45.0), 1.0).unwrap();
+    let top_power_plant = sim.junction_at(&Position::new(600.0, 130.0), 1.0).unwrap();
+    let train = sim.plant_train_to(coal_mine, top_power_plant).unwrap();
+
+    let mut last: Option<TrackPosition> = None;
+    let mut junctions_passed = 0;
+    for _ in 0..2400 {
+        sim.step(0.05);
+        if sim.world.read_storage::<TrainIsInStation>().get(train).is_some_and(|in_station| in_station.station == top_power_plant) {
+            break;
+        }
+        // Still in the station, waiting for the way out.
+        let track_pos = match sim.world.read_storage::<TrackPosition>().get(train) {
+            Some(track_pos) => track_pos.clone(),
+            None => continue,
+        };
+        let pos = sim.world.read_storage::<Position>().get(train).unwrap().clone();
+        let tracks = sim.world.read_resource::<Tracks>();
+        let segment = tracks.get(track_pos.segment).unwrap();
+        assert!(track_pos.offset >= 0.0 && track_pos.offset <= segment.length(), "ran off the end of the rail");
+        let on_rail = track_pos.world_position(&tracks).unwrap();
+        assert!(on_rail.distance_length_to(&pos) < 1e-6, "train is at {:?}, but its rail is at {:?}", pos, on_rail);
+
+        if let Some(last) = last.filter(|last| last.segment != track_pos.segment) {
+            let old = tracks.get(last.segment).unwrap();
+            let junction = [old.from, old.to].iter().cloned().find(|&junction| segment.connects(junction))
+                .expect("jumped over to a rail that doesn't even touch the old one");
+            assert!(last.is_at(junction, &tracks), "left the old rail before getting to its end");
+            junctions_passed += 1;
+        }
+        last = Some(track_pos);
+    }
+    assert!(sim.world.read_storage::<TrainIsInStation>().get(train).is_some(), "train never made it");
+    assert!(junctions_passed >= 3, "that wasn't much of a trip");
+}